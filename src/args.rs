@@ -0,0 +1,220 @@
+//! Command line options of the sketch.
+
+use crate::distribution::*;
+use crate::playback::*;
+use crate::species::*;
+use std::path::PathBuf;
+
+/// Options given on the command line, `None` keeps the sketch's default.
+#[derive(Debug, Clone, Default)]
+pub struct Args {
+    /// `--distribution <name[:key=value,...]>`, see `InitialDistribution::from_str`.
+    pub distribution: Option<InitialDistribution>,
+    /// `--seed <u64>`, the seed of every random choice of the run, random when not given.
+    pub seed: Option<u64>,
+    /// `--species <name[:key=value,...]>`, see `SpeciesSource::from_str`.
+    pub species: Option<SpeciesSource>,
+    /// `--particles <n>`, the number of particles alive at the start.
+    pub particles: Option<u32>,
+    /// `--paused`, starts the simulation paused.
+    pub paused: bool,
+    /// `--steps-per-frame <n>`, fast-forwards by running `n` steps every rendered frame.
+    pub steps_per_frame: Option<u32>,
+    /// `--slow-motion <n>`, runs one step every `n` rendered frames.
+    pub slow_motion: Option<u32>,
+    /// `--checkpoint <path>`, continues the run saved in the checkpoint file,
+    /// whose seed and particle capacity replace `--seed` and `--particles`.
+    pub checkpoint: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum ArgsError {
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidDistribution(DistributionError),
+    InvalidSeed(String),
+    InvalidSpecies(SpeciesError),
+    /// Particle, step and frame counts must be positive integers.
+    InvalidCount(String),
+    /// An option the binary has no use for.
+    Unsupported(&'static str),
+}
+
+impl Args {
+    /// Parses the arguments the sketch was started with.
+    pub fn from_env() -> Result<Self, ArgsError> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, ArgsError> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(flag) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| ArgsError::MissingValue(flag.clone()))
+            };
+
+            match flag.as_str() {
+                "--distribution" => {
+                    parsed.distribution =
+                        Some(value()?.parse().map_err(ArgsError::InvalidDistribution)?);
+                }
+                "--seed" => {
+                    let seed = value()?;
+                    parsed.seed = Some(seed.parse().map_err(|_| ArgsError::InvalidSeed(seed))?);
+                }
+                "--species" => {
+                    parsed.species = Some(value()?.parse().map_err(ArgsError::InvalidSpecies)?);
+                }
+                "--particles" => parsed.particles = Some(parse_count(value()?)?),
+                "--checkpoint" => parsed.checkpoint = Some(PathBuf::from(value()?)),
+                "--paused" => parsed.paused = true,
+                "--steps-per-frame" => parsed.steps_per_frame = Some(parse_count(value()?)?),
+                "--slow-motion" => parsed.slow_motion = Some(parse_count(value()?)?),
+                _ => return Err(ArgsError::UnknownFlag(flag)),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// Fails on the options of the 2D sketch the 3D one has no counterpart for,
+    /// rather than ignoring them.
+    pub fn for_3d(self) -> Result<Self, ArgsError> {
+        if self.distribution.is_some() {
+            return Err(ArgsError::Unsupported("--distribution"));
+        }
+        if self.checkpoint.is_some() {
+            return Err(ArgsError::Unsupported("--checkpoint"));
+        }
+        Ok(self)
+    }
+
+    /// The playback the simulation starts with, `--slow-motion` wins over `--steps-per-frame`.
+    pub fn playback(&self) -> Playback {
+        let mut playback = Playback::new().with_paused(self.paused);
+        if let Some(steps) = self.steps_per_frame {
+            playback = playback.with_steps_per_frame(steps);
+        }
+        if let Some(frames) = self.slow_motion {
+            playback = playback.with_frames_per_step(frames);
+        }
+        playback
+    }
+}
+
+fn parse_count(value: String) -> Result<u32, ArgsError> {
+    match value.parse() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(ArgsError::InvalidCount(value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, ArgsError> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_flags() {
+        let args = parse(&[
+            "--distribution",
+            "lattice:spacing=12",
+            "--seed",
+            "42",
+            "--species",
+            "separate",
+            "--checkpoint",
+            "frames/checkpoint.bin",
+            "--paused",
+            "--particles",
+            "100000",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            args.distribution,
+            Some("lattice:spacing=12".parse().unwrap())
+        );
+        assert_eq!(args.seed, Some(42));
+        assert_eq!(args.species, Some("separate".parse().unwrap()));
+        assert_eq!(
+            args.checkpoint,
+            Some(PathBuf::from("frames/checkpoint.bin"))
+        );
+        assert!(args.playback().paused);
+        assert_eq!(args.particles, Some(100000));
+
+        let defaults = parse(&[]).unwrap();
+        assert_eq!(defaults.distribution, None);
+        assert_eq!(defaults.playback(), Playback::new());
+    }
+
+    #[test]
+    fn prefers_slow_motion() {
+        let args = parse(&["--steps-per-frame", "4"]).unwrap();
+        assert_eq!(args.playback(), Playback::new().with_steps_per_frame(4));
+
+        let args = parse(&["--slow-motion", "3", "--steps-per-frame", "4"]).unwrap();
+        assert_eq!(args.playback(), Playback::new().with_frames_per_step(3));
+    }
+
+    #[test]
+    fn rejects_2d_options_in_3d() {
+        assert!(parse(&["--seed", "1", "--particles", "500"])
+            .unwrap()
+            .for_3d()
+            .is_ok());
+        assert!(matches!(
+            parse(&["--distribution", "disk"]).unwrap().for_3d(),
+            Err(ArgsError::Unsupported("--distribution"))
+        ));
+        assert!(matches!(
+            parse(&["--checkpoint", "frames/checkpoint.bin"])
+                .unwrap()
+                .for_3d(),
+            Err(ArgsError::Unsupported("--checkpoint"))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        assert!(matches!(
+            parse(&["--speed", "2"]),
+            Err(ArgsError::UnknownFlag(_))
+        ));
+        assert!(matches!(
+            parse(&["--seed"]),
+            Err(ArgsError::MissingValue(_))
+        ));
+        assert!(matches!(
+            parse(&["--seed", "-1"]),
+            Err(ArgsError::InvalidSeed(_))
+        ));
+        assert!(matches!(
+            parse(&["--distribution", "spiral"]),
+            Err(ArgsError::InvalidDistribution(_))
+        ));
+        assert!(matches!(
+            parse(&["--species", "swarm"]),
+            Err(ArgsError::InvalidSpecies(_))
+        ));
+        assert!(matches!(
+            parse(&["--particles", "0"]),
+            Err(ArgsError::InvalidCount(_))
+        ));
+        assert!(matches!(
+            parse(&["--slow-motion", "0"]),
+            Err(ArgsError::InvalidCount(_))
+        ));
+        assert!(matches!(
+            parse(&["--steps-per-frame", "many"]),
+            Err(ArgsError::InvalidCount(_))
+        ));
+    }
+}
@@ -0,0 +1,73 @@
+use nannou::prelude::*;
+use std::sync::Arc;
+
+use crate::particles::*;
+use crate::uniforms::*;
+
+/// Something that can advance the particle simulation.
+pub trait SimulationBackend {
+    /// Advances every particle by one update step.
+    /// Fields that size buffers (particle capacity, grid cell, species, emitter, sink, obstacle
+    /// and field counts) are fixed at creation and ignored here.
+    fn step(&mut self, uniforms: &Uniforms);
+
+    /// Returns a copy of the current particle state.
+    fn state(&mut self) -> ParticleState;
+}
+
+/// Runs `update.comp` through a `ParticleSystem` on its own device and queue.
+pub struct GpuBackend {
+    device_queue_pair: Arc<wgpu::DeviceQueuePair>,
+    uniforms: UniformBuffer,
+    particle_system: ParticleSystem,
+}
+
+impl GpuBackend {
+    pub fn new(
+        device_queue_pair: Arc<wgpu::DeviceQueuePair>,
+        uniforms: UniformBuffer,
+        particle_system: ParticleSystem,
+    ) -> Self {
+        Self {
+            device_queue_pair,
+            uniforms,
+            particle_system,
+        }
+    }
+}
+
+impl SimulationBackend for GpuBackend {
+    fn step(&mut self, uniforms: &Uniforms) {
+        self.uniforms.data = Uniforms {
+            particle_capacity: self.uniforms.data.particle_capacity,
+            grid_cell_count: self.uniforms.data.grid_cell_count,
+            species_count: self.uniforms.data.species_count,
+            emitter_count: self.uniforms.data.emitter_count,
+            sink_count: self.uniforms.data.sink_count,
+            obstacle_count: self.uniforms.data.obstacle_count,
+            field_count: self.uniforms.data.field_count,
+            ..*uniforms
+        };
+
+        let device = self.device_queue_pair.device();
+        let desc = wgpu::CommandEncoderDescriptor {
+            label: Some("particle-compute"),
+        };
+        let mut encoder = device.create_command_encoder(&desc);
+
+        self.uniforms.update(device, &mut encoder);
+        self.particle_system
+            .update(&mut encoder, &self.uniforms.data);
+
+        self.device_queue_pair
+            .queue()
+            .submit(Some(encoder.finish()));
+    }
+
+    fn state(&mut self) -> ParticleState {
+        self.particle_system.read_state(
+            self.device_queue_pair.device(),
+            self.device_queue_pair.queue(),
+        )
+    }
+}
@@ -0,0 +1,204 @@
+//! The simulation in a 3D box, seen through an orbiting camera.
+//! Left drag orbits, the mouse wheel zooms. Accepts `--seed`, `--species`, `--particles` and the playback options,
+//! and the playback keys, like the 2D sketch, and refuses `--distribution` and `--checkpoint`.
+
+use generative_matter::particles3d::{ParticleState3d, ParticleSystem3d};
+use generative_matter::{args, camera, capture, playback, random, render, species, uniforms, util};
+use nannou::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+struct Model {
+    particle_system: ParticleSystem3d,
+    uniforms: uniforms::UniformBuffer,
+    frame_capturer: capture::FrameCapturer,
+    render: render::CustomRenderer,
+    camera: camera::OrbitCamera,
+    playback: playback::Playback,
+}
+
+const WIDTH: u32 = 1920;
+const HEIGHT: u32 = 1080;
+/// Every particle interacts with every other, and every pixel tests every particle.
+/// Overridden by `--particles`.
+const PARTICLE_COUNT: u32 = 1000;
+/// Radius of the ball the particles start in.
+const INITIAL_RADIUS: f32 = 192.0;
+/// Extent of the box along z, its other sides are the window size.
+const DEPTH: f32 = 1080.0;
+const CAMERA_DISTANCE: f32 = 1200.0;
+/// Species preset, overridden by `--species`.
+const SPECIES: &str = "random:count=3";
+const BOUNDARY_MODE: uniforms::BoundaryMode = uniforms::BoundaryMode::Open;
+const WORKGROUP_SIZE: u32 = 64;
+const FORCE_KERNEL: uniforms::ForceKernel = uniforms::ForceKernel::InverseSquare;
+
+fn main() {
+    nannou::app(model).update(update).run();
+}
+
+fn model(app: &App) -> Model {
+    let args = args::Args::from_env()
+        .and_then(args::Args::for_3d)
+        .unwrap_or_else(|e| panic!("invalid arguments: {:?}", e));
+
+    // every random choice of the run follows from the seed
+    let seed = args.seed.unwrap_or_else(rand::random);
+    println!("seed: {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    util::check_window_adapter(app).unwrap_or_else(|e| panic!("unsupported adapter: {:?}", e));
+    let window_id = app
+        .new_window()
+        .size(WIDTH, HEIGHT)
+        .device_descriptor(util::device_descriptor())
+        .view(view)
+        .mouse_wheel(mouse_wheel)
+        .key_pressed(key_pressed)
+        .build()
+        .unwrap();
+    let window = app.window(window_id).unwrap();
+    let device = window.device();
+    let sample_count = window.msaa_samples();
+
+    let particle_count = args.particles.unwrap_or(PARTICLE_COUNT);
+    let mut uniforms =
+        uniforms::UniformBuffer::new(device, particle_count, WIDTH as f32, HEIGHT as f32);
+
+    let species = args
+        .species
+        .clone()
+        .unwrap_or_else(|| SPECIES.parse::<species::SpeciesSource>().unwrap())
+        .build(&mut rng)
+        .validated();
+    uniforms.data.species_count = species.count;
+    uniforms.data.seed = random::gpu_seed(seed);
+    uniforms.data.boundary_mode = BOUNDARY_MODE as u32;
+    uniforms.data.force_kernel = FORCE_KERNEL as u32;
+    uniforms.data.depth = DEPTH;
+
+    let state = ParticleState3d::sample(particle_count, INITIAL_RADIUS, &species, &mut rng);
+    let particle_system = ParticleSystem3d::from_state(
+        &util::shaders_directory(app),
+        device,
+        &uniforms,
+        &state,
+        &species,
+        WORKGROUP_SIZE,
+    );
+
+    let frame_capturer = capture::FrameCapturer::new(app, seed);
+
+    let vs_mod = util::compile_shader(app, device, "shader.vert", shaderc::ShaderKind::Vertex);
+    let fs_mod = util::compile_shader(app, device, "shader3d.frag", shaderc::ShaderKind::Fragment);
+
+    // one buffer set per ping-pong position buffer, picked by `ParticleSystem3d::current`
+    let buffer_sets: Vec<Vec<&wgpu::Buffer>> = particle_system
+        .position_buffers
+        .iter()
+        .map(|positions| {
+            vec![
+                positions,
+                &particle_system.species_buffer,
+                &particle_system.attribute_buffer,
+            ]
+        })
+        .collect();
+
+    let render = render::CustomRenderer::with_buffer_sets::<uniforms::Uniforms>(
+        device,
+        &vs_mod,
+        &fs_mod,
+        &buffer_sets,
+        Some(&vec![
+            &particle_system.buffer_size,
+            &particle_system.species_buffer_size,
+            &particle_system.attribute_buffer_size,
+        ]),
+        None,
+        None,
+        Some(&uniforms.buffer),
+        WIDTH,
+        HEIGHT,
+        sample_count,
+        sample_count,
+    )
+    .unwrap();
+
+    Model {
+        particle_system,
+        uniforms,
+        frame_capturer,
+        render,
+        camera: camera::OrbitCamera::new(CAMERA_DISTANCE),
+        playback: args.playback(),
+    }
+}
+
+fn mouse_wheel(_app: &App, model: &mut Model, delta: MouseScrollDelta, _phase: TouchPhase) {
+    let lines = match delta {
+        MouseScrollDelta::LineDelta(_, y) => y,
+        MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+    };
+    model.camera.zoom(lines);
+}
+
+/// Space pauses and resumes, period runs a single step, right and left brackets double and
+/// halve the simulation speed.
+fn key_pressed(_app: &App, model: &mut Model, key: Key) {
+    match key {
+        Key::Space => model.playback.toggle_pause(),
+        Key::Period => model.playback.step(),
+        Key::RBracket => model.playback.faster(),
+        Key::LBracket => model.playback.slower(),
+        _ => {}
+    }
+}
+
+fn update(app: &App, model: &mut Model, _update: Update) {
+    let window = app.main_window();
+    let device = window.device();
+
+    let desc = wgpu::CommandEncoderDescriptor {
+        label: Some("particle-compute-3d"),
+    };
+    let mut encoder = device.create_command_encoder(&desc);
+
+    model.camera.update(app, &mut model.uniforms.data);
+    model.uniforms.update(device, &mut encoder);
+
+    // paused frames only render, so the camera still moves
+    let steps = model.playback.advance();
+    for _ in 0..steps {
+        model.uniforms.update(device, &mut encoder);
+        model
+            .particle_system
+            .update(&mut encoder, &model.uniforms.data);
+
+        model.uniforms.data.frame = model.uniforms.data.frame.wrapping_add(1);
+        model.uniforms.data.time += model.uniforms.data.dt;
+    }
+
+    model
+        .render
+        .render_with(&mut encoder, model.particle_system.current());
+
+    // only simulated frames are captured
+    if steps > 0 {
+        model
+            .frame_capturer
+            .take_snapshot(device, &mut encoder, &model.render.output_texture);
+    }
+
+    window.queue().submit(Some(encoder.finish()));
+
+    model.frame_capturer.save_frame(app);
+}
+
+fn view(_app: &App, model: &Model, frame: Frame) {
+    let mut encoder = frame.command_encoder();
+    model
+        .render
+        .texture_reshaper
+        .encode_render_pass(frame.texture_view(), &mut encoder);
+}
@@ -0,0 +1,210 @@
+use nannou::prelude::*;
+use nannou::wgpu::CommandEncoder;
+use std::path::Path;
+
+use crate::compute::*;
+use crate::uniforms::*;
+use crate::util::*;
+
+/// Bond slots per particle, must match `MAX_BONDS` in `bonds.glsl`.
+pub const MAX_BONDS: u32 = 4;
+
+/// Partner of an empty bond slot, or of a particle without a bond candidate.
+pub const NO_BOND: u32 = u32::MAX;
+
+/// Invocations per workgroup of `bonds.comp`, passed to it as `WORKGROUP_SIZE`.
+pub const BOND_WORKGROUP_SIZE: u32 = 64;
+
+/// A spring to another particle, stored on both ends.
+/// Laid out as the `Bond` struct of `bonds.glsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bond {
+    pub partner: u32,
+    /// Length of the relaxed spring, the distance between the particles when they bonded.
+    pub rest_length: f32,
+}
+
+impl Bond {
+    pub const NONE: Bond = Bond {
+        partner: NO_BOND,
+        rest_length: 0.0,
+    };
+
+    pub fn new(partner: u32, rest_length: f32) -> Self {
+        Bond {
+            partner,
+            rest_length,
+        }
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.partner == NO_BOND
+    }
+}
+
+/// The nearest particle a particle could bond with, and how long it has been within reach.
+/// Laid out as the `BondCandidate` struct of `bonds.glsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BondCandidate {
+    pub partner: u32,
+    pub time: f32,
+}
+
+impl BondCandidate {
+    pub const NONE: BondCandidate = BondCandidate {
+        partner: NO_BOND,
+        time: 0.0,
+    };
+}
+
+/// Forms and breaks bonds once per frame, see `bonds.comp`.
+/// Every particle owns `MAX_BONDS` consecutive slots of `bond_buffer`,
+/// and a bond is listed in the slots of both particles.
+pub struct Bonds {
+    pub bond_buffer: wgpu::Buffer,
+    pub candidate_buffer: wgpu::Buffer,
+    pub bond_buffer_size: u64,
+    pub candidate_buffer_size: u64,
+    candidates: Compute,
+    bonds: Compute,
+    particle_capacity: u32,
+}
+
+impl Bonds {
+    /// `bonds` holds `MAX_BONDS` slots per particle.
+    /// `state_buffer_sets` holds, for each ping-pong position, the position, alive,
+    /// grid cell end and sorted index buffers.
+    pub fn new(
+        shaders_dir: &Path,
+        device: &wgpu::Device,
+        uniforms: &UniformBuffer,
+        bonds: &[Bond],
+        state_buffer_sets: &[Vec<&wgpu::Buffer>],
+        state_buffer_sizes: &[u64],
+    ) -> Self {
+        let particle_capacity = uniforms.data.particle_capacity;
+        assert_eq!(
+            bonds.len(),
+            (particle_capacity * MAX_BONDS) as usize,
+            "bonds do not match the particle capacity"
+        );
+
+        let bond_buffer_size = std::mem::size_of_val(bonds) as u64;
+        let candidate_buffer_size =
+            particle_capacity as u64 * std::mem::size_of::<BondCandidate>() as u64;
+
+        let bond_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
+            label: Some("bond-buffer"),
+            contents: bonds_as_bytes(bonds),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let candidates = vec![BondCandidate::NONE; particle_capacity as usize];
+        let candidate_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
+            label: Some("bond-candidate-buffer"),
+            contents: candidates_as_bytes(&candidates),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let buffer_sets: Vec<Vec<&wgpu::Buffer>> = state_buffer_sets
+            .iter()
+            .map(|buffers| {
+                let mut buffers = buffers.clone();
+                buffers.extend([&bond_buffer, &candidate_buffer]);
+                buffers
+            })
+            .collect();
+        let mut buffer_sizes = state_buffer_sizes.to_vec();
+        buffer_sizes.extend([bond_buffer_size, candidate_buffer_size]);
+
+        let workgroup_size = format!("WORKGROUP_SIZE={}", BOND_WORKGROUP_SIZE);
+        let stage = |define: &str| {
+            let cs_mod = compile_shader_variant(
+                shaders_dir,
+                device,
+                "bonds.comp",
+                shaderc::ShaderKind::Compute,
+                &[define, workgroup_size.as_str()],
+            );
+
+            Compute::with_buffer_sets::<Uniforms>(
+                device,
+                buffer_sets.clone(),
+                Some(buffer_sizes.clone()),
+                Some(&uniforms.buffer),
+                &cs_mod,
+            )
+            .unwrap()
+            .with_workgroup_size(BOND_WORKGROUP_SIZE)
+        };
+        let candidates = stage("STAGE_CANDIDATES");
+        let bonds = stage("STAGE_BONDS");
+
+        Self {
+            bond_buffer,
+            candidate_buffer,
+            bond_buffer_size,
+            candidate_buffer_size,
+            candidates,
+            bonds,
+            particle_capacity,
+        }
+    }
+
+    /// Updates the bond candidates, then breaks and forms bonds in the given ping-pong buffers.
+    /// The neighbor grid must be up to date for those buffers.
+    pub fn update(&self, encoder: &mut CommandEncoder, buffers: usize) {
+        self.candidates
+            .compute_with(encoder, buffers, self.particle_capacity);
+        self.bonds
+            .compute_with(encoder, buffers, self.particle_capacity);
+    }
+
+    /// Copies the bonds back from the GPU, blocking until they are available.
+    pub fn read_bonds(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<Bond> {
+        byte_vec_as_bonds(&read_buffer(
+            device,
+            queue,
+            &self.bond_buffer,
+            self.bond_buffer_size,
+        ))
+    }
+}
+
+/// See the `nannou::wgpu::bytes` documentation for why this is necessary.
+pub fn bonds_as_bytes(data: &[Bond]) -> &[u8] {
+    unsafe { wgpu::bytes::from_slice(data) }
+}
+
+/// See the `nannou::wgpu::bytes` documentation for why this is necessary.
+pub fn candidates_as_bytes(data: &[BondCandidate]) -> &[u8] {
+    unsafe { wgpu::bytes::from_slice(data) }
+}
+
+pub fn byte_vec_as_candidates(bytes: &[u8]) -> Vec<BondCandidate> {
+    bytes
+        .chunks_exact(8)
+        .map(|b| BondCandidate {
+            partner: u32::from_ne_bytes([b[0], b[1], b[2], b[3]]),
+            time: f32::from_ne_bytes([b[4], b[5], b[6], b[7]]),
+        })
+        .collect()
+}
+
+pub fn byte_vec_as_bonds(bytes: &[u8]) -> Vec<Bond> {
+    bytes
+        .chunks_exact(8)
+        .map(|b| {
+            Bond::new(
+                u32::from_ne_bytes([b[0], b[1], b[2], b[3]]),
+                f32::from_ne_bytes([b[4], b[5], b[6], b[7]]),
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,58 @@
+//! Mouse control of the camera `shader3d.frag` renders the 3D sketch from.
+
+use nannou::prelude::*;
+
+use crate::uniforms::*;
+
+/// Left drag orbits the camera around the origin, the mouse wheel moves it closer or further.
+#[derive(Debug, Clone)]
+pub struct OrbitCamera {
+    /// Angle around the y axis, in radians.
+    pub yaw: f32,
+    /// Angle above the xz plane, in radians, kept short of the poles.
+    pub pitch: f32,
+    pub distance: f32,
+    /// Radians per pixel dragged.
+    pub sensitivity: f32,
+    /// Cursor position of the last frame while dragging.
+    last_position: Option<Point2>,
+}
+
+impl OrbitCamera {
+    const MAX_PITCH: f32 = 1.5;
+
+    pub fn new(distance: f32) -> Self {
+        OrbitCamera {
+            yaw: 0.0,
+            pitch: 0.3,
+            distance,
+            sensitivity: 0.005,
+            last_position: None,
+        }
+    }
+
+    /// Moves the camera toward the origin for positive `lines` of scrolling, away for negative.
+    pub fn zoom(&mut self, lines: f32) {
+        self.distance = (self.distance * 0.9.powf(lines)).max(1.0);
+    }
+
+    /// Follows this frame's drag and writes the camera into `uniforms`.
+    pub fn update(&mut self, app: &App, uniforms: &mut Uniforms) {
+        let position = app.mouse.position();
+        if app.mouse.window.is_some() && app.mouse.buttons.left().is_down() {
+            if let Some(last) = self.last_position {
+                let delta = position - last;
+                self.yaw -= delta.x * self.sensitivity;
+                self.pitch = (self.pitch - delta.y * self.sensitivity)
+                    .clamp(-Self::MAX_PITCH, Self::MAX_PITCH);
+            }
+            self.last_position = Some(position);
+        } else {
+            self.last_position = None;
+        }
+
+        uniforms.camera_yaw = self.yaw;
+        uniforms.camera_pitch = self.pitch;
+        uniforms.camera_distance = self.distance;
+    }
+}
@@ -1,4 +1,6 @@
 use nannou::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 
 pub struct FrameCapturer {
     texture_capturer: wgpu::TextureCapturer,
@@ -6,10 +8,14 @@ pub struct FrameCapturer {
 }
 
 impl FrameCapturer {
-    pub fn new(app: &App) -> Self {
+    /// Writes `seed` to `seed.txt` next to the frames, so a capture can be reproduced.
+    pub fn new(app: &App, seed: u64) -> Self {
         let texture_capturer = wgpu::TextureCapturer::default();
 
-        std::fs::create_dir_all(&capture_directory(app)).unwrap();
+        let directory = capture_directory(app);
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("seed.txt"), format!("{}\n", seed))
+            .expect("failed to save the seed");
 
         Self {
             texture_capturer,
@@ -28,7 +34,7 @@ impl FrameCapturer {
         // 1. Resolve the texture to a non-multisampled texture if necessary.
         // 2. Convert the format to non-linear 8-bit sRGBA ready for image storage.
         // 3. Copy the result to a buffer ready to be mapped for reading.
-        self.snapshot = Some(self.texture_capturer.capture(device, encoder, &texture));
+        self.snapshot = Some(self.texture_capturer.capture(device, encoder, texture));
     }
 
     pub fn save_frame(&mut self, app: &App) {
@@ -54,6 +60,23 @@ impl FrameCapturer {
     }
 }
 
+/// Creates the CSV file `name` next to the captured frames, starting with the `header` line.
+pub fn create_csv(app: &App, name: &str, header: &str) -> BufWriter<File> {
+    let directory = capture_directory(app);
+    std::fs::create_dir_all(&directory).unwrap();
+
+    let file = File::create(directory.join(name))
+        .unwrap_or_else(|e| panic!("failed to create {}: {}", name, e));
+    let mut writer = BufWriter::new(file);
+    writeln!(writer, "{}", header).expect("failed to write the csv header");
+    writer
+}
+
+/// The checkpoint file next to the captured frames.
+pub fn checkpoint_path(app: &App) -> std::path::PathBuf {
+    capture_directory(app).join("checkpoint.bin")
+}
+
 /// Returns the directory to save captured frames.
 fn capture_directory(app: &App) -> std::path::PathBuf {
     app.project_path()
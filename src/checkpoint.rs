@@ -0,0 +1,428 @@
+//! Saving a run to a file and continuing it later.
+
+use nannou::prelude::*;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::bonds::*;
+use crate::environment::*;
+use crate::fields::*;
+use crate::obstacles::*;
+use crate::particles::*;
+use crate::population::*;
+use crate::species::*;
+use crate::uniforms::*;
+
+/// First bytes of every checkpoint file.
+const CHECKPOINT_MAGIC: &[u8; 8] = b"GENMATTR";
+
+/// Bumped whenever the file layout, `Uniforms` or the layout of a saved struct changes,
+/// older checkpoints are refused rather than misread. The uniforms are saved as their raw
+/// `repr(C)` bytes, so adding, removing or reordering a field of `Uniforms` needs a bump too.
+pub const CHECKPOINT_VERSION: u32 = 3;
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    NotACheckpoint,
+    UnsupportedVersion(u32),
+    /// The named section does not match the capacity and counts of the uniforms.
+    InvalidSection(&'static str),
+    /// The named part of the checkpoint differs from the simulation it is loaded into.
+    Mismatch(&'static str),
+}
+
+impl From<io::Error> for CheckpointError {
+    fn from(error: io::Error) -> Self {
+        CheckpointError::Io(error)
+    }
+}
+
+/// Everything a run continues from: the uniforms, which hold the frame and time,
+/// the seed of the run, every particle slot, and the species, population and environment.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub seed: u64,
+    pub uniforms: Uniforms,
+    pub state: ParticleState,
+    /// The integrator buffer as is, its layout depends on the integrator.
+    pub integrator: Vec<u8>,
+    /// The bond candidate of every slot, with how long it has been within reach.
+    pub candidates: Vec<BondCandidate>,
+    pub species: SpeciesConfig,
+    pub population: PopulationConfig,
+    pub environment: EnvironmentConfig,
+}
+
+impl Checkpoint {
+    pub fn frame(&self) -> u32 {
+        self.uniforms.frame
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), CheckpointError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, CheckpointError> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Writes the magic bytes, the version and the seed, then the uniforms, every state
+    /// buffer and the configs as length prefixed sections,
+    /// all in the machine's byte order like the GPU buffers.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let state = &self.state;
+        let alive: Vec<u32> = state.alive.iter().map(|alive| *alive as u32).collect();
+
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        writer.write_all(&CHECKPOINT_VERSION.to_ne_bytes())?;
+        writer.write_all(&self.seed.to_ne_bytes())?;
+        write_section(writer, uniforms_as_bytes(&self.uniforms))?;
+        write_section(writer, &vectors_as_byte_vec(&state.positions))?;
+        write_section(writer, &vectors_as_byte_vec(&state.velocities))?;
+        write_section(writer, &uints_as_byte_vec(&state.species))?;
+        write_section(writer, attributes_as_bytes(&state.attributes))?;
+        write_section(writer, &uints_as_byte_vec(&alive))?;
+        write_section(writer, bonds_as_bytes(&state.bonds))?;
+        write_section(writer, &self.integrator)?;
+        write_section(writer, candidates_as_bytes(&self.candidates))?;
+        write_section(writer, structs_as_bytes(&self.species.ratios))?;
+        write_section(writer, structs_as_bytes(&self.species.rules))?;
+        write_section(writer, &self.population.initial_count.to_ne_bytes())?;
+        write_section(writer, structs_as_bytes(&self.population.emitters))?;
+        write_section(writer, structs_as_bytes(&self.population.sinks))?;
+        write_section(writer, structs_as_bytes(&self.environment.obstacles))?;
+        write_section(writer, structs_as_bytes(&self.environment.fields))
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, CheckpointError> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(CheckpointError::NotACheckpoint);
+        }
+
+        let version = u32::from_ne_bytes(read_array(reader)?);
+        if version != CHECKPOINT_VERSION {
+            return Err(CheckpointError::UnsupportedVersion(version));
+        }
+        let seed = u64::from_ne_bytes(read_array(reader)?);
+
+        let uniforms = read_section(reader)?;
+        if uniforms.len() != std::mem::size_of::<Uniforms>() {
+            return Err(CheckpointError::InvalidSection("uniforms"));
+        }
+        let uniforms = bytes_as_uniforms(&uniforms);
+
+        // every other section holds as many elements as the uniforms say,
+        // counts too large to address are as invalid as ones that do not match
+        let mut section = |name: &'static str, count: Option<usize>, element_size: usize| {
+            let bytes = read_section(reader)?;
+            match count.and_then(|count| count.checked_mul(element_size)) {
+                Some(len) if len == bytes.len() => Ok(bytes),
+                _ => Err(CheckpointError::InvalidSection(name)),
+            }
+        };
+
+        let capacity = Some(uniforms.particle_capacity as usize);
+        let positions = byte_vec_as_vectors(&section("positions", capacity, 8)?);
+        let velocities = byte_vec_as_vectors(&section("velocities", capacity, 8)?);
+        let species = byte_vec_as_uints(&section("species", capacity, 4)?);
+        let attributes = byte_vec_as_attributes(&section(
+            "attributes",
+            capacity,
+            std::mem::size_of::<ParticleAttributes>(),
+        )?);
+        let alive = byte_vec_as_uints(&section("alive", capacity, 4)?)
+            .into_iter()
+            .map(|alive| alive != 0)
+            .collect();
+        let bonds = byte_vec_as_bonds(&section(
+            "bonds",
+            capacity.and_then(|capacity| capacity.checked_mul(MAX_BONDS as usize)),
+            std::mem::size_of::<Bond>(),
+        )?);
+        let integrator = section("integrator", capacity, std::mem::size_of::<Vec4>())?;
+        let candidates = byte_vec_as_candidates(&section(
+            "candidates",
+            capacity,
+            std::mem::size_of::<BondCandidate>(),
+        )?);
+
+        let species_count = uniforms.species_count;
+        let ratios = bytes_as_structs(&section("species ratios", Some(species_count as usize), 4)?);
+        let rules = bytes_as_structs(&section(
+            "species rules",
+            (species_count as usize).checked_mul(species_count as usize),
+            std::mem::size_of::<SpeciesRule>(),
+        )?);
+        let initial_count = byte_vec_as_uints(&section("population", Some(1), 4)?)[0];
+        let emitters = bytes_as_structs(&section(
+            "emitters",
+            Some(uniforms.emitter_count as usize),
+            std::mem::size_of::<Emitter>(),
+        )?);
+        let sinks = bytes_as_structs(&section(
+            "sinks",
+            Some(uniforms.sink_count as usize),
+            std::mem::size_of::<Sink>(),
+        )?);
+        let obstacles = bytes_as_structs(&section(
+            "obstacles",
+            Some(uniforms.obstacle_count as usize),
+            std::mem::size_of::<Obstacle>(),
+        )?);
+        let fields = bytes_as_structs(&section(
+            "fields",
+            Some(uniforms.field_count as usize),
+            std::mem::size_of::<ForceField>(),
+        )?);
+
+        Ok(Self {
+            seed,
+            uniforms,
+            state: ParticleState {
+                positions,
+                velocities,
+                species,
+                attributes,
+                alive,
+                bonds,
+            },
+            integrator,
+            candidates,
+            species: SpeciesConfig {
+                count: species_count,
+                rules,
+                ratios,
+            },
+            population: PopulationConfig::new(initial_count)
+                .with_emitters(emitters)
+                .with_sinks(sinks),
+            environment: EnvironmentConfig::new()
+                .with_obstacles(obstacles)
+                .with_fields(fields),
+        })
+    }
+}
+
+fn write_section<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u64).to_ne_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_section<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let len = u64::from_ne_bytes(read_array(reader)?);
+    // read through `take` so a corrupt length fails instead of allocating it up front
+    let mut bytes = vec![];
+    reader.by_ref().take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// See the `nannou::wgpu::bytes` documentation for why this is necessary.
+fn uniforms_as_bytes(uniforms: &Uniforms) -> &[u8] {
+    unsafe { wgpu::bytes::from(uniforms) }
+}
+
+fn bytes_as_uniforms(bytes: &[u8]) -> Uniforms {
+    assert_eq!(bytes.len(), std::mem::size_of::<Uniforms>());
+    // every field is a 32 bit uint or float, for which any bits are valid
+    unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const Uniforms) }
+}
+
+/// See the `nannou::wgpu::bytes` documentation for why this is necessary.
+fn structs_as_bytes<T: Copy>(data: &[T]) -> &[u8] {
+    unsafe { wgpu::bytes::from_slice(data) }
+}
+
+/// Only for the `repr(C)` configs made of 32 bit uints and floats,
+/// for which any bits are valid.
+fn bytes_as_structs<T: Copy>(bytes: &[u8]) -> Vec<T> {
+    bytes
+        .chunks_exact(std::mem::size_of::<T>())
+        .map(|chunk| unsafe { std::ptr::read_unaligned(chunk.as_ptr() as *const T) })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A run of three slots with one of everything.
+    fn checkpoint() -> Checkpoint {
+        let mut uniforms = Uniforms::new(3, 640.0, 480.0);
+        uniforms.frame = 12;
+        uniforms.time = 6.0;
+        uniforms.species_count = 2;
+        uniforms.emitter_count = 1;
+        uniforms.sink_count = 1;
+        uniforms.obstacle_count = 1;
+        uniforms.field_count = 1;
+
+        let mut bonds = vec![Bond::NONE; 3 * MAX_BONDS as usize];
+        bonds[0] = Bond::new(1, 4.0);
+        bonds[MAX_BONDS as usize] = Bond::new(0, 4.0);
+
+        Checkpoint {
+            seed: 1234,
+            uniforms,
+            state: ParticleState {
+                positions: vec![pt2(0.0, 1.0), pt2(2.0, 3.0), pt2(4.0, 5.0)],
+                velocities: vec![pt2(-1.0, 0.5), pt2(0.0, 0.0), pt2(0.25, -2.0)],
+                species: vec![0, 1, 1],
+                attributes: vec![
+                    ParticleAttributes::new(1.0, 1.0, 0.0),
+                    ParticleAttributes::new(2.0, 0.5, -1.0),
+                    ParticleAttributes::default(),
+                ],
+                alive: vec![true, true, false],
+                bonds,
+            },
+            integrator: (0..48).collect(),
+            candidates: vec![
+                BondCandidate {
+                    partner: 1,
+                    time: 0.5,
+                },
+                BondCandidate::NONE,
+                BondCandidate::NONE,
+            ],
+            species: SpeciesConfig {
+                count: 2,
+                rules: vec![
+                    SpeciesRule::new(1.0, 2.0, 0.5, 0.25),
+                    SpeciesRule::default(),
+                    SpeciesRule::new(-1.0, 1.0, 1.0, 1.0),
+                    SpeciesRule::default(),
+                ],
+                ratios: vec![1.0, 3.0],
+            },
+            population: PopulationConfig::new(2)
+                .with_emitters(vec![Emitter {
+                    rate: 2.0,
+                    species: 1,
+                    ..Default::default()
+                }])
+                .with_sinks(vec![Sink::new(pt2(50.0, 0.0), 8.0)]),
+            environment: EnvironmentConfig::new()
+                .with_obstacles(vec![Obstacle::circle(pt2(-20.0, 10.0), 5.0)])
+                .with_fields(vec![ForceField::curl_noise(0.02, 150.0, 0.01)]),
+        }
+    }
+
+    fn bytes(checkpoint: &Checkpoint) -> Vec<u8> {
+        let mut bytes = vec![];
+        checkpoint.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trips() {
+        let expected = checkpoint();
+        let actual = Checkpoint::read(&mut bytes(&expected).as_slice()).unwrap();
+
+        assert_eq!(actual.seed, expected.seed);
+        assert_eq!(
+            uniforms_as_bytes(&actual.uniforms),
+            uniforms_as_bytes(&expected.uniforms)
+        );
+        assert_eq!(actual.frame(), 12);
+        assert_eq!(actual.state, expected.state);
+        assert_eq!(actual.integrator, expected.integrator);
+        assert_eq!(actual.candidates, expected.candidates);
+        assert_eq!(actual.species, expected.species);
+        assert_eq!(actual.population, expected.population);
+        assert_eq!(actual.environment, expected.environment);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(matches!(
+            Checkpoint::read(&mut &b"not a checkpoint"[..]),
+            Err(CheckpointError::NotACheckpoint)
+        ));
+        assert!(matches!(
+            Checkpoint::read(&mut &b""[..]),
+            Err(CheckpointError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = bytes(&checkpoint());
+        bytes[8..12].copy_from_slice(&(CHECKPOINT_VERSION + 1).to_ne_bytes());
+        assert!(matches!(
+            Checkpoint::read(&mut bytes.as_slice()),
+            Err(CheckpointError::UnsupportedVersion(version)) if version == CHECKPOINT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_sections_not_matching_the_uniforms() {
+        let mut short = checkpoint();
+        short.state.positions.pop();
+        assert!(matches!(
+            Checkpoint::read(&mut bytes(&short).as_slice()),
+            Err(CheckpointError::InvalidSection("positions"))
+        ));
+
+        let mut miscounted = checkpoint();
+        miscounted.uniforms.species_count = 3;
+        assert!(matches!(
+            Checkpoint::read(&mut bytes(&miscounted).as_slice()),
+            Err(CheckpointError::InvalidSection("species ratios"))
+        ));
+
+        let mut bytes = bytes(&checkpoint());
+        bytes[20..28].copy_from_slice(&4u64.to_ne_bytes());
+        assert!(matches!(
+            Checkpoint::read(&mut bytes.as_slice()),
+            Err(CheckpointError::InvalidSection("uniforms"))
+        ));
+    }
+
+    #[test]
+    fn rejects_counts_too_large_to_address() {
+        // 65536² species rules overflow 32 bits
+        let mut many_species = checkpoint();
+        many_species.uniforms.species_count = 1 << 16;
+        many_species.species.ratios = vec![1.0; 1 << 16];
+        assert!(matches!(
+            Checkpoint::read(&mut bytes(&many_species).as_slice()),
+            Err(CheckpointError::InvalidSection("species rules"))
+        ));
+
+        let mut huge = checkpoint();
+        huge.uniforms.particle_capacity = u32::MAX;
+        assert!(matches!(
+            Checkpoint::read(&mut bytes(&huge).as_slice()),
+            Err(CheckpointError::InvalidSection("positions"))
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_sections() {
+        let bytes = bytes(&checkpoint());
+        for len in [24, bytes.len() / 2, bytes.len() - 1] {
+            match Checkpoint::read(&mut &bytes[..len]) {
+                Err(CheckpointError::Io(e)) => {
+                    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof)
+                }
+                other => panic!("read {} bytes as {:?}", len, other.map(|c| c.frame())),
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use nannou::prelude::*;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::cpu::{minimum_image, CellMap};
+use crate::particles::ParticleState;
+use crate::uniforms::*;
+
+/// Label of dead particles, must match `NO_CLUSTER` in `shader.frag`.
+pub const NO_CLUSTER: u32 = u32::MAX;
+
+/// Size classes of the histogram, see `ClusterStats::histogram`.
+pub const HISTOGRAM_BINS: usize = 12;
+
+/// Column names of the rows written by `ClusterStats::write_csv`.
+pub const CSV_HEADER: &str = "frame,cluster,size,centroid_x,centroid_y,radius_of_gyration";
+
+/// A connected group of particles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cluster {
+    /// Lowest index among the members, so it stays the same while that particle stays in.
+    pub id: u32,
+    pub size: u32,
+    pub centroid: Point2,
+    /// Root mean square distance of the members from the centroid.
+    pub radius_of_gyration: f32,
+}
+
+/// Connected components of the live particles, where particles closer than a distance
+/// threshold are connected. Isolated particles form clusters of one.
+#[derive(Debug, Clone, Default)]
+pub struct ClusterStats {
+    /// Cluster id of every slot, `NO_CLUSTER` for dead ones.
+    pub labels: Vec<u32>,
+    /// Largest first, clusters of equal size by id.
+    pub clusters: Vec<Cluster>,
+    /// Bin `i` counts the clusters of `2^i` up to `2^(i + 1) - 1` particles,
+    /// the last bin also counts all larger ones.
+    pub histogram: [u32; HISTOGRAM_BINS],
+}
+
+impl ClusterStats {
+    /// Finds the clusters of `state`, connecting particles closer than `distance`.
+    /// Periodic domains connect across the edges, centroids are then measured from the
+    /// lowest index member so clusters spanning half the domain or more get misplaced.
+    pub fn find(state: &ParticleState, distance: f32, uniforms: &Uniforms) -> Self {
+        assert!(distance > 0.0, "the cluster distance must be positive");
+
+        let grid = CellMap::with_cell_size(state, uniforms, distance);
+        let mut parents: Vec<usize> = (0..state.len()).collect();
+        for index in 0..state.len() {
+            if !state.alive[index] {
+                continue;
+            }
+
+            let position = state.positions[index];
+            for other in grid.neighbors(position) {
+                let diff = minimum_image(state.positions[other] - position, uniforms);
+                if other > index && diff.length() < distance {
+                    union(&mut parents, index, other);
+                }
+            }
+        }
+
+        let labels: Vec<u32> = (0..state.len())
+            .map(|index| {
+                if state.alive[index] {
+                    root(&mut parents, index) as u32
+                } else {
+                    NO_CLUSTER
+                }
+            })
+            .collect();
+
+        // member offsets from the root, which is the lowest index of each cluster
+        let mut offsets: HashMap<u32, Vec<Point2>> = HashMap::new();
+        for (index, label) in labels.iter().enumerate() {
+            if *label != NO_CLUSTER {
+                let origin = state.positions[*label as usize];
+                let offset = minimum_image(state.positions[index] - origin, uniforms);
+                offsets.entry(*label).or_default().push(offset);
+            }
+        }
+
+        let mut clusters: Vec<Cluster> = offsets
+            .into_iter()
+            .map(|(id, offsets)| {
+                let size = offsets.len() as f32;
+                let mean = offsets.iter().fold(vec2(0.0, 0.0), |sum, o| sum + *o) / size;
+                let variance = offsets
+                    .iter()
+                    .map(|o| (*o - mean).length_squared())
+                    .sum::<f32>()
+                    / size;
+
+                Cluster {
+                    id,
+                    size: offsets.len() as u32,
+                    centroid: state.positions[id as usize] + mean,
+                    radius_of_gyration: variance.sqrt(),
+                }
+            })
+            .collect();
+        clusters.sort_by_key(|cluster| (std::cmp::Reverse(cluster.size), cluster.id));
+
+        let mut histogram = [0; HISTOGRAM_BINS];
+        for cluster in &clusters {
+            let bin = (31 - cluster.size.leading_zeros()) as usize;
+            histogram[bin.min(HISTOGRAM_BINS - 1)] += 1;
+        }
+
+        Self {
+            labels,
+            clusters,
+            histogram,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.clusters.len()
+    }
+
+    pub fn largest(&self) -> Option<&Cluster> {
+        self.clusters.first()
+    }
+
+    /// A few lines for the HUD: the cluster count, the largest cluster,
+    /// and the nonempty histogram bins.
+    pub fn summary(&self) -> String {
+        let mut lines = vec![format!("clusters: {}", self.count())];
+        if let Some(largest) = self.largest() {
+            lines.push(format!(
+                "largest: {} particles, radius of gyration {:.1}",
+                largest.size, largest.radius_of_gyration
+            ));
+        }
+
+        for (bin, count) in self.histogram.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+
+            let smallest = 1u32 << bin;
+            let sizes = if bin == HISTOGRAM_BINS - 1 {
+                format!("{}+", smallest)
+            } else if smallest == 1 {
+                "1".to_string()
+            } else {
+                format!("{}-{}", smallest, 2 * smallest - 1)
+            };
+            lines.push(format!("  {} particles: {}", sizes, count));
+        }
+
+        lines.join("\n")
+    }
+
+    /// Writes one `CSV_HEADER` row per cluster.
+    pub fn write_csv<W: Write>(&self, writer: &mut W, frame: u32) -> io::Result<()> {
+        for cluster in &self.clusters {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                frame,
+                cluster.id,
+                cluster.size,
+                cluster.centroid.x,
+                cluster.centroid.y,
+                cluster.radius_of_gyration
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Root of the set holding `index`, compressing the path to it.
+fn root(parents: &mut [usize], index: usize) -> usize {
+    let mut root = index;
+    while parents[root] != root {
+        root = parents[root];
+    }
+
+    let mut node = index;
+    while parents[node] != root {
+        let next = parents[node];
+        parents[node] = root;
+        node = next;
+    }
+
+    root
+}
+
+/// Merges the sets of `a` and `b` under the lower of their roots.
+fn union(parents: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (root(parents, a), root(parents, b));
+    parents[a.max(b)] = a.min(b);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bonds::{Bond, MAX_BONDS};
+    use crate::particles::ParticleAttributes;
+
+    /// Live particles at `positions`, apart from the `dead` slots.
+    fn state(positions: &[Point2], dead: &[usize]) -> ParticleState {
+        let count = positions.len();
+        ParticleState {
+            positions: positions.to_vec(),
+            velocities: vec![vec2(0.0, 0.0); count],
+            species: vec![0; count],
+            attributes: vec![ParticleAttributes::default(); count],
+            alive: (0..count).map(|index| !dead.contains(&index)).collect(),
+            bonds: vec![Bond::NONE; count * MAX_BONDS as usize],
+        }
+    }
+
+    #[test]
+    fn connects_particles_closer_than_the_distance() {
+        // a chain of three, a loner, a pair, and a dead particle touching the chain
+        let state = state(
+            &[
+                pt2(10.0, 0.0),
+                pt2(5.0, 0.0),
+                pt2(0.0, 0.0),
+                pt2(100.0, 0.0),
+                pt2(200.0, 0.0),
+                pt2(203.0, 0.0),
+                pt2(-2.0, 0.0),
+            ],
+            &[6],
+        );
+        let uniforms = Uniforms::new(7, 1920.0, 1080.0);
+        let stats = ClusterStats::find(&state, 6.0, &uniforms);
+
+        assert_eq!(stats.labels, vec![0, 0, 0, 3, 4, 4, NO_CLUSTER]);
+        let sizes: Vec<(u32, u32)> = stats.clusters.iter().map(|c| (c.id, c.size)).collect();
+        assert_eq!(sizes, vec![(0, 3), (4, 2), (3, 1)]);
+
+        let chain = stats.largest().unwrap();
+        assert!((chain.centroid - pt2(5.0, 0.0)).length() < 1e-5);
+        assert!((chain.radius_of_gyration - (50.0f32 / 3.0).sqrt()).abs() < 1e-5);
+        assert_eq!(stats.clusters[1].centroid, pt2(201.5, 0.0));
+        assert_eq!(stats.clusters[1].radius_of_gyration, 1.5);
+    }
+
+    #[test]
+    fn bins_clusters_by_size() {
+        // clusters of 1, 2, 3 and 4 particles, and one too large for every bin but the last
+        let mut positions = vec![];
+        for (cluster, size) in [1, 2, 3, 4, 5000].iter().enumerate() {
+            for member in 0..*size {
+                positions.push(pt2(member as f32, cluster as f32 * 10.0));
+            }
+        }
+        let uniforms = Uniforms::new(positions.len() as u32, 1920.0, 1080.0);
+        let stats = ClusterStats::find(&state(&positions, &[]), 1.5, &uniforms);
+
+        let mut expected = [0; HISTOGRAM_BINS];
+        expected[0] = 1;
+        expected[1] = 2;
+        expected[2] = 1;
+        expected[HISTOGRAM_BINS - 1] = 1;
+        assert_eq!(stats.histogram, expected);
+        assert_eq!(stats.count(), 5);
+    }
+
+    #[test]
+    fn connects_across_periodic_edges() {
+        let state = state(&[pt2(-49.0, 0.0), pt2(49.0, 0.0), pt2(0.0, 0.0)], &[]);
+        let mut uniforms = Uniforms::new(3, 100.0, 100.0);
+        uniforms.boundary_mode = BoundaryMode::Wrap as u32;
+        let stats = ClusterStats::find(&state, 3.0, &uniforms);
+
+        assert_eq!(stats.labels, vec![0, 0, 2]);
+        // measured from the lowest index member, so just outside the left edge
+        let pair = stats.largest().unwrap();
+        assert!((pair.centroid - pt2(-50.0, 0.0)).length() < 1e-5);
+        assert!((pair.radius_of_gyration - 1.0).abs() < 1e-5);
+    }
+}
@@ -6,9 +6,18 @@ pub enum ComputeError {
     BufferCountAndBufferSizeCountMismatch,
 }
 
+/// The most workgroups a single dispatch dimension may hold.
+pub const MAX_DISPATCH_GROUPS: u32 = 65535;
+
+/// The largest workgroup every device supports.
+pub const MAX_WORKGROUP_SIZE: u32 = 256;
+
 pub struct Compute {
-    pub bind_group: wgpu::BindGroup,
+    /// One bind group per buffer set, all sharing the same layout.
+    pub bind_groups: Vec<wgpu::BindGroup>,
     pub pipeline: wgpu::ComputePipeline,
+    /// Must match the shader's `local_size_x`.
+    pub workgroup_size: u32,
 }
 
 /// Builds and manages a Compute pipeline with any number of buffers,
@@ -21,69 +30,141 @@ impl Compute {
         uniform_buffer: Option<&wgpu::Buffer>,
         cs_mod: &wgpu::ShaderModule,
     ) -> Result<Self, ComputeError>
+    where
+        T: std::marker::Copy,
+    {
+        let buffer_sets = buffers.map(|b| vec![b]).unwrap_or_default();
+        Self::with_buffer_sets::<T>(device, buffer_sets, buffer_sizes, uniform_buffer, cs_mod)
+    }
+
+    /// Like `new`, but creates one bind group per set of buffers so the same pipeline
+    /// can run on different buffers, for example with the input and output swapped.
+    /// Every set must match `buffer_sizes`.
+    pub fn with_buffer_sets<T>(
+        device: &wgpu::Device,
+        buffer_sets: Vec<Vec<&wgpu::Buffer>>,
+        buffer_sizes: Option<Vec<wgpu::BufferAddress>>,
+        uniform_buffer: Option<&wgpu::Buffer>,
+        cs_mod: &wgpu::ShaderModule,
+    ) -> Result<Self, ComputeError>
     where
         T: std::marker::Copy,
     {
         let mut bind_group_layout_builder = wgpu::BindGroupLayoutBuilder::new();
-        let mut bind_group_builder = wgpu::BindGroupBuilder::new();
 
-        // add buffers to bind group
-        if let Some(b) = buffers.as_ref() {
+        // add buffers to the layout
+        let buffer_count = buffer_sets.first().map_or(0, |b| b.len());
+        if buffer_count > 0 {
             if let Some(s) = buffer_sizes.as_ref() {
-                if b.len() != s.len() {
+                if buffer_sets.iter().any(|b| b.len() != s.len()) {
                     return Err(ComputeError::BufferCountAndBufferSizeCountMismatch);
                 }
 
                 let storage_dynamic = false;
                 let storage_readonly = false;
 
-                for (i, buffer) in b.iter().enumerate() {
-                    let buffer_size = s[i];
-
+                for _ in 0..buffer_count {
                     bind_group_layout_builder = bind_group_layout_builder.storage_buffer(
                         wgpu::ShaderStages::COMPUTE,
                         storage_dynamic,
                         storage_readonly,
                     );
-
-                    let buffer_size_bytes = std::num::NonZeroU64::new(buffer_size).unwrap();
-                    bind_group_builder =
-                        bind_group_builder.buffer_bytes(buffer, 0, Some(buffer_size_bytes));
                 }
             } else {
                 return Err(ComputeError::MissingBufferSizes);
             }
         }
 
-        // add uniform buffer to bind group
-        if let Some(u) = uniform_buffer {
+        // add uniform buffer to the layout
+        if uniform_buffer.is_some() {
             let uniform_dynamic = false;
             bind_group_layout_builder = bind_group_layout_builder
                 .uniform_buffer(wgpu::ShaderStages::COMPUTE, uniform_dynamic);
-
-            bind_group_builder = bind_group_builder.buffer::<T>(u, 0..1);
         }
 
         let bind_group_layout = bind_group_layout_builder.build(device);
-        let bind_group = bind_group_builder.build(device, &bind_group_layout);
+
+        // a pipeline without buffers still needs its uniform bind group
+        let buffer_sets = if buffer_sets.is_empty() {
+            vec![vec![]]
+        } else {
+            buffer_sets
+        };
+
+        let bind_groups = buffer_sets
+            .iter()
+            .map(|buffers| {
+                let mut bind_group_builder = wgpu::BindGroupBuilder::new();
+
+                for (i, buffer) in buffers.iter().enumerate() {
+                    let buffer_size = buffer_sizes.as_ref().unwrap()[i];
+                    let buffer_size_bytes = std::num::NonZeroU64::new(buffer_size).unwrap();
+                    bind_group_builder =
+                        bind_group_builder.buffer_bytes(buffer, 0, Some(buffer_size_bytes));
+                }
+
+                if let Some(u) = uniform_buffer {
+                    bind_group_builder = bind_group_builder.buffer::<T>(u, 0..1);
+                }
+
+                bind_group_builder.build(device, &bind_group_layout)
+            })
+            .collect();
 
         let pipeline_layout = create_pipeline_layout(device, &bind_group_layout);
         let pipeline = create_compute_pipeline(device, &pipeline_layout, cs_mod);
 
         Ok(Self {
-            bind_group,
+            bind_groups,
             pipeline,
+            workgroup_size: 1,
         })
     }
 
-    pub fn compute(&self, encoder: &mut wgpu::CommandEncoder, num_groups: u32) {
+    /// Sets the workgroup size the shader was compiled with, 1 by default.
+    pub fn with_workgroup_size(mut self, workgroup_size: u32) -> Self {
+        assert!(
+            workgroup_size > 0 && workgroup_size <= MAX_WORKGROUP_SIZE,
+            "workgroup size must be in 1..={}",
+            MAX_WORKGROUP_SIZE
+        );
+        self.workgroup_size = workgroup_size;
+        self
+    }
+
+    /// Runs at least `invocations` invocations, see `dispatch_size`.
+    pub fn compute(&self, encoder: &mut wgpu::CommandEncoder, invocations: u32) {
+        self.compute_with(encoder, 0, invocations);
+    }
+
+    /// Like `compute`, using the bind group of the given buffer set.
+    pub fn compute_with(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        buffer_set: usize,
+        invocations: u32,
+    ) {
+        let (groups_x, groups_y) = dispatch_size(invocations, self.workgroup_size);
+
         let pass_desc = wgpu::ComputePassDescriptor {
             label: Some("compute-pass"),
         };
         let mut cpass = encoder.begin_compute_pass(&pass_desc);
         cpass.set_pipeline(&self.pipeline);
-        cpass.set_bind_group(0, &self.bind_group, &[]);
-        cpass.dispatch(num_groups, 1, 1);
+        cpass.set_bind_group(0, &self.bind_groups[buffer_set], &[]);
+        cpass.dispatch(groups_x, groups_y, 1);
+    }
+}
+
+/// Workgroups needed in x and y for `invocations`, split into rows once x would pass the limit.
+/// Shaders flatten the two dimensions with `invocation_index` from `dispatch.glsl`
+/// and must bounds check the result, since the last workgroups may be partly out of range.
+pub fn dispatch_size(invocations: u32, workgroup_size: u32) -> (u32, u32) {
+    let groups = invocations.div_ceil(workgroup_size);
+    if groups <= MAX_DISPATCH_GROUPS {
+        (groups, 1)
+    } else {
+        (MAX_DISPATCH_GROUPS, groups.div_ceil(MAX_DISPATCH_GROUPS))
     }
 }
 
@@ -93,7 +174,7 @@ fn create_pipeline_layout(
 ) -> wgpu::PipelineLayout {
     device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
         label: Some("compute-pipeline-layout"),
-        bind_group_layouts: &[&bind_group_layout],
+        bind_group_layouts: &[bind_group_layout],
         push_constant_ranges: &[],
     })
 }
@@ -106,7 +187,7 @@ fn create_compute_pipeline(
     let desc = wgpu::ComputePipelineDescriptor {
         label: Some("compute-pipeline"),
         layout: Some(layout),
-        module: &cs_mod,
+        module: cs_mod,
         entry_point: "main",
     };
     device.create_compute_pipeline(&desc)
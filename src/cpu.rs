@@ -0,0 +1,1150 @@
+use nannou::prelude::*;
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+use crate::backend::*;
+use crate::bonds::*;
+use crate::environment::*;
+use crate::particles::*;
+use crate::population::*;
+use crate::quadtree::*;
+use crate::random;
+use crate::species::*;
+use crate::uniforms::*;
+
+/// A multi-threaded CPU port of `update.comp`, for running without a GPU.
+/// The force, collision, momentum, clamping and integration math mirrors the shader line for line.
+pub struct CpuBackend {
+    state: ParticleState,
+    species: SpeciesConfig,
+    population: PopulationConfig,
+    environment: EnvironmentConfig,
+    /// Counterpart of the GPU integrator buffer.
+    integrator_state: Vec<Vec4>,
+    /// Counterpart of the GPU bond candidate buffer.
+    bond_candidates: Vec<BondCandidate>,
+}
+
+impl CpuBackend {
+    pub fn new(
+        state: ParticleState,
+        species: SpeciesConfig,
+        population: PopulationConfig,
+        environment: EnvironmentConfig,
+    ) -> Self {
+        let integrator_state = vec![Vec4::ZERO; state.len()];
+        let bond_candidates = vec![BondCandidate::NONE; state.len()];
+        Self {
+            state,
+            species,
+            population,
+            environment,
+            integrator_state,
+            bond_candidates,
+        }
+    }
+
+    /// Port of `population.comp`, removes particles inside sinks and spawns into dead slots.
+    fn update_population(&mut self, uniforms: &Uniforms) {
+        let emitters = &self.population.emitters;
+        let total_rate: f32 = emitters.iter().map(|emitter| emitter.rate).sum();
+        // counted before any changes, like the GPU live count
+        let dead_count = self.state.len() - self.state.live_count();
+        let frame = uniforms.frame;
+
+        for index in 0..self.state.len() {
+            if self.state.alive[index] {
+                let position = self.state.positions[index];
+                if self
+                    .population
+                    .sinks
+                    .iter()
+                    .any(|sink| sink.contains(position))
+                {
+                    self.state.alive[index] = false;
+                }
+                continue;
+            }
+
+            // every dead slot spawns with the same chance, so the emitters spawn at their rate on average
+            if total_rate <= 0.0 || dead_count == 0 {
+                continue;
+            }
+
+            let random = |stream| random::random(uniforms.seed, index as u32, frame, stream);
+            if random(2) >= total_rate * uniforms.dt / dead_count as f32 {
+                continue;
+            }
+
+            // pick an emitter weighted by its rate
+            let mut pick = random(3) * total_rate;
+            let mut e = 0;
+            while e + 1 < emitters.len() && pick >= emitters[e].rate {
+                pick -= emitters[e].rate;
+                e += 1;
+            }
+            let emitter = &emitters[e];
+
+            self.state.positions[index] = emitter.center
+                + random::random_in_disk(uniforms.seed, index as u32, frame, 4) * emitter.radius;
+            self.state.velocities[index] = emitter.velocity
+                + random::random_in_disk(uniforms.seed, index as u32, frame, 6)
+                    * emitter.velocity_spread;
+            self.state.species[index] = emitter.species;
+            self.state.attributes[index] = emitter.attributes;
+            self.integrator_state[index] = Vec4::ZERO;
+            self.state.alive[index] = true;
+        }
+    }
+
+    /// Port of `bonds.comp`, updates the bond candidates, then breaks and forms bonds.
+    fn update_bonds(&mut self, uniforms: &Uniforms) {
+        let state = &self.state;
+        let grid = CellMap::new(state, uniforms);
+        let last_candidates = &self.bond_candidates;
+
+        let candidates: Vec<BondCandidate> = (0..state.len())
+            .into_par_iter()
+            .map(|index| {
+                // the timer keeps running as long as the candidate stays the same
+                let partner = nearest_candidate(state, &grid, index, uniforms);
+                let last = last_candidates[index];
+                if partner != NO_BOND && partner == last.partner {
+                    BondCandidate {
+                        partner,
+                        time: last.time + uniforms.dt,
+                    }
+                } else {
+                    BondCandidate {
+                        partner,
+                        time: if partner == NO_BOND { 0.0 } else { uniforms.dt },
+                    }
+                }
+            })
+            .collect();
+
+        let bonds: Vec<Bond> = (0..state.len())
+            .into_par_iter()
+            .flat_map_iter(|index| update_particle_bonds(state, &candidates, index, uniforms))
+            .collect();
+
+        self.bond_candidates = candidates;
+        self.state.bonds = bonds;
+    }
+
+    fn run_stage(&mut self, stage: UpdateStage, uniforms: &Uniforms) {
+        let state = &self.state;
+        let species = &self.species;
+        let environment = &self.environment;
+        let integrator_state = &self.integrator_state;
+
+        // same unlimited range fallback as the shader
+        let unlimited = uniforms.attraction_range == 0.0 || uniforms.repulsion_range == 0.0;
+        let grid = if stage.uses_forces() && !unlimited {
+            Some(CellMap::new(state, uniforms))
+        } else {
+            None
+        };
+        let tree = if stage.uses_forces() && unlimited && uniforms.barnes_hut_theta > 0.0 {
+            Some(QuadtreeMap::new(state, uniforms, species.count))
+        } else {
+            None
+        };
+
+        let results: Vec<(Point2, Point2, Vec4)> = (0..state.len())
+            .into_par_iter()
+            .map(|index| {
+                // dead particles keep their state
+                if !state.alive[index] {
+                    return (
+                        state.positions[index],
+                        state.velocities[index],
+                        integrator_state[index],
+                    );
+                }
+
+                let particle = Particle {
+                    index,
+                    state,
+                    species,
+                    environment,
+                    grid: grid.as_ref(),
+                    tree: tree.as_ref(),
+                    uniforms,
+                };
+                particle.update(stage, integrator_state[index])
+            })
+            .collect();
+
+        for (index, (position, velocity, scratch)) in results.into_iter().enumerate() {
+            self.state.positions[index] = position;
+            self.state.velocities[index] = velocity;
+            self.integrator_state[index] = scratch;
+        }
+    }
+}
+
+impl SimulationBackend for CpuBackend {
+    fn step(&mut self, uniforms: &Uniforms) {
+        let integrator = Integrator::from_uint(uniforms.integrator);
+
+        if self.population.is_dynamic() {
+            self.update_population(uniforms);
+        }
+
+        if uniforms.bond_capture_distance > 0.0 {
+            self.update_bonds(uniforms);
+        }
+
+        for _ in 0..uniforms.substeps.max(1) {
+            for stage in UpdateStage::for_integrator(integrator) {
+                self.run_stage(*stage, uniforms);
+            }
+        }
+
+        if Thermostat::from_uint(uniforms.thermostat) != Thermostat::Off {
+            self.run_stage(UpdateStage::Thermostat, uniforms);
+        }
+    }
+
+    fn state(&mut self) -> ParticleState {
+        self.state.clone()
+    }
+}
+
+/// Live particle indices bucketed by grid cell, the exact counterpart of the GPU spatial hash.
+pub(crate) struct CellMap {
+    cell_size: Point2,
+    /// Cells per axis of a periodic domain, `None` for the unbounded grid.
+    wrap: Option<(i32, i32)>,
+    domain: Point2,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl CellMap {
+    fn new(state: &ParticleState, uniforms: &Uniforms) -> Self {
+        Self::with_cell_size(state, uniforms, grid_cell_size(uniforms))
+    }
+
+    /// Live particles binned into cells of at least `cell_size`,
+    /// so all particles within that distance lie in the 3x3 neighborhood.
+    pub(crate) fn with_cell_size(
+        state: &ParticleState,
+        uniforms: &Uniforms,
+        cell_size: f32,
+    ) -> Self {
+        let domain = vec2(uniforms.width, uniforms.height);
+
+        // periodic domains need a whole number of cells per axis so cells line up across the edges
+        let (cell_size, wrap) = match BoundaryMode::from_uint(uniforms.boundary_mode) {
+            BoundaryMode::Wrap => {
+                let x = ((domain.x / cell_size) as i32).max(1);
+                let y = ((domain.y / cell_size) as i32).max(1);
+                (domain / vec2(x as f32, y as f32), Some((x, y)))
+            }
+            _ => (vec2(cell_size, cell_size), None),
+        };
+
+        let mut map = Self {
+            cell_size,
+            wrap,
+            domain,
+            cells: HashMap::new(),
+        };
+        for (index, position) in state.positions.iter().enumerate() {
+            if state.alive[index] {
+                let cell = map.cell_of(*position);
+                map.cells.entry(cell).or_default().push(index);
+            }
+        }
+
+        map
+    }
+
+    fn cell_of(&self, position: Point2) -> (i32, i32) {
+        match self.wrap {
+            Some(_) => {
+                let offset = position + self.domain * 0.5;
+                self.wrap_cell((
+                    (offset.x / self.cell_size.x).floor() as i32,
+                    (offset.y / self.cell_size.y).floor() as i32,
+                ))
+            }
+            None => (
+                (position.x / self.cell_size.x).floor() as i32,
+                (position.y / self.cell_size.y).floor() as i32,
+            ),
+        }
+    }
+
+    fn wrap_cell(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        match self.wrap {
+            Some((columns, rows)) => (x.rem_euclid(columns), y.rem_euclid(rows)),
+            None => (x, y),
+        }
+    }
+
+    pub(crate) fn neighbors(&self, position: Point2) -> impl Iterator<Item = usize> + '_ {
+        let (x, y) = self.cell_of(position);
+        let mut cells: Vec<(i32, i32)> = (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| self.wrap_cell((x + dx, y + dy))))
+            .collect();
+
+        // small periodic grids wrap onto the same cell more than once
+        cells.sort_unstable();
+        cells.dedup();
+
+        cells
+            .into_iter()
+            .filter_map(move |cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+    }
+}
+
+/// The Barnes-Hut quadtree, the exact counterpart of `Quadtree` with the same node layout.
+pub(crate) struct QuadtreeMap {
+    depth: u32,
+    species_count: usize,
+    /// Per node the bounds followed by one sum per species, see `quadtree.glsl`.
+    nodes: Vec<Vec4>,
+    /// Live particle indices of every leaf.
+    leaves: Vec<Vec<usize>>,
+}
+
+impl QuadtreeMap {
+    fn new(state: &ParticleState, u: &Uniforms, species_count: u32) -> Self {
+        let depth = u.quadtree_depth;
+        let species_count = species_count as usize;
+        let stride = species_count + 1;
+        let mut tree = Self {
+            depth,
+            species_count,
+            nodes: vec![Vec4::ZERO; quadtree_node_count(depth) as usize * stride],
+            leaves: vec![vec![]; quadtree_leaf_count(depth) as usize],
+        };
+
+        for (index, position) in state.positions.iter().enumerate() {
+            if state.alive[index] {
+                tree.leaves[quadtree_leaf(*position, u)].push(index);
+            }
+        }
+
+        let empty_bounds = vec4(1e30, 1e30, -1e30, -1e30);
+        for leaf in 0..tree.leaves.len() {
+            let node = tree.node(depth, leaf);
+            let mut bounds = empty_bounds;
+            for &i in &tree.leaves[leaf] {
+                let p = state.positions[i];
+                bounds = vec4(
+                    bounds.x.min(p.x),
+                    bounds.y.min(p.y),
+                    bounds.z.max(p.x),
+                    bounds.w.max(p.y),
+                );
+                let charge = state.attributes[i].charge;
+                tree.nodes[node + 1 + state.species[i] as usize] += vec4(p.x, p.y, 1.0, charge);
+            }
+            tree.nodes[node] = bounds;
+        }
+
+        // every level from the one below, like `quadtree_reduce.comp`
+        for level in (0..depth).rev() {
+            let side = 1 << level;
+            for index in 0..side * side {
+                let node = tree.node(level, index);
+                let (x, y) = (index % side, index / side);
+                let mut bounds = empty_bounds;
+                for dy in 0..2 {
+                    for dx in 0..2 {
+                        let child = tree.node(level + 1, (2 * y + dy) * 2 * side + 2 * x + dx);
+                        let c = tree.nodes[child];
+                        bounds = vec4(
+                            bounds.x.min(c.x),
+                            bounds.y.min(c.y),
+                            bounds.z.max(c.z),
+                            bounds.w.max(c.w),
+                        );
+                        for s in 1..stride {
+                            let sum = tree.nodes[child + s];
+                            tree.nodes[node + s] += sum;
+                        }
+                    }
+                }
+                tree.nodes[node] = bounds;
+            }
+        }
+
+        tree
+    }
+
+    /// Offset of the node at `index` on `level`, like `quadtree_node`.
+    fn node(&self, level: u32, index: usize) -> usize {
+        let level_offset = (quadtree_leaf_count(level) as usize - 1) / 3;
+        (level_offset + index) * (self.species_count + 1)
+    }
+
+    fn sums(&self, node: usize) -> &[Vec4] {
+        &self.nodes[node + 1..node + 1 + self.species_count]
+    }
+}
+
+/// Leaf containing `position`, particles outside the domain go to the nearest leaf on the edge.
+fn quadtree_leaf(position: Point2, u: &Uniforms) -> usize {
+    let side = 1 << u.quadtree_depth;
+    let cell = ((position / vec2(u.width, u.height) + 0.5) * side as f32).floor();
+    let x = (cell.x as i32).clamp(0, side - 1);
+    let y = (cell.y as i32).clamp(0, side - 1);
+    (y * side + x) as usize
+}
+
+/// Cells are large enough that every interaction lies within the 3x3 neighborhood.
+fn grid_cell_size(u: &Uniforms) -> f32 {
+    u.attraction_range
+        .max(u.repulsion_range)
+        .max(u.particle_radius * u.max_radius_scale * 2.0)
+}
+
+/// The `MAX_BONDS` bond slots of particle `index`.
+fn bond_slots(state: &ParticleState, index: usize) -> &[Bond] {
+    let first_slot = index * MAX_BONDS as usize;
+    &state.bonds[first_slot..first_slot + MAX_BONDS as usize]
+}
+
+fn bond_count(state: &ParticleState, index: usize) -> usize {
+    bond_slots(state, index)
+        .iter()
+        .filter(|bond| !bond.is_none())
+        .count()
+}
+
+/// Nearest live particle within the capture distance that both have a free slot for,
+/// ties go to the lower index. Port of `nearest_candidate` in `bonds.comp`.
+fn nearest_candidate(state: &ParticleState, grid: &CellMap, index: usize, u: &Uniforms) -> u32 {
+    if !state.alive[index] || bond_count(state, index) >= MAX_BONDS as usize {
+        return NO_BOND;
+    }
+
+    let position = state.positions[index];
+    // the grid only reaches one cell away
+    let capture_distance = u.bond_capture_distance.min(grid_cell_size(u));
+    let mut best = NO_BOND;
+    let mut best_dist = capture_distance;
+
+    for i in grid.neighbors(position) {
+        let bonded = bond_slots(state, index)
+            .iter()
+            .any(|bond| bond.partner == i as u32);
+        if i == index || bonded || bond_count(state, i) >= MAX_BONDS as usize {
+            continue;
+        }
+
+        let dist = minimum_image(state.positions[i] - position, u).length();
+        let nearer = dist < best_dist || (dist == best_dist && (i as u32) < best);
+        if dist < capture_distance && nearer {
+            best = i as u32;
+            best_dist = dist;
+        }
+    }
+
+    best
+}
+
+/// Breaks bonds to dead particles and bonds stretched beyond the break strain, then bonds with
+/// a candidate that picked this particle back for long enough. Port of `STAGE_BONDS`.
+fn update_particle_bonds(
+    state: &ParticleState,
+    candidates: &[BondCandidate],
+    index: usize,
+    u: &Uniforms,
+) -> Vec<Bond> {
+    if !state.alive[index] {
+        return vec![Bond::NONE; MAX_BONDS as usize];
+    }
+
+    let position = state.positions[index];
+    let mut bonds: Vec<Bond> = bond_slots(state, index)
+        .iter()
+        .map(|bond| {
+            if bond.is_none() {
+                return *bond;
+            }
+
+            let partner = bond.partner as usize;
+            let dist = minimum_image(state.positions[partner] - position, u).length();
+            if !state.alive[partner] || dist > bond.rest_length * (1.0 + u.bond_break_strain) {
+                Bond::NONE
+            } else {
+                *bond
+            }
+        })
+        .collect();
+
+    let candidate = candidates[index];
+    if candidate.partner == NO_BOND || candidate.time < u.bond_time {
+        return bonds;
+    }
+    let other = candidates[candidate.partner as usize];
+    if other.partner != index as u32 || other.time < u.bond_time {
+        return bonds;
+    }
+
+    let partner_position = state.positions[candidate.partner as usize];
+    let rest_length = minimum_image(partner_position - position, u).length();
+    if let Some(slot) = bonds.iter_mut().find(|bond| bond.is_none()) {
+        *slot = Bond::new(candidate.partner, rest_length);
+    }
+
+    bonds
+}
+
+/// Everything one invocation of `update.comp` can see.
+struct Particle<'a> {
+    index: usize,
+    state: &'a ParticleState,
+    species: &'a SpeciesConfig,
+    environment: &'a EnvironmentConfig,
+    grid: Option<&'a CellMap>,
+    tree: Option<&'a QuadtreeMap>,
+    uniforms: &'a Uniforms,
+}
+
+impl<'a> Particle<'a> {
+    /// Runs one stage, returning the new position, velocity and integrator state.
+    fn update(&self, stage: UpdateStage, mut scratch: Vec4) -> (Point2, Point2, Vec4) {
+        let u = self.uniforms;
+        let mut position = self.state.positions[self.index];
+        let mut velocity = self.state.velocities[self.index];
+
+        // length of one substep
+        let h = u.dt / u.substeps.max(1) as f32;
+
+        match stage {
+            UpdateStage::Euler => {
+                // semi-implicit euler: kick, then drift with the new velocity
+                let (acceleration, impulse) = self.forces(position, velocity);
+                velocity =
+                    self.clamp_velocity(self.damp(velocity + impulse * h, h) + acceleration * h);
+                position += velocity * h;
+                self.apply_boundary(&mut position, &mut velocity);
+                self.apply_obstacles(&mut position, &mut velocity);
+            }
+            UpdateStage::VerletDrift => {
+                // velocity verlet, first half: drift using the acceleration of the last step
+                let last_acceleration = vec2(scratch.x, scratch.y);
+                position += velocity * h + 0.5 * last_acceleration * h * h;
+                if self.apply_boundary(&mut position, &mut velocity) {
+                    scratch = Vec4::ZERO;
+                }
+                self.apply_obstacles(&mut position, &mut velocity);
+            }
+            UpdateStage::VerletKick => {
+                // velocity verlet, second half: kick with the average of the old and new acceleration
+                let last_acceleration = vec2(scratch.x, scratch.y);
+                let (acceleration, impulse) = self.forces(position, velocity);
+                velocity = self.damp(velocity + impulse * h, h)
+                    + 0.5 * (last_acceleration + acceleration) * h;
+                velocity = self.clamp_velocity(velocity);
+                scratch = vec4(acceleration.x, acceleration.y, 0.0, 0.0);
+            }
+            UpdateStage::Rk2Half => {
+                // midpoint method, first half: save the start state and step to the midpoint
+                scratch = vec4(position.x, position.y, velocity.x, velocity.y);
+                let (acceleration, impulse) = self.forces(position, velocity);
+                let half_h = h * 0.5;
+                position += velocity * half_h;
+                velocity = self.clamp_velocity(
+                    self.damp(velocity + impulse * half_h, half_h) + acceleration * half_h,
+                );
+            }
+            UpdateStage::Rk2Full => {
+                // midpoint method, second half: full step from the start using the midpoint derivatives
+                let (acceleration, impulse) = self.forces(position, velocity);
+                position = vec2(scratch.x, scratch.y) + velocity * h;
+                velocity = self.clamp_velocity(
+                    self.damp(vec2(scratch.z, scratch.w) + impulse * h, h) + acceleration * h,
+                );
+                self.apply_boundary(&mut position, &mut velocity);
+                self.apply_obstacles(&mut position, &mut velocity);
+            }
+            UpdateStage::Thermostat => {
+                let mass = self.state.attributes[self.index].mass;
+                velocity = self.clamp_velocity(thermostat_velocity(self.index, velocity, mass, u));
+            }
+        }
+
+        (position, velocity, scratch)
+    }
+
+    /// Returns the clamped acceleration at `position` and `velocity`
+    /// and the collision impulse per unit time.
+    fn forces(&self, position: Point2, velocity: Point2) -> (Point2, Point2) {
+        let u = self.uniforms;
+        let index = self.index;
+        let state = self.state;
+        let mut force = vec2(0.0, 0.0);
+        let mut impulse = vec2(0.0, 0.0);
+
+        if let Some(tree) = self.tree {
+            self.barnes_hut(tree, position, &mut force, &mut impulse);
+        } else {
+            let mut visit = |i: usize| {
+                if i != index && state.alive[i] {
+                    self.interact(i, position, &mut force, &mut impulse);
+                }
+            };
+
+            match self.grid {
+                Some(grid) => grid.neighbors(position).for_each(&mut visit),
+                None => (0..state.len()).for_each(&mut visit),
+            }
+        }
+
+        // springs along bonds, damped by the relative velocity along them
+        for bond in bond_slots(state, index) {
+            let partner = bond.partner as usize;
+            if bond.is_none() || !state.alive[partner] {
+                continue;
+            }
+
+            let diff = minimum_image(state.positions[partner] - position, u);
+            let dist = diff.length();
+            if dist > 0.0 {
+                let dir = diff / dist;
+                let relative_speed = (state.velocities[partner] - velocity).dot(dir);
+                let stretch = dist - bond.rest_length;
+                force += dir * (u.bond_stiffness * stretch + u.bond_damping * relative_speed);
+            }
+        }
+
+        // heavier particles accelerate less
+        let mut acceleration = force / state.attributes[index].mass;
+
+        // attract to center
+        acceleration += position * -u.center_strength;
+
+        // external fields push every particle alike
+        for field in &self.environment.fields {
+            acceleration += field.acceleration(position, u.time, u.seed);
+        }
+
+        // clamp acceleration for stability
+        if u.max_acceleration > 0.0 {
+            let mag = acceleration.length().min(u.max_acceleration);
+            acceleration = acceleration / acceleration.length() * mag;
+        }
+
+        // the cursor is applied after the clamp, so grabbed particles keep up with it
+        acceleration += mouse_acceleration(position, velocity, u);
+
+        (acceleration, impulse)
+    }
+
+    /// Barnes-Hut approximation of the unlimited range forces, visiting the nodes in the
+    /// same order as `barnes_hut` in `update.comp`.
+    fn barnes_hut(
+        &self,
+        tree: &QuadtreeMap,
+        position: Point2,
+        force: &mut Point2,
+        impulse: &mut Point2,
+    ) {
+        let u = self.uniforms;
+        let own_species = self.state.species[self.index];
+        let own_charge = self.state.attributes[self.index].charge;
+        let near = grid_cell_size(u);
+
+        let mut stack = vec![(0, 0)];
+        while let Some((level, local_index)) = stack.pop() {
+            let node = tree.node(level, local_index);
+            let total = tree.sums(node).iter().fold(Vec4::ZERO, |sum, s| sum + *s);
+            if total.z == 0.0 {
+                continue;
+            }
+
+            let bounds = tree.nodes[node];
+            let half_extent = (vec2(bounds.z, bounds.w) - vec2(bounds.x, bounds.y)) * 0.5;
+            let center = (vec2(bounds.x, bounds.y) + vec2(bounds.z, bounds.w)) * 0.5;
+            let to_center = minimum_image(center - position, u);
+            let box_distance = (to_center.abs() - half_extent).max(Vec2::ZERO).length();
+            let size = 2.0 * half_extent.x.max(half_extent.y);
+            let center_distance =
+                minimum_image(vec2(total.x, total.y) / total.z - position, u).length();
+
+            if box_distance > near && size < u.barnes_hut_theta * center_distance {
+                for (other_species, sums) in tree.sums(node).iter().enumerate() {
+                    if sums.z == 0.0 {
+                        continue;
+                    }
+
+                    let diff = minimum_image(vec2(sums.x, sums.y) / sums.z - position, u);
+                    let dist = diff.length();
+                    if dist > 0.0 {
+                        let rule = self.species.rule(own_species, other_species as u32);
+                        let charge_product = own_charge * sums.w;
+                        *force += diff / dist
+                            * (sums.z * kernel_force(dist, rule, u)
+                                + charge_force(dist, charge_product, u));
+                    }
+                }
+            } else if level == tree.depth {
+                for &i in &tree.leaves[local_index] {
+                    if i != self.index {
+                        self.interact(i, position, force, impulse);
+                    }
+                }
+            } else {
+                let side = 1 << level;
+                let (x, y) = (local_index % side, local_index / side);
+                for dy in 0..2 {
+                    for dx in 0..2 {
+                        stack.push((level + 1, (2 * y + dy) * 2 * side + 2 * x + dx));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Accumulates the force and collision response of particle `other` on this particle.
+    fn interact(&self, other: usize, position: Point2, force: &mut Point2, impulse: &mut Point2) {
+        let u = self.uniforms;
+        let state = self.state;
+        let own_attributes = &state.attributes[self.index];
+        let other_attributes = &state.attributes[other];
+        let diff = minimum_image(state.positions[other] - position, u);
+        let dist = diff.length();
+        let radius2 = radius_of(own_attributes, u) + radius_of(other_attributes, u);
+
+        // determine force to apply
+        if dist > 0.0 {
+            let dir = diff / dist;
+
+            // rules scale the global parameters for this pair of species
+            let rule = self
+                .species
+                .rule(state.species[self.index], state.species[other]);
+            let charge_product = own_attributes.charge * other_attributes.charge;
+            *force += dir * (kernel_force(dist, rule, u) + charge_force(dist, charge_product, u));
+
+            // detect collisions, the lighter particle takes more of the response
+            if dist < radius2 {
+                let mv = diff * (-(radius2 - dist));
+                let share =
+                    2.0 * other_attributes.mass / (own_attributes.mass + other_attributes.mass);
+                *impulse += mv * u.collision_response * share;
+            }
+        }
+    }
+
+    /// momentum is the fraction of velocity kept per unit of time
+    fn damp(&self, velocity: Point2, h: f32) -> Point2 {
+        velocity * self.uniforms.momentum.powf(h)
+    }
+
+    fn clamp_velocity(&self, velocity: Point2) -> Point2 {
+        let max_velocity = self.uniforms.max_velocity;
+        if max_velocity > 0.0 {
+            let mag = velocity.length().min(max_velocity);
+            velocity / velocity.length() * mag
+        } else {
+            velocity
+        }
+    }
+
+    /// Keeps the particle inside the domain according to `boundary_mode`, returns true on respawn.
+    fn apply_boundary(&self, position: &mut Point2, velocity: &mut Point2) -> bool {
+        let u = self.uniforms;
+        let domain = vec2(u.width, u.height);
+        let half_domain = domain * 0.5;
+
+        match BoundaryMode::from_uint(u.boundary_mode) {
+            BoundaryMode::Open => {}
+            BoundaryMode::Wrap => *position = wrap_position(*position, domain),
+            BoundaryMode::Reflect => {
+                // mirror about the wall and bounce back, losing speed according to restitution
+                for axis in 0..2 {
+                    if position[axis] < -half_domain[axis] {
+                        position[axis] = -2.0 * half_domain[axis] - position[axis];
+                        velocity[axis] = velocity[axis].abs() * u.restitution;
+                    } else if position[axis] > half_domain[axis] {
+                        position[axis] = 2.0 * half_domain[axis] - position[axis];
+                        velocity[axis] = -velocity[axis].abs() * u.restitution;
+                    }
+                }
+
+                // a particle faster than the whole domain can still end up outside
+                *position = position.clamp(-half_domain, half_domain);
+            }
+            BoundaryMode::Respawn => {
+                if position.x.abs() > half_domain.x || position.y.abs() > half_domain.y {
+                    let index = self.index as u32;
+                    let x = random::random(u.seed, index, u.frame, 0);
+                    let y = random::random(u.seed, index, u.frame, 1);
+                    *position = (vec2(x, y) - 0.5) * domain;
+                    *velocity = vec2(0.0, 0.0);
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Pushes the particle out of every obstacle it overlaps,
+    /// bouncing and slowing it according to the obstacle's restitution and friction.
+    fn apply_obstacles(&self, position: &mut Point2, velocity: &mut Point2) {
+        let radius = radius_of(&self.state.attributes[self.index], self.uniforms);
+
+        for obstacle in &self.environment.obstacles {
+            let (distance, normal) = obstacle.distance(*position);
+            let depth = radius - distance;
+            if depth <= 0.0 {
+                continue;
+            }
+
+            *position += normal * depth;
+
+            // only velocities heading into the obstacle bounce
+            let normal_speed = velocity.dot(normal);
+            if normal_speed < 0.0 {
+                let tangent = *velocity - normal * normal_speed;
+                *velocity = tangent * (1.0 - obstacle.friction)
+                    - normal * normal_speed * obstacle.restitution;
+            }
+        }
+    }
+}
+
+/// Radius of a particle, at most `max_radius_scale` times `particle_radius`.
+fn radius_of(attributes: &ParticleAttributes, u: &Uniforms) -> f32 {
+    u.particle_radius * attributes.radius
+}
+
+/// Coulomb force between two particles whose charges multiply to `charge_product`,
+/// like charges repel. Fades out at the repulsion range like the other kernels.
+fn charge_force(dist: f32, charge_product: f32, u: &Uniforms) -> f32 {
+    -u.charge_strength * charge_product / (dist * dist) * cutoff(dist, u.repulsion_range, 1.0, u)
+}
+
+/// Fades a force out over the last `kernel_smoothing` fraction of its range.
+/// A global range of 0 is unlimited.
+fn cutoff(dist: f32, global_range: f32, scale: f32, u: &Uniforms) -> f32 {
+    if global_range == 0.0 {
+        return 1.0;
+    }
+
+    let range = global_range * scale;
+    let fade = range * u.kernel_smoothing;
+    if fade <= 0.0 {
+        return if dist <= range { 1.0 } else { 0.0 };
+    }
+
+    1.0 - smoothstep(range - fade, range, dist)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Bell curve with a width of `gaussian_width` times the range,
+/// see `ForceKernel::needs_finite_ranges` for a range of 0.
+fn gaussian(dist: f32, range: f32, u: &Uniforms) -> f32 {
+    let width = u.gaussian_width * range;
+    if width <= 0.0 {
+        return 0.0;
+    }
+
+    let x = dist / width;
+    (-0.5 * x * x).exp()
+}
+
+/// Signed magnitude of the force between two particles at `dist`, positive attracts.
+fn kernel_force(dist: f32, rule: &SpeciesRule, u: &Uniforms) -> f32 {
+    let attraction = u.attraction_strength * rule.attraction_strength;
+    let repulsion = u.repulsion_strength * rule.repulsion_strength;
+    let attraction_range = u.attraction_range * rule.attraction_range;
+    let repulsion_range = u.repulsion_range * rule.repulsion_range;
+    let attraction_cutoff = cutoff(dist, u.attraction_range, rule.attraction_range, u);
+    let repulsion_cutoff = cutoff(dist, u.repulsion_range, rule.repulsion_range, u);
+
+    match ForceKernel::from_uint(u.force_kernel) {
+        ForceKernel::InverseSquare => {
+            let r2 = dist * dist;
+            attraction / r2 * attraction_cutoff - repulsion / r2 * repulsion_cutoff
+        }
+        ForceKernel::Gaussian => {
+            attraction * gaussian(dist, attraction_range, u) * attraction_cutoff
+                - repulsion * gaussian(dist, repulsion_range, u) * repulsion_cutoff
+        }
+        ForceKernel::LennardJones => {
+            // clamped below half of sigma, where the repulsion explodes,
+            // the r^-6 tail is scaled like attraction and the r^-12 core like repulsion
+            let r = dist.max(0.5 * u.lennard_jones_sigma);
+            let s = u.lennard_jones_sigma / r;
+            let s6 = s * s * s * s * s * s;
+            let tail = 24.0 * u.lennard_jones_epsilon * s6 / r;
+            let core = 48.0 * u.lennard_jones_epsilon * s6 * s6 / r;
+            tail * rule.attraction_strength * attraction_cutoff
+                - core * rule.repulsion_strength * repulsion_cutoff
+        }
+        ForceKernel::LinearRamp => {
+            // particle life: linear repulsion up to ramp_beta of the attraction range,
+            // then a triangular attraction falling back to zero at the range
+            if attraction_range <= 0.0 {
+                return 0.0;
+            }
+
+            let r = dist / attraction_range;
+            let beta = u.ramp_beta;
+            if r < beta {
+                repulsion * (r / beta - 1.0)
+            } else if r < 1.0 {
+                attraction * (1.0 - (2.0 * r - 1.0 - beta).abs() / (1.0 - beta))
+            } else {
+                0.0
+            }
+        }
+        ForceKernel::Morse => {
+            // the single exponential is scaled like attraction, the squared one like repulsion
+            let e = (-u.morse_width * (dist - u.morse_equilibrium)).exp();
+            let magnitude = 2.0 * u.morse_depth * u.morse_width;
+            magnitude * e * rule.attraction_strength * attraction_cutoff
+                - magnitude * e * e * rule.repulsion_strength * repulsion_cutoff
+        }
+    }
+}
+
+/// Acceleration toward or away from the cursor within `mouse_radius`,
+/// or the spring holding grabbed particles to it.
+fn mouse_acceleration(position: Point2, velocity: Point2, u: &Uniforms) -> Point2 {
+    let diff = minimum_image(vec2(u.mouse_x, u.mouse_y) - position, u);
+    let dist = diff.length();
+    let mode = MouseMode::from_uint(u.mouse_mode);
+    if mode == MouseMode::Off || dist >= u.mouse_radius {
+        return vec2(0.0, 0.0);
+    }
+
+    if mode == MouseMode::Grab {
+        // critically damped spring toward the cursor, moving along with it
+        let mouse_velocity = vec2(u.mouse_velocity_x, u.mouse_velocity_y);
+        return diff * u.mouse_strength
+            + (mouse_velocity - velocity) * 2.0 * u.mouse_strength.sqrt();
+    }
+
+    if dist == 0.0 {
+        return vec2(0.0, 0.0);
+    }
+
+    // strongest at the cursor, fading out linearly to the edge of the radius
+    let mut pull = u.mouse_strength * (1.0 - dist / u.mouse_radius);
+    if mode == MouseMode::Repel {
+        pull = -pull;
+    }
+
+    diff / dist * pull
+}
+
+/// Velocity after one frame of thermostat, port of `thermostat_velocity` in `thermostat.glsl`.
+fn thermostat_velocity(index: usize, velocity: Point2, mass: f32, u: &Uniforms) -> Point2 {
+    match Thermostat::from_uint(u.thermostat) {
+        Thermostat::Langevin => {
+            // exact Ornstein-Uhlenbeck step over the frame: friction, and random kicks
+            // that balance it at the target temperature
+            let decay = (-u.langevin_friction * u.dt).exp();
+            let spread = ((1.0 - decay * decay) * u.target_temperature / mass).sqrt();
+            let kick = random::random_normal(u.seed, index as u32, u.frame, 8);
+            velocity * decay + kick * spread
+        }
+        Thermostat::Berendsen => velocity * u.velocity_scale,
+        Thermostat::Off => velocity,
+    }
+}
+
+/// Shortest vector between two points, across the edges of a periodic domain.
+pub(crate) fn minimum_image(diff: Point2, u: &Uniforms) -> Point2 {
+    if BoundaryMode::from_uint(u.boundary_mode) == BoundaryMode::Wrap {
+        let domain = vec2(u.width, u.height);
+        diff - domain * (diff / domain).round()
+    } else {
+        diff
+    }
+}
+
+fn wrap_position(position: Point2, domain: Point2) -> Point2 {
+    let offset = position + domain * 0.5;
+    offset - domain * (offset / domain).floor() - domain * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Particles at rest at `positions`, followed by one dead slot.
+    fn state(positions: &[Point2]) -> ParticleState {
+        let count = positions.len();
+        ParticleState {
+            positions: positions.to_vec(),
+            velocities: vec![vec2(0.0, 0.0); count],
+            species: vec![0; count],
+            attributes: vec![ParticleAttributes::default(); count],
+            alive: vec![true; count],
+            bonds: vec![Bond::NONE; count * MAX_BONDS as usize],
+        }
+        .with_capacity(count as u32 + 1)
+    }
+
+    fn backend(state: ParticleState, population: PopulationConfig) -> CpuBackend {
+        CpuBackend::new(
+            state,
+            SpeciesConfig::default(),
+            population,
+            EnvironmentConfig::new(),
+        )
+    }
+
+    fn uniforms() -> Uniforms {
+        Uniforms {
+            center_strength: 0.0,
+            ..Uniforms::new(3, 200.0, 160.0)
+        }
+    }
+
+    #[test]
+    fn pairs_push_each_other_apart_evenly() {
+        let mut state = state(&[pt2(-10.0, 5.0), pt2(10.0, 5.0)]);
+        state.positions[2] = pt2(50.0, 50.0);
+        state.velocities[2] = vec2(1.0, 1.0);
+        let mut cpu = backend(state.clone(), PopulationConfig::new(2));
+
+        cpu.step(&uniforms());
+        let stepped = cpu.state();
+
+        // repulsion is stronger than attraction at the default strengths
+        let (left, right) = (stepped.positions[0], stepped.positions[1]);
+        assert!(left.x < -10.0 && right.x > 10.0);
+        assert!((left.x + right.x).abs() < 1e-5);
+        assert_eq!((left.y, right.y), (5.0, 5.0));
+        let momentum = stepped.velocities[0] + stepped.velocities[1];
+        assert!(momentum.length() < 1e-6);
+
+        // dead slots keep their state
+        assert_eq!(stepped.positions[2], state.positions[2]);
+        assert_eq!(stepped.velocities[2], state.velocities[2]);
+        assert!(!stepped.alive[2]);
+    }
+
+    #[test]
+    fn wraps_across_the_edges() {
+        let mut state = state(&[pt2(99.0, 0.0)]);
+        state.velocities[0] = vec2(3.0, 0.0);
+        let mut cpu = backend(state, PopulationConfig::new(1));
+
+        cpu.step(&Uniforms {
+            boundary_mode: BoundaryMode::Wrap as u32,
+            ..uniforms()
+        });
+
+        let position = cpu.state().positions[0];
+        assert!((position.x - (99.0 + 3.0 * 0.97 - 200.0)).abs() < 1e-4);
+        assert_eq!(position.y, 0.0);
+    }
+
+    #[test]
+    fn sinks_remove_and_emitters_fill_dead_slots() {
+        let population = PopulationConfig::new(2)
+            .with_emitters(vec![Emitter {
+                center: pt2(-60.0, 0.0),
+                radius: 1.0,
+                rate: 1000.0,
+                velocity_spread: 0.0,
+                species: 1,
+                ..Default::default()
+            }])
+            .with_sinks(vec![Sink::new(pt2(60.0, 0.0), 5.0)]);
+        let mut cpu = CpuBackend::new(
+            state(&[pt2(60.0, 0.0), pt2(0.0, 40.0)]),
+            SpeciesConfig {
+                count: 2,
+                rules: vec![SpeciesRule::default(); 4],
+                ratios: vec![1.0, 1.0],
+            },
+            population,
+            EnvironmentConfig::new(),
+        );
+
+        cpu.step(&uniforms());
+        let stepped = cpu.state();
+
+        assert_eq!(stepped.alive, vec![false, true, true]);
+        assert_eq!(stepped.species[2], 1);
+        assert!(stepped.positions[2].distance(pt2(-60.0, 0.0)) < 1.0 + 5.0);
+    }
+
+    #[test]
+    fn barnes_hut_with_a_small_theta_matches_the_direct_sum() {
+        let positions: Vec<Point2> = (0..24)
+            .map(|i| {
+                let angle = i as f32 * 2.4;
+                pt2(angle.cos(), angle.sin()) * (4.0 + i as f32 * 3.0)
+            })
+            .collect();
+        let unlimited = Uniforms {
+            attraction_range: 0.0,
+            ..uniforms()
+        };
+        let mut direct = backend(state(&positions), PopulationConfig::new(24));
+        let mut tree = backend(state(&positions), PopulationConfig::new(24));
+
+        for _ in 0..4 {
+            direct.step(&unlimited);
+            tree.step(&Uniforms {
+                barnes_hut_theta: 0.01,
+                ..unlimited
+            });
+        }
+
+        for (d, t) in direct.state().positions.iter().zip(tree.state().positions) {
+            assert!(d.distance(t) < 1e-3, "direct {:?} tree {:?}", d, t);
+        }
+    }
+
+    #[test]
+    fn finds_neighbors_across_periodic_edges() {
+        let state = state(&[pt2(-99.0, 0.0), pt2(99.0, 0.0), pt2(0.0, 0.0)]);
+        let wrap = Uniforms {
+            boundary_mode: BoundaryMode::Wrap as u32,
+            ..uniforms()
+        };
+
+        let wrapped = CellMap::with_cell_size(&state, &wrap, 20.0);
+        let mut neighbors: Vec<usize> = wrapped.neighbors(pt2(99.0, 0.0)).collect();
+        neighbors.sort_unstable();
+        assert_eq!(neighbors, vec![0, 1]);
+
+        let open = CellMap::with_cell_size(&state, &uniforms(), 20.0);
+        assert_eq!(open.neighbors(pt2(99.0, 0.0)).collect::<Vec<_>>(), vec![1]);
+
+        assert_eq!(minimum_image(vec2(190.0, -150.0), &wrap), vec2(-10.0, 10.0));
+        assert_eq!(
+            minimum_image(vec2(190.0, -150.0), &uniforms()),
+            vec2(190.0, -150.0)
+        );
+    }
+
+    #[test]
+    fn cuts_forces_off_at_their_range() {
+        let u = uniforms();
+        assert_eq!(cutoff(1000.0, 0.0, 1.0, &u), 1.0);
+        assert_eq!(cutoff(20.0, 20.0, 1.0, &u), 1.0);
+        assert_eq!(cutoff(20.5, 20.0, 1.0, &u), 0.0);
+
+        let smooth = Uniforms {
+            kernel_smoothing: 0.5,
+            ..u
+        };
+        assert_eq!(cutoff(10.0, 20.0, 1.0, &smooth), 1.0);
+        assert!((cutoff(15.0, 20.0, 1.0, &smooth) - 0.5).abs() < 1e-6);
+        assert_eq!(cutoff(20.0, 20.0, 1.0, &smooth), 0.0);
+    }
+}
@@ -0,0 +1,395 @@
+use futures::FutureExt;
+use nannou::prelude::*;
+use nannou::wgpu::CommandEncoder;
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::Path;
+use std::pin::Pin;
+
+use crate::compute::*;
+use crate::particles::ParticleState;
+use crate::uniforms::*;
+use crate::util::*;
+
+/// Invocations of the single `diagnostics.comp` workgroup, must match `DIAGNOSTICS_WORKGROUP_SIZE`.
+pub const DIAGNOSTICS_WORKGROUP_SIZE: u32 = 256;
+
+/// Column names of the rows written by `Diagnostics::write_csv`.
+pub const CSV_HEADER: &str =
+    "frame,time,live_count,kinetic_energy,momentum_x,momentum_y,mean_speed,max_speed,centroid_x,centroid_y,temperature";
+
+/// Sums over the live particles. Laid out as the `DiagnosticSums` struct of `diagnostics.comp`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DiagnosticSums {
+    pub kinetic_energy: f32,
+    pub mass: f32,
+    pub speed: f32,
+    pub max_speed: f32,
+    pub momentum: Point2,
+    pub position: Point2,
+    pub count: u32,
+    padding: u32,
+}
+
+impl DiagnosticSums {
+    /// The sums `diagnostics.comp` computes, on the CPU.
+    pub fn from_state(state: &ParticleState) -> Self {
+        let mut sums = Self::default();
+        for index in 0..state.len() {
+            if !state.alive[index] {
+                continue;
+            }
+
+            let mass = state.attributes[index].mass;
+            let velocity = state.velocities[index];
+            let speed = velocity.length();
+            sums.kinetic_energy += 0.5 * mass * speed * speed;
+            sums.mass += mass;
+            sums.speed += speed;
+            sums.max_speed = sums.max_speed.max(speed);
+            sums.momentum += velocity * mass;
+            sums.position += state.positions[index];
+            sums.count += 1;
+        }
+
+        sums
+    }
+}
+
+/// Whole system measures of one frame, to tell settled parameter sets from exploding ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Diagnostics {
+    pub frame: u32,
+    pub time: f32,
+    pub live_count: u32,
+    pub kinetic_energy: f32,
+    /// Net momentum, which only forces from outside the system change.
+    pub momentum: Point2,
+    pub mean_speed: f32,
+    pub max_speed: f32,
+    /// Mean position of the live particles.
+    pub centroid: Point2,
+    /// Kinetic energy per particle after removing the motion of the center of mass,
+    /// with unit Boltzmann constant and two degrees of freedom per particle.
+    pub temperature: f32,
+}
+
+impl Diagnostics {
+    pub fn from_sums(sums: &DiagnosticSums, frame: u32, time: f32) -> Self {
+        let count = sums.count.max(1) as f32;
+        let bulk_energy = if sums.mass > 0.0 {
+            0.5 * sums.momentum.length_squared() / sums.mass
+        } else {
+            0.0
+        };
+
+        Diagnostics {
+            frame,
+            time,
+            live_count: sums.count,
+            kinetic_energy: sums.kinetic_energy,
+            momentum: sums.momentum,
+            mean_speed: sums.speed / count,
+            max_speed: sums.max_speed,
+            centroid: sums.position / count,
+            temperature: (sums.kinetic_energy - bulk_energy).max(0.0) / count,
+        }
+    }
+
+    /// Factor a Berendsen thermostat scales velocities by to bring this reading's temperature
+    /// toward `target` with a `coupling_time`, when velocities were last scaled `elapsed` ago.
+    pub fn berendsen_scale(&self, target: f32, coupling_time: f32, elapsed: f32) -> f32 {
+        if self.temperature <= 0.0 {
+            return 1.0;
+        }
+
+        // never overshoot, a coupling time shorter than the interval closes the gap at once
+        let rate = if coupling_time > elapsed {
+            elapsed / coupling_time
+        } else {
+            1.0
+        };
+        (1.0 + rate * (target / self.temperature - 1.0))
+            .max(0.0)
+            .sqrt()
+    }
+
+    /// A few lines for the HUD.
+    pub fn summary(&self) -> String {
+        format!(
+            "kinetic energy: {:.2}\nmomentum: ({:.2}, {:.2})\nspeed: {:.3} mean, {:.3} max\ncentroid: ({:.1}, {:.1})\ntemperature: {:.4}",
+            self.kinetic_energy,
+            self.momentum.x,
+            self.momentum.y,
+            self.mean_speed,
+            self.max_speed,
+            self.centroid.x,
+            self.centroid.y,
+            self.temperature
+        )
+    }
+
+    /// Writes one `CSV_HEADER` row.
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            self.frame,
+            self.time,
+            self.live_count,
+            self.kinetic_energy,
+            self.momentum.x,
+            self.momentum.y,
+            self.mean_speed,
+            self.max_speed,
+            self.centroid.x,
+            self.centroid.y,
+            self.temperature
+        )
+    }
+}
+
+type Mapping = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>;
+
+/// Where the last reduction is on its way back to the CPU.
+enum Readback {
+    Idle,
+    /// Encoded, waiting for the commands to be submitted.
+    Encoded {
+        frame: u32,
+        time: f32,
+    },
+    Mapping {
+        frame: u32,
+        time: f32,
+        mapping: Mapping,
+    },
+}
+
+/// Reduces the particle state to `DiagnosticSums` on the GPU and reads them back
+/// without blocking, see `diagnostics.comp`.
+/// One reduction is in flight at a time, `encode` skips frames until it has been read.
+pub struct DiagnosticsReduction {
+    compute: Compute,
+    sum_buffer: wgpu::Buffer,
+    read_buffer: wgpu::Buffer,
+    readback: Readback,
+}
+
+impl DiagnosticsReduction {
+    /// `state_buffer_sets` holds, for each ping-pong position, the position, velocity,
+    /// alive and attribute buffers.
+    pub fn new(
+        shaders_dir: &Path,
+        device: &wgpu::Device,
+        uniforms: &UniformBuffer,
+        state_buffer_sets: &[Vec<&wgpu::Buffer>],
+        state_buffer_sizes: &[u64],
+    ) -> Self {
+        let sum_buffer_size = std::mem::size_of::<DiagnosticSums>() as u64;
+        let sum_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("diagnostics-buffer"),
+            size: sum_buffer_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let read_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("diagnostics-read-buffer"),
+            size: sum_buffer_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let buffer_sets: Vec<Vec<&wgpu::Buffer>> = state_buffer_sets
+            .iter()
+            .map(|buffers| {
+                let mut buffers = buffers.clone();
+                buffers.push(&sum_buffer);
+                buffers
+            })
+            .collect();
+        let mut buffer_sizes = state_buffer_sizes.to_vec();
+        buffer_sizes.push(sum_buffer_size);
+
+        let cs_mod = compile_shader_in(
+            shaders_dir,
+            device,
+            "diagnostics.comp",
+            shaderc::ShaderKind::Compute,
+        );
+        let compute = Compute::with_buffer_sets::<Uniforms>(
+            device,
+            buffer_sets,
+            Some(buffer_sizes),
+            Some(&uniforms.buffer),
+            &cs_mod,
+        )
+        .unwrap()
+        .with_workgroup_size(DIAGNOSTICS_WORKGROUP_SIZE);
+
+        Self {
+            compute,
+            sum_buffer,
+            read_buffer,
+            readback: Readback::Idle,
+        }
+    }
+
+    /// Encodes a reduction of the given ping-pong buffers, labeled with `frame` and `time`.
+    /// Returns false without encoding anything while the previous one is still being read.
+    pub fn encode(
+        &mut self,
+        encoder: &mut CommandEncoder,
+        buffers: usize,
+        frame: u32,
+        time: f32,
+    ) -> bool {
+        if !matches!(self.readback, Readback::Idle) {
+            return false;
+        }
+
+        // a single workgroup covers every slot
+        self.compute
+            .compute_with(encoder, buffers, DIAGNOSTICS_WORKGROUP_SIZE);
+        let size = std::mem::size_of::<DiagnosticSums>() as u64;
+        encoder.copy_buffer_to_buffer(&self.sum_buffer, 0, &self.read_buffer, 0, size);
+        self.readback = Readback::Encoded { frame, time };
+        true
+    }
+
+    /// Starts reading back the encoded reduction, call once its commands are submitted.
+    pub fn submitted(&mut self) {
+        if let Readback::Encoded { frame, time } = self.readback {
+            let mapping = self.read_buffer.slice(..).map_async(wgpu::MapMode::Read);
+            self.readback = Readback::Mapping {
+                frame,
+                time,
+                mapping: Box::pin(mapping),
+            };
+        }
+    }
+
+    /// The diagnostics of the reduction being read, once they have arrived.
+    /// Never blocks, call every frame to keep the readback moving.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<Diagnostics> {
+        let (frame, time, result) = match &mut self.readback {
+            Readback::Mapping {
+                frame,
+                time,
+                mapping,
+            } => {
+                device.poll(wgpu::Maintain::Poll);
+                (*frame, *time, mapping.as_mut().now_or_never()?)
+            }
+            _ => return None,
+        };
+        result.expect("failed to map the diagnostics buffer");
+
+        let sums = {
+            let bytes = self.read_buffer.slice(..).get_mapped_range();
+            byte_vec_as_sums(&bytes)
+        };
+        self.read_buffer.unmap();
+        self.readback = Readback::Idle;
+
+        Some(Diagnostics::from_sums(&sums, frame, time))
+    }
+}
+
+pub fn byte_vec_as_sums(bytes: &[u8]) -> DiagnosticSums {
+    let float = |i: usize| f32::from_ne_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+    DiagnosticSums {
+        kinetic_energy: float(0),
+        mass: float(4),
+        speed: float(8),
+        max_speed: float(12),
+        momentum: vec2(float(16), float(20)),
+        position: vec2(float(24), float(28)),
+        count: u32::from_ne_bytes([bytes[32], bytes[33], bytes[34], bytes[35]]),
+        padding: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bonds::{Bond, MAX_BONDS};
+    use crate::particles::ParticleAttributes;
+
+    #[test]
+    fn sums_over_live_particles() {
+        let state = ParticleState {
+            positions: vec![pt2(0.0, 0.0), pt2(4.0, 2.0), pt2(100.0, 100.0)],
+            velocities: vec![vec2(3.0, 4.0), vec2(-1.0, 0.0), vec2(50.0, 0.0)],
+            species: vec![0; 3],
+            attributes: vec![
+                ParticleAttributes::new(2.0, 1.0, 0.0),
+                ParticleAttributes::new(1.0, 1.0, 0.0),
+                ParticleAttributes::new(1.0, 1.0, 0.0),
+            ],
+            alive: vec![true, true, false],
+            bonds: vec![Bond::NONE; 3 * MAX_BONDS as usize],
+        };
+
+        let sums = DiagnosticSums::from_state(&state);
+        assert_eq!(sums.count, 2);
+        assert_eq!(sums.mass, 3.0);
+        assert_eq!(sums.kinetic_energy, 0.5 * 2.0 * 25.0 + 0.5 * 1.0);
+        assert_eq!(sums.speed, 6.0);
+        assert_eq!(sums.max_speed, 5.0);
+        assert_eq!(sums.momentum, vec2(5.0, 8.0));
+        assert_eq!(sums.position, vec2(4.0, 2.0));
+
+        let diagnostics = Diagnostics::from_sums(&sums, 3, 1.5);
+        assert_eq!(diagnostics.mean_speed, 3.0);
+        assert_eq!(diagnostics.centroid, vec2(2.0, 1.0));
+        // the bulk motion of the center of mass is not heat
+        let bulk = 0.5 * (25.0 + 64.0) / 3.0;
+        assert!((diagnostics.temperature - (25.5 - bulk) / 2.0).abs() < 1e-5);
+    }
+
+    fn reading(temperature: f32) -> Diagnostics {
+        Diagnostics {
+            temperature,
+            ..Diagnostics::from_sums(&DiagnosticSums::default(), 0, 0.0)
+        }
+    }
+
+    #[test]
+    fn keeps_velocities_at_the_target() {
+        assert_eq!(reading(0.2).berendsen_scale(0.2, 50.0, 10.0), 1.0);
+    }
+
+    #[test]
+    fn scales_part_of_the_way_to_the_target() {
+        // a fifth of the way from 0.2 to 0.1 in temperature, which goes with the squared speed
+        let scale = reading(0.2).berendsen_scale(0.1, 50.0, 10.0);
+        assert!((scale * scale - 0.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn closes_the_gap_at_once_without_overshooting() {
+        for coupling_time in [10.0, 5.0, 0.0] {
+            let scale = reading(0.4).berendsen_scale(0.1, coupling_time, 10.0);
+            assert!((scale - 0.5).abs() < 1e-6, "{}", scale);
+        }
+    }
+
+    #[test]
+    fn leaves_a_system_at_rest_alone() {
+        assert_eq!(reading(0.0).berendsen_scale(0.1, 50.0, 10.0), 1.0);
+        assert_eq!(reading(0.0).berendsen_scale(0.1, 1.0, 10.0), 1.0);
+    }
+
+    #[test]
+    fn sums_nothing_without_live_particles() {
+        let sums = DiagnosticSums::from_state(&ParticleState::default());
+        assert_eq!(sums, DiagnosticSums::default());
+
+        let diagnostics = Diagnostics::from_sums(&sums, 0, 0.0);
+        assert_eq!(diagnostics.live_count, 0);
+        assert_eq!(diagnostics.temperature, 0.0);
+        assert_eq!(diagnostics.centroid, vec2(0.0, 0.0));
+    }
+}
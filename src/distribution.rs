@@ -0,0 +1,606 @@
+use nannou::image;
+use nannou::prelude::*;
+use rand::Rng;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Where particles start out, every shape is centered on the origin.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InitialDistribution {
+    /// Uniformly random angle and distance from the center, so denser toward the center.
+    Disk { radius: f32 },
+    /// Like the disk, with the distance from the center starting at `inner_radius`.
+    Annulus {
+        inner_radius: f32,
+        outer_radius: f32,
+    },
+    /// A square or hexagonal lattice, each point moved by up to `jitter` times the spacing.
+    Lattice {
+        spacing: f32,
+        jitter: f32,
+        hexagonal: bool,
+    },
+    /// Normally distributed blobs with a standard deviation of `spread`,
+    /// centered at random in a disk of `radius`.
+    GaussianClusters {
+        clusters: u32,
+        spread: f32,
+        radius: f32,
+    },
+    /// Random points in a disk, no two closer than `min_distance`.
+    PoissonDisc { radius: f32, min_distance: f32 },
+    /// Random points with a density following the brightness of a grayscale image,
+    /// `scale` world units per pixel. The image must have some bright pixels.
+    Image { path: PathBuf, scale: f32 },
+}
+
+#[derive(Debug)]
+pub enum DistributionError {
+    UnknownDistribution(String),
+    UnknownParameter(String),
+    InvalidValue(String),
+    MissingParameter(&'static str),
+    /// The image could not be opened, or has no bright pixels to sample from.
+    InvalidImage(String),
+    /// The poisson disc only had room for `placed` of the `count` particles.
+    PoissonDiscFull {
+        count: u32,
+        placed: u32,
+    },
+}
+
+impl Default for InitialDistribution {
+    fn default() -> Self {
+        InitialDistribution::Disk { radius: 200.0 }
+    }
+}
+
+impl InitialDistribution {
+    /// Default parameters of the named distribution, see `from_str` for the names.
+    pub fn preset(name: &str) -> Result<Self, DistributionError> {
+        match name {
+            "disk" => Ok(InitialDistribution::default()),
+            "annulus" => Ok(InitialDistribution::Annulus {
+                inner_radius: 100.0,
+                outer_radius: 200.0,
+            }),
+            "lattice" => Ok(InitialDistribution::Lattice {
+                spacing: 8.0,
+                jitter: 0.2,
+                hexagonal: false,
+            }),
+            "clusters" => Ok(InitialDistribution::GaussianClusters {
+                clusters: 6,
+                spread: 20.0,
+                radius: 300.0,
+            }),
+            "poisson" => Ok(InitialDistribution::PoissonDisc {
+                radius: 250.0,
+                min_distance: 6.0,
+            }),
+            "image" => Ok(InitialDistribution::Image {
+                path: PathBuf::new(),
+                scale: 1.0,
+            }),
+            _ => Err(DistributionError::UnknownDistribution(name.to_string())),
+        }
+    }
+
+    /// Samples `count` positions.
+    pub fn sample<R: Rng>(
+        &self,
+        count: u32,
+        rng: &mut R,
+    ) -> Result<Vec<Point2>, DistributionError> {
+        let points = match self {
+            InitialDistribution::Disk { radius } => (0..count)
+                .map(|_| random_in_annulus(0.0, *radius, rng))
+                .collect(),
+            InitialDistribution::Annulus {
+                inner_radius,
+                outer_radius,
+            } => (0..count)
+                .map(|_| random_in_annulus(*inner_radius, *outer_radius, rng))
+                .collect(),
+            InitialDistribution::Lattice {
+                spacing,
+                jitter,
+                hexagonal,
+            } => lattice(count, *spacing, *jitter, *hexagonal, rng),
+            InitialDistribution::GaussianClusters {
+                clusters,
+                spread,
+                radius,
+            } => {
+                let centers: Vec<Point2> = (0..(*clusters).max(1))
+                    .map(|_| random_in_annulus(0.0, *radius, rng))
+                    .collect();
+                (0..count)
+                    .map(|_| {
+                        let center = centers[rng.gen_range(0, centers.len())];
+                        center + random_normal(rng) * *spread
+                    })
+                    .collect()
+            }
+            InitialDistribution::PoissonDisc {
+                radius,
+                min_distance,
+            } => poisson_disc(count, *radius, *min_distance, rng)?,
+            InitialDistribution::Image { path, scale } => {
+                sample_image(&open_image(path)?, count, *scale, rng)
+            }
+        };
+
+        Ok(points)
+    }
+
+    /// Overrides one parameter by name.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), DistributionError> {
+        match (self, key) {
+            (InitialDistribution::Disk { radius }, "radius")
+            | (InitialDistribution::GaussianClusters { radius, .. }, "radius")
+            | (InitialDistribution::PoissonDisc { radius, .. }, "radius") => {
+                *radius = parse(value)?
+            }
+            (InitialDistribution::Annulus { inner_radius, .. }, "inner_radius") => {
+                *inner_radius = parse(value)?
+            }
+            (InitialDistribution::Annulus { outer_radius, .. }, "outer_radius") => {
+                *outer_radius = parse(value)?
+            }
+            (InitialDistribution::Lattice { spacing, .. }, "spacing") => *spacing = parse(value)?,
+            (InitialDistribution::Lattice { jitter, .. }, "jitter") => *jitter = parse(value)?,
+            (InitialDistribution::Lattice { hexagonal, .. }, "hexagonal") => {
+                *hexagonal = parse(value)?
+            }
+            (InitialDistribution::GaussianClusters { clusters, .. }, "clusters") => {
+                *clusters = parse(value)?
+            }
+            (InitialDistribution::GaussianClusters { spread, .. }, "spread") => {
+                *spread = parse(value)?
+            }
+            (InitialDistribution::PoissonDisc { min_distance, .. }, "min_distance") => {
+                *min_distance = parse(value)?
+            }
+            (InitialDistribution::Image { path, .. }, "path") => *path = PathBuf::from(value),
+            (InitialDistribution::Image { scale, .. }, "scale") => *scale = parse(value)?,
+            _ => return Err(DistributionError::UnknownParameter(key.to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// Checks the parameters `sample` relies on: positive, finite sizes and an annulus
+    /// whose inner radius is below the outer one.
+    fn validate(&self) -> Result<(), DistributionError> {
+        match self {
+            InitialDistribution::Disk { radius } => positive("radius", *radius),
+            InitialDistribution::Annulus {
+                inner_radius,
+                outer_radius,
+            } => {
+                positive("outer_radius", *outer_radius)?;
+                if !(*inner_radius >= 0.0 && inner_radius < outer_radius) {
+                    return Err(DistributionError::InvalidValue(format!(
+                        "inner_radius={} must be at least 0 and below outer_radius={}",
+                        inner_radius, outer_radius
+                    )));
+                }
+                Ok(())
+            }
+            InitialDistribution::Lattice {
+                spacing, jitter, ..
+            } => {
+                positive("spacing", *spacing)?;
+                non_negative("jitter", *jitter)
+            }
+            InitialDistribution::GaussianClusters { spread, radius, .. } => {
+                positive("radius", *radius)?;
+                non_negative("spread", *spread)
+            }
+            InitialDistribution::PoissonDisc {
+                radius,
+                min_distance,
+            } => {
+                positive("radius", *radius)?;
+                positive("min_distance", *min_distance)
+            }
+            InitialDistribution::Image { scale, .. } => positive("scale", *scale),
+        }
+    }
+}
+
+/// Parses a distribution from `name` or `name:key=value,key=value`, starting from the preset
+/// of that name, for example `lattice:spacing=12,hexagonal=true` or `image:path=matter.png`.
+impl FromStr for InitialDistribution {
+    type Err = DistributionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let mut distribution = Self::preset(parts.next().unwrap())?;
+
+        for parameter in parts.next().unwrap_or("").split(',') {
+            if parameter.is_empty() {
+                continue;
+            }
+
+            let mut key_value = parameter.splitn(2, '=');
+            let key = key_value.next().unwrap();
+            let value = key_value
+                .next()
+                .ok_or_else(|| DistributionError::InvalidValue(parameter.to_string()))?;
+            distribution.set(key, value)?;
+        }
+
+        distribution.validate()?;
+
+        // the image is opened once up front, so a bad path is reported with the arguments
+        if let InitialDistribution::Image { path, .. } = &distribution {
+            if path.as_os_str().is_empty() {
+                return Err(DistributionError::MissingParameter("path"));
+            }
+            open_image(path)?;
+        }
+
+        Ok(distribution)
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, DistributionError> {
+    value
+        .parse()
+        .map_err(|_| DistributionError::InvalidValue(value.to_string()))
+}
+
+fn positive(key: &str, value: f32) -> Result<(), DistributionError> {
+    if value > 0.0 && value.is_finite() {
+        Ok(())
+    } else {
+        Err(DistributionError::InvalidValue(format!(
+            "{}={} must be positive",
+            key, value
+        )))
+    }
+}
+
+fn non_negative(key: &str, value: f32) -> Result<(), DistributionError> {
+    if value >= 0.0 && value.is_finite() {
+        Ok(())
+    } else {
+        Err(DistributionError::InvalidValue(format!(
+            "{}={} must be at least 0",
+            key, value
+        )))
+    }
+}
+
+/// Opens the image at `path` as grayscale, it must have some bright pixels to sample from.
+fn open_image(path: &Path) -> Result<image::GrayImage, DistributionError> {
+    let image = image::open(path)
+        .map_err(|e| DistributionError::InvalidImage(format!("{}: {}", path.display(), e)))?
+        .to_luma8();
+    if !image.pixels().any(|p| p[0] > 0) {
+        return Err(DistributionError::InvalidImage(format!(
+            "{}: no bright pixels to sample from",
+            path.display()
+        )));
+    }
+
+    Ok(image)
+}
+
+/// Uniformly random angle and distance from the center in a ring, a disk when `inner_radius` is 0.
+fn random_in_annulus<R: Rng>(inner_radius: f32, outer_radius: f32, rng: &mut R) -> Point2 {
+    let angle = rng.gen_range(-PI, PI);
+    let radius = rng.gen_range(inner_radius, outer_radius);
+    vec2(angle.cos(), angle.sin()) * radius
+}
+
+/// Standard normal distributed point, using the Box-Muller transform.
+fn random_normal<R: Rng>(rng: &mut R) -> Point2 {
+    let u = 1.0 - rng.gen::<f32>();
+    let angle = rng.gen_range(-PI, PI);
+    vec2(angle.cos(), angle.sin()) * (-2.0 * u.ln()).sqrt()
+}
+
+/// The `count` lattice points closest to the origin.
+fn lattice<R: Rng>(
+    count: u32,
+    spacing: f32,
+    jitter: f32,
+    hexagonal: bool,
+    rng: &mut R,
+) -> Vec<Point2> {
+    let row_height = if hexagonal {
+        spacing * 3.0f32.sqrt() * 0.5
+    } else {
+        spacing
+    };
+
+    // a square of points large enough to hold a disk of count points
+    let side = (count as f32 * 4.0 / PI).sqrt().ceil() as i32 + 1;
+    let mut points: Vec<Point2> = (-side..=side)
+        .flat_map(|row| {
+            let offset = if hexagonal && row % 2 != 0 {
+                spacing * 0.5
+            } else {
+                0.0
+            };
+            (-side..=side)
+                .map(move |column| vec2(column as f32 * spacing + offset, row as f32 * row_height))
+        })
+        .collect();
+    points.sort_by(|a, b| a.length_squared().partial_cmp(&b.length_squared()).unwrap());
+    points.truncate(count as usize);
+
+    points
+        .into_iter()
+        .map(|point| {
+            let offset = vec2(rng.gen_range(-0.5, 0.5), rng.gen_range(-0.5, 0.5));
+            point + offset * jitter * spacing
+        })
+        .collect()
+}
+
+/// Bridson's algorithm in a disk, stopping once `count` points are placed.
+/// Fails when the disk fills up first.
+fn poisson_disc<R: Rng>(
+    count: u32,
+    radius: f32,
+    min_distance: f32,
+    rng: &mut R,
+) -> Result<Vec<Point2>, DistributionError> {
+    if count == 0 {
+        return Ok(vec![]);
+    }
+
+    // every background cell holds at most one point
+    let cell_size = min_distance / 2.0f32.sqrt();
+    let cell_of = |p: Point2| {
+        (
+            (p.x / cell_size).floor() as i32,
+            (p.y / cell_size).floor() as i32,
+        )
+    };
+
+    let first = random_in_annulus(0.0, radius, rng);
+    let mut points = vec![first];
+    let mut active = vec![0];
+    let mut cells = HashMap::new();
+    cells.insert(cell_of(first), 0);
+
+    const ATTEMPTS: u32 = 30;
+    while points.len() < count as usize && !active.is_empty() {
+        let active_index = rng.gen_range(0, active.len());
+        let origin = points[active[active_index]];
+
+        let candidate = (0..ATTEMPTS)
+            .map(|_| origin + random_in_annulus(min_distance, 2.0 * min_distance, rng))
+            .find(|candidate| {
+                if candidate.length() > radius {
+                    return false;
+                }
+
+                let (x, y) = cell_of(*candidate);
+                (-2..=2).all(|dy| {
+                    (-2..=2).all(|dx| match cells.get(&(x + dx, y + dy)) {
+                        Some(i) => points[*i].distance(*candidate) >= min_distance,
+                        None => true,
+                    })
+                })
+            });
+
+        match candidate {
+            Some(candidate) => {
+                cells.insert(cell_of(candidate), points.len());
+                active.push(points.len());
+                points.push(candidate);
+            }
+            None => {
+                active.swap_remove(active_index);
+            }
+        }
+    }
+
+    if points.len() < count as usize {
+        return Err(DistributionError::PoissonDiscFull {
+            count,
+            placed: points.len() as u32,
+        });
+    }
+
+    Ok(points)
+}
+
+/// Rejection samples pixels by brightness, spreading particles uniformly within each pixel.
+/// The image must have some bright pixels, see `open_image`.
+fn sample_image<R: Rng>(
+    image: &image::GrayImage,
+    count: u32,
+    scale: f32,
+    rng: &mut R,
+) -> Vec<Point2> {
+    let (width, height) = image.dimensions();
+
+    let size = vec2(width as f32, height as f32);
+    let mut points = vec![];
+    while points.len() < count as usize {
+        let x = rng.gen_range(0, width);
+        let y = rng.gen_range(0, height);
+        if rng.gen_range(0, 255) < image.get_pixel(x, y)[0] {
+            let pixel = vec2(x as f32 + rng.gen::<f32>(), y as f32 + rng.gen::<f32>());
+            // images go down from the top left, the world goes up from the center
+            let point = (pixel - size * 0.5) * vec2(1.0, -1.0) * scale;
+            points.push(point);
+        }
+    }
+
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn parses_distributions() {
+        assert_eq!(
+            "disk".parse::<InitialDistribution>().unwrap(),
+            InitialDistribution::default()
+        );
+        assert_eq!(
+            "lattice:spacing=12,hexagonal=true"
+                .parse::<InitialDistribution>()
+                .unwrap(),
+            InitialDistribution::Lattice {
+                spacing: 12.0,
+                jitter: 0.2,
+                hexagonal: true
+            }
+        );
+        assert_eq!(
+            "annulus:inner_radius=10,outer_radius=20,"
+                .parse::<InitialDistribution>()
+                .unwrap(),
+            InitialDistribution::Annulus {
+                inner_radius: 10.0,
+                outer_radius: 20.0
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_distributions() {
+        assert!(matches!(
+            "spiral".parse::<InitialDistribution>(),
+            Err(DistributionError::UnknownDistribution(_))
+        ));
+        assert!(matches!(
+            "disk:spacing=4".parse::<InitialDistribution>(),
+            Err(DistributionError::UnknownParameter(_))
+        ));
+        assert!(matches!(
+            "disk:radius=wide".parse::<InitialDistribution>(),
+            Err(DistributionError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            "disk:radius".parse::<InitialDistribution>(),
+            Err(DistributionError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            "image".parse::<InitialDistribution>(),
+            Err(DistributionError::MissingParameter("path"))
+        ));
+        assert!(matches!(
+            "image:path=/nonexistent/missing.png".parse::<InitialDistribution>(),
+            Err(DistributionError::InvalidImage(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_sizes_sampling_cannot_use() {
+        for s in &[
+            "disk:radius=0",
+            "disk:radius=inf",
+            "clusters:radius=0",
+            "clusters:spread=-1",
+            "annulus:inner_radius=300",
+            "annulus:inner_radius=-1",
+            "annulus:outer_radius=NaN",
+            "lattice:spacing=NaN",
+            "lattice:spacing=-8",
+            "lattice:jitter=NaN",
+            "poisson:min_distance=0",
+            "poisson:radius=-5",
+            "image:path=matter.png,scale=0",
+        ] {
+            assert!(
+                matches!(
+                    s.parse::<InitialDistribution>(),
+                    Err(DistributionError::InvalidValue(_))
+                ),
+                "{}",
+                s
+            );
+        }
+
+        // sizes of 0 that sampling handles
+        assert!("annulus:inner_radius=0"
+            .parse::<InitialDistribution>()
+            .is_ok());
+        assert!("clusters:spread=0,clusters=0"
+            .parse::<InitialDistribution>()
+            .is_ok());
+        assert!("lattice:jitter=0".parse::<InitialDistribution>().is_ok());
+    }
+
+    #[test]
+    fn samples_no_points() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for name in &["disk", "annulus", "lattice", "clusters", "poisson"] {
+            let distribution = InitialDistribution::preset(name).unwrap();
+            assert!(distribution.sample(0, &mut rng).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn samples_images() {
+        let dark = std::env::temp_dir().join("generative-matter-dark.png");
+        image::GrayImage::new(4, 4).save(&dark).unwrap();
+        assert!(matches!(
+            format!("image:path={}", dark.display()).parse::<InitialDistribution>(),
+            Err(DistributionError::InvalidImage(_))
+        ));
+
+        // a single bright pixel in the top left corner
+        let dot = std::env::temp_dir().join("generative-matter-dot.png");
+        let mut image = image::GrayImage::new(4, 4);
+        image.put_pixel(0, 0, image::Luma([255]));
+        image.save(&dot).unwrap();
+        let distribution: InitialDistribution = format!("image:path={},scale=2", dot.display())
+            .parse()
+            .unwrap();
+
+        let points = distribution
+            .sample(20, &mut StdRng::seed_from_u64(1))
+            .unwrap();
+        assert_eq!(points.len(), 20);
+        assert!(points
+            .iter()
+            .all(|p| (-4.0..=-2.0).contains(&p.x) && (2.0..=4.0).contains(&p.y)));
+    }
+
+    #[test]
+    fn samples_count_points() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for name in &["disk", "annulus", "lattice", "clusters", "poisson"] {
+            let distribution = InitialDistribution::preset(name).unwrap();
+            assert_eq!(distribution.sample(100, &mut rng).unwrap().len(), 100);
+        }
+
+        let poisson = InitialDistribution::PoissonDisc {
+            radius: 100.0,
+            min_distance: 10.0,
+        };
+        let points = poisson.sample(40, &mut rng).unwrap();
+        for (i, a) in points.iter().enumerate() {
+            assert!(a.length() <= 100.0);
+            assert!(points[i + 1..].iter().all(|b| a.distance(*b) >= 10.0));
+        }
+    }
+
+    #[test]
+    fn rejects_an_overfull_poisson_disc() {
+        let poisson = InitialDistribution::PoissonDisc {
+            radius: 10.0,
+            min_distance: 10.0,
+        };
+        assert!(matches!(
+            poisson.sample(100, &mut StdRng::seed_from_u64(1)),
+            Err(DistributionError::PoissonDiscFull { count: 100, .. })
+        ));
+    }
+}
@@ -0,0 +1,27 @@
+use crate::fields::*;
+use crate::obstacles::*;
+
+/// The static world the particles move through: obstacles to collide with
+/// and force fields pushing them around.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnvironmentConfig {
+    pub obstacles: Vec<Obstacle>,
+    pub fields: Vec<ForceField>,
+}
+
+impl EnvironmentConfig {
+    /// An empty environment.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_obstacles(mut self, obstacles: Vec<Obstacle>) -> Self {
+        self.obstacles = obstacles;
+        self
+    }
+
+    pub fn with_fields(mut self, fields: Vec<ForceField>) -> Self {
+        self.fields = fields;
+        self
+    }
+}
@@ -0,0 +1,227 @@
+use nannou::prelude::*;
+
+use crate::random::hash;
+
+/// Kind of a `ForceField`, must match the `FIELD_*` constants of `fields.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    /// Divergence free flow along the curl of time-varying value noise.
+    CurlNoise = 0,
+    /// Swirl around `center`, counterclockwise for positive strengths.
+    Vortex = 1,
+    /// A constant pull along `direction`.
+    Gravity = 2,
+    /// Pushes away from `center` for positive strengths, pulls in for negative ones.
+    Radial = 3,
+}
+
+impl FieldKind {
+    pub fn from_uint(value: u32) -> Self {
+        match value {
+            1 => FieldKind::Vortex,
+            2 => FieldKind::Gravity,
+            3 => FieldKind::Radial,
+            _ => FieldKind::CurlNoise,
+        }
+    }
+}
+
+/// An external acceleration applied to every particle regardless of its mass.
+/// Laid out as the `ForceField` struct of `fields.glsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ForceField {
+    /// A `FieldKind` as uint.
+    pub kind: u32,
+    pub strength: f32,
+    /// Distance from `center` at which the field is down to half strength, 0 to never fade.
+    pub falloff: f32,
+    /// World units per cell of curl noise.
+    pub scale: f32,
+    /// Noise cells per unit of time the curl noise evolves by.
+    pub speed: f32,
+    padding: f32,
+    pub center: Point2,
+    /// Unit direction of gravity.
+    pub direction: Point2,
+}
+
+impl ForceField {
+    pub fn curl_noise(strength: f32, scale: f32, speed: f32) -> Self {
+        ForceField {
+            scale,
+            speed,
+            ..Self::new(FieldKind::CurlNoise, strength)
+        }
+    }
+
+    pub fn vortex(center: Point2, strength: f32) -> Self {
+        Self::new(FieldKind::Vortex, strength).with_center(center)
+    }
+
+    pub fn gravity(direction: Point2, strength: f32) -> Self {
+        ForceField {
+            direction: direction.normalize(),
+            ..Self::new(FieldKind::Gravity, strength)
+        }
+    }
+
+    /// A source for positive strengths, a sink for negative ones.
+    pub fn radial(center: Point2, strength: f32) -> Self {
+        Self::new(FieldKind::Radial, strength).with_center(center)
+    }
+
+    fn new(kind: FieldKind, strength: f32) -> Self {
+        ForceField {
+            kind: kind as u32,
+            strength,
+            falloff: 0.0,
+            scale: 100.0,
+            speed: 0.0,
+            padding: 0.0,
+            center: pt2(0.0, 0.0),
+            direction: vec2(0.0, -1.0),
+        }
+    }
+
+    /// Point the falloff is measured from, also the center of vortices and radial fields.
+    pub fn with_center(mut self, center: Point2) -> Self {
+        self.center = center;
+        self
+    }
+
+    pub fn with_falloff(mut self, falloff: f32) -> Self {
+        self.falloff = falloff;
+        self
+    }
+
+    /// Acceleration at `position` at simulated `time`, with the noise varying by `seed`.
+    /// Mirrors `field_acceleration` of `fields.glsl`.
+    pub fn acceleration(&self, position: Point2, time: f32, seed: u32) -> Point2 {
+        let diff = position - self.center;
+        let dist = diff.length();
+        let fade = if self.falloff > 0.0 {
+            let x = dist / self.falloff;
+            1.0 / (1.0 + x * x)
+        } else {
+            1.0
+        };
+
+        let direction = match FieldKind::from_uint(self.kind) {
+            FieldKind::CurlNoise => {
+                let p = vec3(
+                    position.x / self.scale,
+                    position.y / self.scale,
+                    time * self.speed,
+                );
+                let gradient = value_noise_gradient(p, seed);
+                vec2(gradient.y, -gradient.x)
+            }
+            FieldKind::Vortex if dist > 0.0 => vec2(-diff.y, diff.x) / dist,
+            FieldKind::Gravity => self.direction,
+            FieldKind::Radial if dist > 0.0 => diff / dist,
+            _ => vec2(0.0, 0.0),
+        };
+
+        direction * self.strength * fade
+    }
+}
+
+/// Pseudo random value in [-1, 1) of a noise lattice point.
+fn lattice_value(x: i32, y: i32, z: i32, seed: u32) -> f32 {
+    let h = hash(hash(hash(x as u32 ^ seed) ^ y as u32) ^ z as u32);
+    (h >> 8) as f32 / 8388608.0 - 1.0
+}
+
+/// Gradient in x and y of 3D value noise with a quintic interpolant.
+fn value_noise_gradient(p: Vec3, seed: u32) -> Point2 {
+    let cell = p.floor();
+    let w = p - cell;
+    let u = w * w * w * (w * (w * 6.0 - 15.0) + 10.0);
+    let du = 30.0 * w * w * (w * (w - 2.0) + 1.0);
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+
+    let a = lattice_value(x, y, z, seed);
+    let b = lattice_value(x + 1, y, z, seed);
+    let c = lattice_value(x, y + 1, z, seed);
+    let d = lattice_value(x + 1, y + 1, z, seed);
+    let e = lattice_value(x, y, z + 1, seed);
+    let f = lattice_value(x + 1, y, z + 1, seed);
+    let g = lattice_value(x, y + 1, z + 1, seed);
+    let h = lattice_value(x + 1, y + 1, z + 1, seed);
+
+    let k1 = b - a;
+    let k2 = c - a;
+    let k4 = a - b - c + d;
+    let k5 = a - c - e + g;
+    let k6 = a - b - e + f;
+    let k7 = -a + b + c - d + e - f - g + h;
+
+    vec2(
+        du.x * (k1 + k4 * u.y + k6 * u.z + k7 * u.y * u.z),
+        du.y * (k2 + k5 * u.z + k4 * u.x + k7 * u.z * u.x),
+    )
+}
+
+/// See the `nannou::wgpu::bytes` documentation for why this is necessary.
+pub fn fields_as_bytes(data: &[ForceField]) -> &[u8] {
+    unsafe { wgpu::bytes::from_slice(data) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: Point2, expected: Point2) {
+        assert!(
+            actual.distance(expected) < 1e-5,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn points_along_the_field_kind() {
+        let vortex = ForceField::vortex(pt2(10.0, 0.0), 2.0);
+        assert_near(vortex.acceleration(pt2(15.0, 0.0), 0.0, 0), vec2(0.0, 2.0));
+        assert_near(vortex.acceleration(pt2(10.0, 0.0), 0.0, 0), vec2(0.0, 0.0));
+
+        let gravity = ForceField::gravity(vec2(3.0, 4.0), 0.5);
+        assert_near(
+            gravity.acceleration(pt2(-80.0, 7.0), 0.0, 0),
+            vec2(0.3, 0.4),
+        );
+
+        let sink = ForceField::radial(pt2(0.0, 0.0), -1.0);
+        assert_near(sink.acceleration(pt2(0.0, -5.0), 0.0, 0), vec2(0.0, 1.0));
+        assert_near(sink.acceleration(pt2(0.0, 0.0), 0.0, 0), vec2(0.0, 0.0));
+    }
+
+    #[test]
+    fn fades_to_half_strength_at_the_falloff() {
+        let radial = ForceField::radial(pt2(0.0, 0.0), 1.0).with_falloff(20.0);
+        assert_near(radial.acceleration(pt2(20.0, 0.0), 0.0, 0), vec2(0.5, 0.0));
+        assert_near(radial.acceleration(pt2(0.0, 40.0), 0.0, 0), vec2(0.0, 0.2));
+    }
+
+    #[test]
+    fn curl_noise_is_divergence_free() {
+        let field = ForceField::curl_noise(1.0, 10.0, 0.1);
+        let h = 1e-2;
+        for (i, seed) in [0, 1, 42].iter().enumerate() {
+            let p = pt2(3.7 + i as f32 * 11.3, -5.1 + i as f32 * 7.9);
+            let a = |p: Point2| field.acceleration(p, 2.5, *seed);
+            let divergence = (a(p + vec2(h, 0.0)).x - a(p - vec2(h, 0.0)).x
+                + a(p + vec2(0.0, h)).y
+                - a(p - vec2(0.0, h)).y)
+                / (2.0 * h);
+            assert!(divergence.abs() < 1e-2, "divergence {}", divergence);
+            assert!(a(p).length() > 0.0);
+        }
+
+        // and varies with the seed
+        let p = pt2(3.7, -5.1);
+        assert_ne!(field.acceleration(p, 0.0, 0), field.acceleration(p, 0.0, 1));
+    }
+}
@@ -0,0 +1,272 @@
+use nannou::prelude::*;
+use nannou::wgpu::CommandEncoder;
+use std::path::Path;
+
+use crate::compute::*;
+use crate::quadtree::quadtree_leaf_count;
+use crate::uniforms::*;
+use crate::util::*;
+
+/// Invocations per workgroup in the grid shaders, must match `GRID_WORKGROUP_SIZE` in `grid.glsl`.
+pub const GRID_WORKGROUP_SIZE: u32 = 256;
+
+/// The two level prefix sum can handle one block sum per invocation of a single workgroup.
+pub const MAX_GRID_CELLS: u32 = GRID_WORKGROUP_SIZE * GRID_WORKGROUP_SIZE;
+
+/// Picks a hash table size of roughly one cell per particle.
+pub fn default_grid_cell_count(particle_capacity: u32) -> u32 {
+    particle_capacity
+        .next_power_of_two()
+        .clamp(GRID_WORKGROUP_SIZE, MAX_GRID_CELLS)
+}
+
+/// A uniform grid over a spatial hash, rebuilt every frame with a counting sort:
+/// particles are binned by cell, the cell counts are prefix summed,
+/// and the particle indices are scattered into cell order.
+/// Dead particles are left out of the grid.
+pub struct NeighborGrid {
+    pub cell_end_buffer: wgpu::Buffer,
+    pub sorted_index_buffer: wgpu::Buffer,
+    pub cell_buffer_size: u64,
+    pub index_buffer_size: u64,
+    count: Compute,
+    scan_blocks: Compute,
+    scan_sums: Compute,
+    scan_add: Compute,
+    sort: Compute,
+    particle_capacity: u32,
+    cell_count: u32,
+}
+
+impl NeighborGrid {
+    pub fn new(
+        shaders_dir: &Path,
+        device: &wgpu::Device,
+        uniforms: &UniformBuffer,
+        position_buffers: &[wgpu::Buffer; 2],
+        position_buffer_size: u64,
+        alive_buffer: &wgpu::Buffer,
+        alive_buffer_size: u64,
+    ) -> Self {
+        Self::with_bins(
+            shaders_dir,
+            device,
+            uniforms,
+            position_buffers,
+            position_buffer_size,
+            alive_buffer,
+            alive_buffer_size,
+            uniforms.data.grid_cell_count,
+            &[],
+        )
+    }
+
+    /// Bins particles by the leaf of the `Quadtree` containing them instead of by hashed cell,
+    /// so the particles of every leaf are listed contiguously.
+    pub fn quadtree_leaves(
+        shaders_dir: &Path,
+        device: &wgpu::Device,
+        uniforms: &UniformBuffer,
+        position_buffers: &[wgpu::Buffer; 2],
+        position_buffer_size: u64,
+        alive_buffer: &wgpu::Buffer,
+        alive_buffer_size: u64,
+    ) -> Self {
+        Self::with_bins(
+            shaders_dir,
+            device,
+            uniforms,
+            position_buffers,
+            position_buffer_size,
+            alive_buffer,
+            alive_buffer_size,
+            quadtree_leaf_count(uniforms.data.quadtree_depth),
+            &["QUADTREE_LEAVES"],
+        )
+    }
+
+    /// A counting sort into `cell_count` bins, with `defines` picking the binning of `grid.glsl`.
+    #[allow(clippy::too_many_arguments)]
+    fn with_bins(
+        shaders_dir: &Path,
+        device: &wgpu::Device,
+        uniforms: &UniformBuffer,
+        position_buffers: &[wgpu::Buffer; 2],
+        position_buffer_size: u64,
+        alive_buffer: &wgpu::Buffer,
+        alive_buffer_size: u64,
+        cell_count: u32,
+        defines: &[&str],
+    ) -> Self {
+        let particle_capacity = uniforms.data.particle_capacity;
+        assert!(
+            cell_count > 0 && cell_count <= MAX_GRID_CELLS,
+            "grid cell count must be in 1..={}",
+            MAX_GRID_CELLS
+        );
+
+        let uint_size = std::mem::size_of::<u32>() as u64;
+        let cell_buffer_size = cell_count as u64 * uint_size;
+        let block_sum_buffer_size = GRID_WORKGROUP_SIZE as u64 * uint_size;
+        let bin_buffer_size = particle_capacity as u64 * uint_size * 2;
+        let index_buffer_size = particle_capacity as u64 * uint_size;
+
+        let cell_count_buffer = create_grid_buffer(device, "cell-count-buffer", cell_buffer_size);
+        let cell_end_buffer = create_grid_buffer(device, "cell-end-buffer", cell_buffer_size);
+        let block_sum_buffer =
+            create_grid_buffer(device, "block-sum-buffer", block_sum_buffer_size);
+        let particle_bin_buffer =
+            create_grid_buffer(device, "particle-bin-buffer", bin_buffer_size);
+        let sorted_index_buffer =
+            create_grid_buffer(device, "sorted-index-buffer", index_buffer_size);
+
+        let count_cs_mod = compile_shader_variant(
+            shaders_dir,
+            device,
+            "grid_count.comp",
+            shaderc::ShaderKind::Compute,
+            defines,
+        );
+        let scan_blocks_cs_mod = compile_shader_variant(
+            shaders_dir,
+            device,
+            "grid_scan_blocks.comp",
+            shaderc::ShaderKind::Compute,
+            defines,
+        );
+        let scan_sums_cs_mod = compile_shader_variant(
+            shaders_dir,
+            device,
+            "grid_scan_sums.comp",
+            shaderc::ShaderKind::Compute,
+            defines,
+        );
+        let scan_add_cs_mod = compile_shader_variant(
+            shaders_dir,
+            device,
+            "grid_scan_add.comp",
+            shaderc::ShaderKind::Compute,
+            defines,
+        );
+        let sort_cs_mod = compile_shader_variant(
+            shaders_dir,
+            device,
+            "grid_sort.comp",
+            shaderc::ShaderKind::Compute,
+            defines,
+        );
+
+        // one bind group per ping-pong position buffer
+        let count = Compute::with_buffer_sets::<Uniforms>(
+            device,
+            position_buffers
+                .iter()
+                .map(|position_buffer| {
+                    vec![
+                        position_buffer,
+                        alive_buffer,
+                        &cell_count_buffer,
+                        &particle_bin_buffer,
+                    ]
+                })
+                .collect(),
+            Some(vec![
+                position_buffer_size,
+                alive_buffer_size,
+                cell_buffer_size,
+                bin_buffer_size,
+            ]),
+            Some(&uniforms.buffer),
+            &count_cs_mod,
+        )
+        .unwrap()
+        .with_workgroup_size(GRID_WORKGROUP_SIZE);
+
+        let scan_blocks = Compute::new::<Uniforms>(
+            device,
+            Some(vec![
+                &cell_count_buffer,
+                &cell_end_buffer,
+                &block_sum_buffer,
+            ]),
+            Some(vec![
+                cell_buffer_size,
+                cell_buffer_size,
+                block_sum_buffer_size,
+            ]),
+            Some(&uniforms.buffer),
+            &scan_blocks_cs_mod,
+        )
+        .unwrap()
+        .with_workgroup_size(GRID_WORKGROUP_SIZE);
+
+        let scan_sums = Compute::new::<Uniforms>(
+            device,
+            Some(vec![&block_sum_buffer]),
+            Some(vec![block_sum_buffer_size]),
+            Some(&uniforms.buffer),
+            &scan_sums_cs_mod,
+        )
+        .unwrap()
+        .with_workgroup_size(GRID_WORKGROUP_SIZE);
+
+        let scan_add = Compute::new::<Uniforms>(
+            device,
+            Some(vec![&cell_end_buffer, &block_sum_buffer]),
+            Some(vec![cell_buffer_size, block_sum_buffer_size]),
+            Some(&uniforms.buffer),
+            &scan_add_cs_mod,
+        )
+        .unwrap()
+        .with_workgroup_size(GRID_WORKGROUP_SIZE);
+
+        let sort = Compute::new::<Uniforms>(
+            device,
+            Some(vec![
+                &particle_bin_buffer,
+                &cell_end_buffer,
+                &sorted_index_buffer,
+            ]),
+            Some(vec![bin_buffer_size, cell_buffer_size, index_buffer_size]),
+            Some(&uniforms.buffer),
+            &sort_cs_mod,
+        )
+        .unwrap()
+        .with_workgroup_size(GRID_WORKGROUP_SIZE);
+
+        // the intermediate buffers are kept alive by the bind groups that use them
+        Self {
+            cell_end_buffer,
+            sorted_index_buffer,
+            cell_buffer_size,
+            index_buffer_size,
+            count,
+            scan_blocks,
+            scan_sums,
+            scan_add,
+            sort,
+            particle_capacity,
+            cell_count,
+        }
+    }
+
+    /// Rebuilds the grid from the positions in the given ping-pong buffer.
+    pub fn update(&self, encoder: &mut CommandEncoder, positions: usize) {
+        self.count
+            .compute_with(encoder, positions, self.particle_capacity);
+        self.scan_blocks.compute(encoder, self.cell_count);
+        // a single workgroup scans the block sums
+        self.scan_sums.compute(encoder, GRID_WORKGROUP_SIZE);
+        self.scan_add.compute(encoder, self.cell_count);
+        self.sort.compute(encoder, self.particle_capacity);
+    }
+}
+
+fn create_grid_buffer(device: &wgpu::Device, label: &str, size: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    })
+}
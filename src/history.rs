@@ -0,0 +1,274 @@
+use nannou::prelude::*;
+use nannou::wgpu::CommandEncoder;
+use std::io::{self, Write};
+
+use crate::particles::ParticleState;
+use crate::util::*;
+
+/// Column names of the rows written by `HistoryEntry::write_csv`.
+pub const CSV_HEADER: &str = "frame,time,particle,x,y,velocity_x,velocity_y,species";
+
+/// When a snapshot was taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistoryEntry {
+    pub frame: u32,
+    pub time: f32,
+}
+
+impl HistoryEntry {
+    /// Writes one `CSV_HEADER` row per live particle of the snapshot's `state`.
+    pub fn write_csv<W: Write>(&self, writer: &mut W, state: &ParticleState) -> io::Result<()> {
+        for index in 0..state.len() {
+            if !state.alive[index] {
+                continue;
+            }
+
+            let position = state.positions[index];
+            let velocity = state.velocities[index];
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{}",
+                self.frame,
+                self.time,
+                index,
+                position.x,
+                position.y,
+                velocity.x,
+                velocity.y,
+                state.species[index]
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Which slots of a ring of `capacity` slots are in use, the `len` slots from `start` on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RingSlots {
+    capacity: usize,
+    /// Slot of the oldest snapshot.
+    start: usize,
+    len: usize,
+}
+
+impl RingSlots {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Slot of the snapshot `age` snapshots before the latest.
+    fn slot(&self, age: usize) -> Option<usize> {
+        if age < self.len {
+            Some((self.start + self.len - 1 - age) % self.capacity)
+        } else {
+            None
+        }
+    }
+
+    /// Slot for a new latest snapshot, taking the oldest one's once the ring is full.
+    fn push(&mut self) -> usize {
+        if self.len == self.capacity {
+            self.start = (self.start + 1) % self.capacity;
+            self.len -= 1;
+        }
+        self.len += 1;
+        (self.start + self.len - 1) % self.capacity
+    }
+
+    fn drop_latest(&mut self, count: usize) {
+        self.len -= count.min(self.len);
+    }
+}
+
+/// A GPU side ring buffer of the last snapshots of a fixed list of state buffers.
+/// Snapshots are copied on the GPU without reading anything back, and are addressed by age,
+/// 0 being the latest.
+pub struct History {
+    /// One ring per state buffer, holding `capacity` copies of it back to back.
+    rings: Vec<wgpu::Buffer>,
+    sizes: Vec<u64>,
+    slots: RingSlots,
+    /// Entries by slot, only the slots in use hold snapshots.
+    entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    /// Room for `capacity` snapshots of state buffers of the given sizes.
+    pub fn new(device: &wgpu::Device, capacity: usize, sizes: &[u64]) -> Self {
+        assert!(capacity > 0, "the history must hold at least one snapshot");
+
+        let rings = sizes
+            .iter()
+            .enumerate()
+            .map(|(i, size)| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(&format!("history-buffer-{}", i)),
+                    size: size * capacity as u64,
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+
+        Self {
+            rings,
+            sizes: sizes.to_vec(),
+            slots: RingSlots::new(capacity),
+            entries: vec![
+                HistoryEntry {
+                    frame: 0,
+                    time: 0.0,
+                };
+                capacity
+            ],
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity
+    }
+
+    /// Number of snapshots stored.
+    pub fn len(&self) -> usize {
+        self.slots.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.len == 0
+    }
+
+    /// The snapshot `age` snapshots before the latest.
+    pub fn entry(&self, age: usize) -> Option<HistoryEntry> {
+        self.slots.slot(age).map(|slot| self.entries[slot])
+    }
+
+    /// Encodes a copy of `sources`, which match the sizes given to `new`, as the latest snapshot.
+    /// The oldest snapshot is overwritten once the history is full.
+    pub fn record(
+        &mut self,
+        encoder: &mut CommandEncoder,
+        sources: &[&wgpu::Buffer],
+        entry: HistoryEntry,
+    ) {
+        assert_eq!(
+            sources.len(),
+            self.rings.len(),
+            "wrong number of state buffers"
+        );
+
+        let slot = self.slots.push();
+        self.entries[slot] = entry;
+
+        for ((source, ring), size) in sources.iter().zip(&self.rings).zip(&self.sizes) {
+            encoder.copy_buffer_to_buffer(source, 0, ring, slot as u64 * size, *size);
+        }
+    }
+
+    /// Encodes a copy of the snapshot `age` snapshots before the latest into `targets`.
+    /// Returns `None` without encoding anything when there is no such snapshot.
+    pub fn restore(
+        &self,
+        encoder: &mut CommandEncoder,
+        age: usize,
+        targets: &[&wgpu::Buffer],
+    ) -> Option<HistoryEntry> {
+        assert_eq!(
+            targets.len(),
+            self.rings.len(),
+            "wrong number of state buffers"
+        );
+
+        let slot = self.slots.slot(age)?;
+        for ((target, ring), size) in targets.iter().zip(&self.rings).zip(&self.sizes) {
+            encoder.copy_buffer_to_buffer(ring, slot as u64 * size, target, 0, *size);
+        }
+        Some(self.entries[slot])
+    }
+
+    /// Forgets the `count` latest snapshots, so recording continues after the one before them.
+    pub fn drop_latest(&mut self, count: usize) {
+        self.slots.drop_latest(count);
+    }
+
+    /// Forgets every snapshot, for example when the state is replaced by a loaded checkpoint.
+    pub fn clear(&mut self) {
+        self.slots = RingSlots::new(self.capacity());
+    }
+
+    /// Copies the snapshot `age` snapshots before the latest back from the GPU,
+    /// one byte vector per state buffer, blocking until it is available.
+    pub fn read(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        age: usize,
+    ) -> Option<Vec<Vec<u8>>> {
+        let slot = self.slots.slot(age)?;
+        Some(
+            self.rings
+                .iter()
+                .zip(&self.sizes)
+                .map(|(ring, size)| {
+                    read_buffer_range(device, queue, ring, slot as u64 * size, *size)
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ages 0, 1, ... mapped to their slots.
+    fn slots_by_age(slots: &RingSlots) -> Vec<usize> {
+        (0..slots.len).map(|age| slots.slot(age).unwrap()).collect()
+    }
+
+    #[test]
+    fn fills_slots_in_order() {
+        let mut slots = RingSlots::new(3);
+        assert_eq!(slots.slot(0), None);
+
+        assert_eq!(slots.push(), 0);
+        assert_eq!(slots.push(), 1);
+        assert_eq!(slots_by_age(&slots), vec![1, 0]);
+        assert_eq!(slots.slot(2), None);
+    }
+
+    #[test]
+    fn overwrites_the_oldest_once_full() {
+        let mut slots = RingSlots::new(3);
+        for _ in 0..3 {
+            slots.push();
+        }
+
+        assert_eq!(slots.push(), 0);
+        assert_eq!(slots.push(), 1);
+        assert_eq!(slots.len, 3);
+        assert_eq!(slots_by_age(&slots), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn drops_the_latest() {
+        let mut slots = RingSlots::new(3);
+        for _ in 0..4 {
+            slots.push();
+        }
+
+        // the oldest snapshot, in slot 1, becomes the latest
+        slots.drop_latest(2);
+        assert_eq!(slots_by_age(&slots), vec![1]);
+        assert_eq!(slots.push(), 2);
+        assert_eq!(slots_by_age(&slots), vec![2, 1]);
+
+        slots.drop_latest(5);
+        assert_eq!(slots.len, 0);
+        assert_eq!(slots.slot(0), None);
+    }
+}
@@ -0,0 +1,111 @@
+//! Mouse interaction with the particles of the sketch window.
+
+use nannou::prelude::*;
+
+use crate::uniforms::*;
+
+/// Converts a window point, centered with y up like `app.mouse.position()`, into simulation space.
+/// The inverse of `shader.frag`, which stretches the `width` x `height` view over the window.
+pub fn window_to_simulation(point: Point2, window_size: Vec2, uniforms: &Uniforms) -> Point2 {
+    point / window_size * vec2(uniforms.width, uniforms.height)
+}
+
+/// Left drag attracts particles and right drag repels them,
+/// in grab mode left drag holds the particles under the cursor and drags them along.
+#[derive(Debug, Clone)]
+pub struct MouseInteraction {
+    pub radius: f32,
+    /// Peak acceleration of attraction and repulsion.
+    pub strength: f32,
+    /// Spring stiffness holding grabbed particles, stable below 1 at a `dt` of 1.
+    pub grab_stiffness: f32,
+    pub grab: bool,
+    /// Cursor position of the last frame that simulated any time,
+    /// to move grabbed particles along with the cursor.
+    last_position: Option<Point2>,
+}
+
+impl MouseInteraction {
+    pub fn new(radius: f32, strength: f32) -> Self {
+        MouseInteraction {
+            radius,
+            strength,
+            grab_stiffness: 0.3,
+            grab: false,
+            last_position: None,
+        }
+    }
+
+    pub fn with_grab_stiffness(mut self, grab_stiffness: f32) -> Self {
+        self.grab_stiffness = grab_stiffness;
+        self
+    }
+
+    pub fn toggle_grab(&mut self) {
+        self.grab = !self.grab;
+    }
+
+    /// Writes this frame's cursor position, velocity and mode into `uniforms`,
+    /// `elapsed` is the simulated time the frame's steps advance by, 0 while paused.
+    pub fn update(&mut self, app: &App, uniforms: &mut Uniforms, elapsed: f32) {
+        let window_size = app.main_window().rect().wh();
+        let position = window_to_simulation(app.mouse.position(), window_size, uniforms);
+        let velocity = self.cursor_velocity(position, elapsed);
+
+        let buttons = &app.mouse.buttons;
+        let mode = if app.mouse.window.is_none() {
+            MouseMode::Off
+        } else if buttons.left().is_down() && self.grab {
+            MouseMode::Grab
+        } else if buttons.left().is_down() {
+            MouseMode::Attract
+        } else if buttons.right().is_down() {
+            MouseMode::Repel
+        } else {
+            MouseMode::Off
+        };
+
+        uniforms.mouse_mode = mode as u32;
+        uniforms.mouse_x = position.x;
+        uniforms.mouse_y = position.y;
+        uniforms.mouse_velocity_x = velocity.x;
+        uniforms.mouse_velocity_y = velocity.y;
+        uniforms.mouse_radius = self.radius;
+        uniforms.mouse_strength = if mode == MouseMode::Grab {
+            self.grab_stiffness
+        } else {
+            self.strength
+        };
+    }
+
+    /// The cursor's movement since the last frame that simulated any time, over the `elapsed`
+    /// time of this frame. Frames that simulate nothing keep the movement for the next one.
+    fn cursor_velocity(&mut self, position: Point2, elapsed: f32) -> Vec2 {
+        let last = *self.last_position.get_or_insert(position);
+        if elapsed <= 0.0 {
+            return vec2(0.0, 0.0);
+        }
+
+        self.last_position = Some(position);
+        (position - last) / elapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_with_the_cursor_over_the_simulated_time() {
+        let mut mouse = MouseInteraction::new(100.0, 0.3);
+        assert_eq!(mouse.cursor_velocity(pt2(0.0, 0.0), 1.0), vec2(0.0, 0.0));
+
+        // four steps of dt 0.5 in one frame
+        assert_eq!(mouse.cursor_velocity(pt2(8.0, -4.0), 2.0), vec2(4.0, -2.0));
+
+        // slow motion and pauses carry the movement over to the next step
+        assert_eq!(mouse.cursor_velocity(pt2(10.0, -4.0), 0.0), vec2(0.0, 0.0));
+        assert_eq!(mouse.cursor_velocity(pt2(12.0, -4.0), 0.0), vec2(0.0, 0.0));
+        assert_eq!(mouse.cursor_velocity(pt2(14.0, -4.0), 1.0), vec2(6.0, 0.0));
+    }
+}
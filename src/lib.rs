@@ -0,0 +1,27 @@
+pub mod args;
+pub mod backend;
+pub mod bonds;
+pub mod camera;
+pub mod capture;
+pub mod checkpoint;
+pub mod clusters;
+pub mod compute;
+pub mod cpu;
+pub mod diagnostics;
+pub mod distribution;
+pub mod environment;
+pub mod fields;
+pub mod grid;
+pub mod history;
+pub mod interaction;
+pub mod obstacles;
+pub mod particles;
+pub mod particles3d;
+pub mod playback;
+pub mod population;
+pub mod quadtree;
+pub mod random;
+pub mod render;
+pub mod species;
+pub mod uniforms;
+pub mod util;
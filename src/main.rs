@@ -1,33 +1,127 @@
+use generative_matter::particles::ParticleSystem;
+use generative_matter::{
+    args, capture, checkpoint, clusters, diagnostics, distribution, environment, fields, history,
+    interaction, obstacles, particles, playback, population, random, render, species, uniforms,
+    util,
+};
 use nannou::prelude::*;
-use particles::ParticleSystem;
-
-mod capture;
-mod compute;
-mod particles;
-mod render;
-mod uniforms;
-mod util;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 
 struct Model {
     particle_system: ParticleSystem,
     uniforms: uniforms::UniformBuffer,
     frame_capturer: capture::FrameCapturer,
     render: render::CustomRenderer,
+    mouse: interaction::MouseInteraction,
+    /// Clusters found in the last analyzed frame.
+    clusters: clusters::ClusterStats,
+    /// Every analyzed frame's clusters, as `frames/clusters.csv`.
+    cluster_log: BufWriter<File>,
+    /// The latest `DIAGNOSTICS_HISTORY` diagnostics, oldest first.
+    diagnostics: Vec<diagnostics::Diagnostics>,
+    /// Every diagnostics reading, as `frames/diagnostics.csv`.
+    diagnostics_log: BufWriter<File>,
+    show_hud: bool,
+    /// Age of the snapshot shown while scrubbing through the history, `None` while simulating.
+    rewind: Option<usize>,
+    playback: playback::Playback,
+    seed: u64,
 }
 
 const WIDTH: u32 = 1920;
 const HEIGHT: u32 = 1080;
+/// Particles alive at the start, overridden by `--particles`.
 const PARTICLE_COUNT: u32 = 1500;
+/// Dead slots on top of the initial particles, room for particles spawned by emitters.
+const SPAWN_HEADROOM: u32 = 500;
+/// Initial distribution preset, overridden by `--distribution`.
+const DISTRIBUTION: &str = "disk:radius=192";
+/// Species preset, overridden by `--species`.
+const SPECIES: &str = "random:count=3,ratios=1/1/1";
+const BOUNDARY_MODE: uniforms::BoundaryMode = uniforms::BoundaryMode::Open;
+const WORKGROUP_SIZE: u32 = 64;
+const TILED_FORCES: bool = false;
+/// Opening angle of the Barnes-Hut approximation used when a force range is 0 (unlimited),
+/// 0 computes every pair exactly instead.
+const BARNES_HUT_THETA: f32 = 0.5;
+const FORCE_KERNEL: uniforms::ForceKernel = uniforms::ForceKernel::InverseSquare;
+/// Reach of the cursor in simulation units, and the peak acceleration of attraction and repulsion.
+const MOUSE_RADIUS: f32 = 120.0;
+const MOUSE_STRENGTH: f32 = 0.3;
+/// Particles closer than this bond after lingering next to each other, 0 disables bonds.
+const BOND_CAPTURE_DISTANCE: f32 = 0.0;
+/// Particles closer than this belong to the same cluster.
+const CLUSTER_DISTANCE: f32 = 6.0;
+/// Frames between cluster analyses, each of which reads the state back from the GPU.
+const CLUSTER_INTERVAL: u32 = 30;
+/// Frames between diagnostics reductions, more while the previous readback is pending.
+const DIAGNOSTICS_INTERVAL: u32 = 10;
+/// Diagnostics readings kept for the HUD plot.
+const DIAGNOSTICS_HISTORY: usize = 300;
+/// Factor the up and down keys change the target temperature by.
+const TEMPERATURE_STEP: f32 = 1.25;
+/// Snapshots kept to rewind to, and frames between them.
+const HISTORY_LENGTH: usize = 120;
+const HISTORY_INTERVAL: u32 = 10;
+
+/// Particles stream in from the left and drain on the right.
+fn population(particle_count: u32) -> population::PopulationConfig {
+    let emitters = vec![population::Emitter {
+        center: pt2(-320.0, 0.0),
+        radius: 16.0,
+        rate: 2.0,
+        velocity: pt2(1.0, 0.0),
+        ..Default::default()
+    }];
+    let sinks = vec![population::Sink::new(pt2(320.0, 0.0), 24.0)];
+
+    population::PopulationConfig::new(particle_count)
+        .with_emitters(emitters)
+        .with_sinks(sinks)
+}
+
+/// Static colliders particles flow around, for example
+/// `Obstacle::capsule(pt2(-200.0, 100.0), pt2(-40.0, -60.0), 8.0)` for one side of a funnel,
+/// and external force fields, for example `ForceField::curl_noise(0.02, 150.0, 0.01)`.
+fn environment() -> environment::EnvironmentConfig {
+    let obstacles: Vec<obstacles::Obstacle> = vec![];
+    let fields: Vec<fields::ForceField> = vec![];
+
+    environment::EnvironmentConfig::new()
+        .with_obstacles(obstacles)
+        .with_fields(fields)
+}
 
 fn main() {
     nannou::app(model).update(update).run();
 }
 
 fn model(app: &App) -> Model {
+    let args = args::Args::from_env().unwrap_or_else(|e| panic!("invalid arguments: {:?}", e));
+    let playback = args.playback();
+    let checkpoint = args.checkpoint.as_ref().map(|path| {
+        checkpoint::Checkpoint::load(path)
+            .unwrap_or_else(|e| panic!("failed to load {}: {:?}", path.display(), e))
+    });
+
+    // every random choice of the run follows from the seed, a checkpoint continues its own run
+    let seed = match &checkpoint {
+        Some(checkpoint) => checkpoint.seed,
+        None => args.seed.unwrap_or_else(rand::random),
+    };
+    println!("seed: {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    util::check_window_adapter(app).unwrap_or_else(|e| panic!("unsupported adapter: {:?}", e));
     let window_id = app
         .new_window()
         .size(WIDTH, HEIGHT)
+        .device_descriptor(util::device_descriptor())
         .view(view)
+        .key_pressed(key_pressed)
         .build()
         .unwrap();
     let window = app.window(window_id).unwrap();
@@ -37,28 +131,133 @@ fn model(app: &App) -> Model {
     println!("creating uniforms");
 
     // Create the buffer that will store the uniforms.
-    let uniforms =
-        uniforms::UniformBuffer::new(device, PARTICLE_COUNT, WIDTH as f32, HEIGHT as f32);
+    let particle_count = args.particles.unwrap_or(PARTICLE_COUNT);
+    // a checkpoint brings its own capacity, which `load_checkpoint` checks the state against
+    let particle_capacity = match &checkpoint {
+        Some(checkpoint) => checkpoint.uniforms.particle_capacity,
+        None => particle_count.saturating_add(SPAWN_HEADROOM),
+    };
+    let mut uniforms =
+        uniforms::UniformBuffer::new(device, particle_capacity, WIDTH as f32, HEIGHT as f32);
+
+    // a checkpoint brings its own species, population and environment
+    let species = match &checkpoint {
+        Some(checkpoint) => checkpoint.species.clone(),
+        None => args
+            .species
+            .clone()
+            .unwrap_or_else(|| SPECIES.parse::<species::SpeciesSource>().unwrap())
+            .build(&mut rng),
+    }
+    .validated();
+    uniforms.data.species_count = species.count;
+    uniforms.data.seed = random::gpu_seed(seed);
+    uniforms.data.boundary_mode = BOUNDARY_MODE as u32;
+    uniforms.data.force_kernel = FORCE_KERNEL as u32;
+    uniforms.data.barnes_hut_theta = BARNES_HUT_THETA;
+    uniforms.data.bond_capture_distance = BOND_CAPTURE_DISTANCE;
+
+    let population = checkpoint
+        .as_ref()
+        .map_or_else(
+            || population(particle_count),
+            |checkpoint| checkpoint.population.clone(),
+        )
+        .validated(particle_capacity, species.count);
+    uniforms.data.emitter_count = population.emitters.len() as u32;
+    uniforms.data.sink_count = population.sinks.len() as u32;
+    // sampled particles have the default attributes
+    uniforms.data.max_radius_scale = population
+        .max_radius_scale()
+        .max(particles::ParticleAttributes::default().radius);
+
+    let environment = checkpoint
+        .as_ref()
+        .map_or_else(environment, |checkpoint| checkpoint.environment.clone());
+    uniforms.data.obstacle_count = environment.obstacles.len() as u32;
+    uniforms.data.field_count = environment.fields.len() as u32;
+
+    if let Some(checkpoint) = &checkpoint {
+        uniforms.data = checkpoint.uniforms;
+    }
+
+    let distribution = args.distribution.unwrap_or_else(|| {
+        DISTRIBUTION
+            .parse::<distribution::InitialDistribution>()
+            .unwrap()
+    });
 
     println!("creating particle system");
 
-    let particle_system =
-        particles::ParticleSystem::new(app, device, &uniforms, WIDTH as f32 * 0.1);
+    let shaders_dir = util::shaders_directory(app);
+    let particle_system = particles::ParticleSystem::new(
+        &shaders_dir,
+        device,
+        &uniforms,
+        &distribution,
+        &species,
+        &population,
+        &environment,
+        particles::UpdateOptions {
+            workgroup_size: WORKGROUP_SIZE,
+            tiled_forces: TILED_FORCES,
+        },
+        &mut rng,
+    )
+    .unwrap_or_else(|e| panic!("failed to sample the initial distribution: {:?}", e))
+    .with_history(device, HISTORY_LENGTH);
+    if let Some(checkpoint) = &checkpoint {
+        particle_system
+            .load_checkpoint(window.queue(), checkpoint)
+            .unwrap_or_else(|e| panic!("failed to continue the checkpoint: {:?}", e));
+        println!("continuing from frame {}", checkpoint.frame());
+    }
 
     println!("finalizing reasources");
 
-    let frame_capturer = capture::FrameCapturer::new(app);
+    let frame_capturer = capture::FrameCapturer::new(app, seed);
+    let cluster_log = capture::create_csv(app, "clusters.csv", clusters::CSV_HEADER);
+    let diagnostics_log = capture::create_csv(app, "diagnostics.csv", diagnostics::CSV_HEADER);
 
     println!("loading shaders");
     let vs_mod = util::compile_shader(app, device, "shader.vert", shaderc::ShaderKind::Vertex);
     let fs_mod = util::compile_shader(app, device, "shader.frag", shaderc::ShaderKind::Fragment);
 
-    let render = render::CustomRenderer::new::<uniforms::Uniforms>(
+    // one buffer set per ping-pong position buffer, picked by `ParticleSystem::current`
+    let buffer_sets: Vec<Vec<&wgpu::Buffer>> = particle_system
+        .position_buffers
+        .iter()
+        .map(|positions| {
+            vec![
+                positions,
+                &particle_system.species_buffer,
+                &particle_system.population.alive_buffer,
+                &particle_system.attribute_buffer,
+                &particle_system.obstacle_buffer,
+                &particle_system.bonds.bond_buffer,
+                &particle_system.cluster_buffer,
+                &particle_system.grid.cell_end_buffer,
+                &particle_system.grid.sorted_index_buffer,
+            ]
+        })
+        .collect();
+
+    let render = render::CustomRenderer::with_buffer_sets::<uniforms::Uniforms>(
         device,
         &vs_mod,
         &fs_mod,
-        Some(&vec![&particle_system.position_out_buffer]),
-        Some(&vec![&particle_system.buffer_size]),
+        &buffer_sets,
+        Some(&vec![
+            &particle_system.buffer_size,
+            &particle_system.species_buffer_size,
+            &particle_system.population.alive_buffer_size,
+            &particle_system.attribute_buffer_size,
+            &particle_system.obstacle_buffer_size,
+            &particle_system.bonds.bond_buffer_size,
+            &particle_system.cluster_buffer_size,
+            &particle_system.grid.cell_buffer_size,
+            &particle_system.grid.index_buffer_size,
+        ]),
         None,
         None,
         Some(&uniforms.buffer),
@@ -74,9 +273,144 @@ fn model(app: &App) -> Model {
         uniforms,
         frame_capturer,
         render,
+        mouse: interaction::MouseInteraction::new(MOUSE_RADIUS, MOUSE_STRENGTH),
+        clusters: clusters::ClusterStats::default(),
+        cluster_log,
+        diagnostics: vec![],
+        diagnostics_log,
+        show_hud: true,
+        rewind: None,
+        playback,
+        seed,
+    }
+}
+
+/// G toggles grabbing particles with the left mouse button instead of attracting them,
+/// B toggles drawing bonds, C coloring by cluster and H the HUD.
+/// T cycles through the thermostats, up and down raise and lower the target temperature.
+/// Left and right scrub through the history, return resumes from the shown snapshot
+/// and D dumps the whole history.
+/// Space pauses and resumes, period runs a single step, right and left brackets double and
+/// halve the simulation speed.
+/// S saves a checkpoint and L continues from it.
+fn key_pressed(app: &App, model: &mut Model, key: Key) {
+    let uniforms = &mut model.uniforms.data;
+    let history_len = model
+        .particle_system
+        .history
+        .as_ref()
+        .map_or(0, |history| history.len());
+    match key {
+        Key::Left if history_len > 0 => {
+            model.rewind = Some(model.rewind.map_or(0, |age| (age + 1).min(history_len - 1)))
+        }
+        Key::Right => model.rewind = model.rewind.map(|age| age.saturating_sub(1)),
+        Key::Return => {
+            // the simulation replays from the shown snapshot's frame, recording it again
+            if let (Some(age), Some(history)) =
+                (model.rewind.take(), model.particle_system.history.as_mut())
+            {
+                if let Some(entry) = history.entry(age) {
+                    uniforms.frame = entry.frame;
+                    uniforms.time = entry.time;
+                }
+                history.drop_latest(age + 1);
+            }
+        }
+        Key::D => dump_history(app, model),
+        Key::S => save_checkpoint(app, model),
+        Key::L => load_checkpoint(app, model),
+        Key::Space => model.playback.toggle_pause(),
+        Key::Period => model.playback.step(),
+        Key::RBracket => model.playback.faster(),
+        Key::LBracket => model.playback.slower(),
+        Key::T => {
+            uniforms.thermostat = uniforms::Thermostat::from_uint(uniforms.thermostat).next() as u32
+        }
+        Key::Up => uniforms.target_temperature *= TEMPERATURE_STEP,
+        Key::Down => uniforms.target_temperature /= TEMPERATURE_STEP,
+        Key::G => model.mouse.toggle_grab(),
+        Key::B => uniforms.show_bonds ^= 1,
+        Key::C => uniforms.color_by_cluster ^= 1,
+        Key::H => model.show_hud = !model.show_hud,
+        _ => {}
     }
 }
 
+/// Writes every snapshot of the history, oldest first, to `frames/history.csv`.
+fn dump_history(app: &App, model: &Model) {
+    let history_len = match &model.particle_system.history {
+        Some(history) => history.len(),
+        None => return,
+    };
+
+    let window = app.main_window();
+    let mut writer = capture::create_csv(app, "history.csv", history::CSV_HEADER);
+    for age in (0..history_len).rev() {
+        let (entry, state) = model
+            .particle_system
+            .read_history(window.device(), window.queue(), age)
+            .unwrap();
+        entry
+            .write_csv(&mut writer, &state)
+            .expect("failed to write history.csv");
+    }
+    writer.flush().expect("failed to write history.csv");
+    println!("wrote {} snapshots to history.csv", history_len);
+}
+
+/// Writes the latest state, or the snapshot shown while rewinding, to `frames/checkpoint.bin`.
+fn save_checkpoint(app: &App, model: &Model) {
+    let mut uniforms = model.uniforms.data;
+    if let (Some(age), Some(history)) = (model.rewind, &model.particle_system.history) {
+        if let Some(entry) = history.entry(age) {
+            uniforms.frame = entry.frame;
+            uniforms.time = entry.time;
+        }
+    }
+
+    let window = app.main_window();
+    let checkpoint = model.particle_system.read_checkpoint(
+        window.device(),
+        window.queue(),
+        model.seed,
+        &uniforms,
+    );
+    let path = capture::checkpoint_path(app);
+    checkpoint
+        .save(&path)
+        .unwrap_or_else(|e| panic!("failed to save {}: {:?}", path.display(), e));
+    println!("saved frame {} to {}", checkpoint.frame(), path.display());
+}
+
+/// Continues from `frames/checkpoint.bin` and forgets the history. The checkpoint must come
+/// from a run with the same species, population and environment,
+/// others can be continued with `--checkpoint` instead.
+fn load_checkpoint(app: &App, model: &mut Model) {
+    let path = capture::checkpoint_path(app);
+    let loaded = checkpoint::Checkpoint::load(&path).and_then(|checkpoint| {
+        model
+            .particle_system
+            .load_checkpoint(app.main_window().queue(), &checkpoint)
+            .map(|_| checkpoint)
+    });
+    let checkpoint = match loaded {
+        Ok(checkpoint) => checkpoint,
+        Err(e) => {
+            println!("failed to load {}: {:?}", path.display(), e);
+            return;
+        }
+    };
+
+    model.seed = checkpoint.seed;
+    model.uniforms.data = checkpoint.uniforms;
+    model.rewind = None;
+    if let Some(history) = model.particle_system.history.as_mut() {
+        history.clear();
+    }
+    println!("continuing from frame {}", checkpoint.frame());
+}
+
 fn update(app: &App, model: &mut Model, _update: Update) {
     let window = app.main_window();
     let device = window.device();
@@ -87,42 +421,201 @@ fn update(app: &App, model: &mut Model, _update: Update) {
     };
     let mut encoder = device.create_command_encoder(&desc);
 
+    // paused frames only render, so camera and style changes still show,
+    // and while rewinding the shown snapshot replaces the state and nothing is simulated
+    let steps = match model.rewind {
+        Some(_) => 0,
+        None => model.playback.advance(),
+    };
+    let elapsed = steps as f32 * model.uniforms.data.dt;
+    model.mouse.update(app, &mut model.uniforms.data, elapsed);
     model.uniforms.update(device, &mut encoder);
 
-    model.particle_system.update(&mut encoder);
-
-    model.render.render(&mut encoder);
-
-    encoder.copy_buffer_to_buffer(
-        &model.particle_system.position_out_buffer,
-        0,
-        &model.particle_system.position_in_buffer,
-        0,
-        model.particle_system.buffer_size,
-    );
+    if let Some(age) = model.rewind {
+        model.particle_system.restore_history(&mut encoder, age);
+        model
+            .render
+            .render_with(&mut encoder, model.particle_system.current());
+        window.queue().submit(Some(encoder.finish()));
+        return;
+    }
 
-    encoder.copy_buffer_to_buffer(
-        &model.particle_system.velocity_out_buffer,
-        0,
-        &model.particle_system.velocity_in_buffer,
-        0,
-        model.particle_system.buffer_size,
-    );
+    let mut analyze_clusters = false;
+    for _ in 0..steps {
+        let frame = step(model, device, &mut encoder);
+        analyze_clusters |= frame.is_multiple_of(CLUSTER_INTERVAL);
+    }
+    // the renderer looks particles up in the grid, which updates built before they moved
+    model.particle_system.list_live(&mut encoder);
 
     model
-        .frame_capturer
-        .take_snapshot(device, &mut encoder, &model.render.output_texture);
+        .render
+        .render_with(&mut encoder, model.particle_system.current());
+
+    // only simulated frames are captured
+    if steps > 0 {
+        model
+            .frame_capturer
+            .take_snapshot(device, &mut encoder, &model.render.output_texture);
+    }
 
     // Submit the compute pass to the device's queue.
     window.queue().submit(Some(encoder.finish()));
 
     model.frame_capturer.save_frame(app);
+
+    // diagnostics arrive a few frames after they were encoded,
+    // each new temperature reading rescales velocities once under the berendsen thermostat
+    let uniforms = &mut model.uniforms.data;
+    model.particle_system.diagnostics.submitted();
+    if let Some(reading) = model.particle_system.diagnostics.poll(device) {
+        if uniforms::Thermostat::from_uint(uniforms.thermostat) == uniforms::Thermostat::Berendsen {
+            let elapsed = match model.diagnostics.last() {
+                Some(last) => reading.time - last.time,
+                None => DIAGNOSTICS_INTERVAL as f32 * uniforms.dt,
+            };
+            uniforms.velocity_scale = reading.berendsen_scale(
+                uniforms.target_temperature,
+                uniforms.berendsen_coupling_time,
+                elapsed,
+            );
+        }
+
+        reading
+            .write_csv(&mut model.diagnostics_log)
+            .and_then(|_| model.diagnostics_log.flush())
+            .expect("failed to write diagnostics.csv");
+        if model.diagnostics.len() == DIAGNOSTICS_HISTORY {
+            model.diagnostics.remove(0);
+        }
+        model.diagnostics.push(reading);
+    }
+
+    if analyze_clusters {
+        let frame = model.uniforms.data.frame.wrapping_sub(1);
+        let state = model.particle_system.read_state(device, window.queue());
+        model.clusters =
+            clusters::ClusterStats::find(&state, CLUSTER_DISTANCE, &model.uniforms.data);
+        model
+            .particle_system
+            .write_clusters(window.queue(), &model.clusters.labels);
+        model
+            .clusters
+            .write_csv(&mut model.cluster_log, frame)
+            .and_then(|_| model.cluster_log.flush())
+            .expect("failed to write clusters.csv");
+    }
 }
 
-fn view(_app: &App, model: &Model, frame: Frame) {
-    let mut encoder = frame.command_encoder();
-    model
-        .render
-        .texture_reshaper
-        .encode_render_pass(frame.texture_view(), &mut *encoder);
+/// Encodes one simulation step with the uniforms of the current frame, then moves on to
+/// the next frame. Returns the frame that was simulated.
+fn step(model: &mut Model, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) -> u32 {
+    model.uniforms.update(device, encoder);
+    // a berendsen rescale applies to a single step
+    model.uniforms.data.velocity_scale = 1.0;
+
+    // snapshots hold the state a frame starts from, so resuming replays that frame
+    let frame = model.uniforms.data.frame;
+    if frame.is_multiple_of(HISTORY_INTERVAL) {
+        model
+            .particle_system
+            .record_history(encoder, frame, model.uniforms.data.time);
+    }
+
+    model.particle_system.update(encoder, &model.uniforms.data);
+
+    if frame.is_multiple_of(DIAGNOSTICS_INTERVAL) {
+        model
+            .particle_system
+            .encode_diagnostics(encoder, frame, model.uniforms.data.time);
+    }
+
+    model.uniforms.data.frame = frame.wrapping_add(1);
+    model.uniforms.data.time += model.uniforms.data.dt;
+    frame
+}
+
+fn view(app: &App, model: &Model, frame: Frame) {
+    {
+        let mut encoder = frame.command_encoder();
+        model
+            .render
+            .texture_reshaper
+            .encode_render_pass(frame.texture_view(), &mut encoder);
+    }
+
+    // the HUD is drawn over the window only, captured frames stay clean
+    if model.show_hud {
+        let draw = app.draw();
+        let area = app.window_rect().pad(20.0);
+        let mut text = model.clusters.summary();
+        if let Some(latest) = model.diagnostics.last() {
+            text = format!("{}\n\n{}", latest.summary(), text);
+        }
+        let uniforms = &model.uniforms.data;
+        if let (Some(age), Some(history)) = (model.rewind, &model.particle_system.history) {
+            if let Some(entry) = history.entry(age) {
+                text = format!(
+                    "rewind: frame {}, snapshot {} of {}\n{}",
+                    entry.frame,
+                    history.len() - age,
+                    history.len(),
+                    text
+                );
+            }
+        }
+        let thermostat = uniforms::Thermostat::from_uint(uniforms.thermostat);
+        if thermostat != uniforms::Thermostat::Off {
+            text = format!(
+                "thermostat: {:?}, target temperature {:.4}\n{}",
+                thermostat, uniforms.target_temperature, text
+            );
+        }
+        let playback = model.playback.summary();
+        if !playback.is_empty() {
+            text = format!("{}\n{}", playback, text);
+        }
+        draw.text(&text)
+            .xy(area.xy())
+            .wh(area.wh())
+            .left_justify()
+            .align_text_top()
+            .font_size(14)
+            .color(WHITE);
+
+        let energies: Vec<f32> = model.diagnostics.iter().map(|d| d.kinetic_energy).collect();
+        let plot = Rect::from_w_h(300.0, 80.0).bottom_left_of(area);
+        draw_time_series(&draw, plot, &energies);
+        draw.text("kinetic energy")
+            .xy(plot.xy())
+            .wh(plot.wh())
+            .left_justify()
+            .align_text_top()
+            .font_size(12)
+            .color(GRAY);
+
+        draw.to_frame(app, &frame).unwrap();
+    }
+}
+
+/// Line plot of `values` filling `rect`, scaled from zero to the largest value.
+fn draw_time_series(draw: &Draw, rect: Rect, values: &[f32]) {
+    draw.rect()
+        .xy(rect.xy())
+        .wh(rect.wh())
+        .no_fill()
+        .stroke(GRAY)
+        .stroke_weight(1.0);
+
+    let max = values.iter().cloned().fold(0.0, f32::max);
+    if values.len() < 2 || max <= 0.0 {
+        return;
+    }
+
+    let points = values.iter().enumerate().map(|(i, value)| {
+        let x = map_range(i, 0, values.len() - 1, rect.left(), rect.right());
+        let y = map_range(*value, 0.0, max, rect.bottom(), rect.top());
+        pt2(x, y)
+    });
+    draw.polyline().weight(1.5).points(points).color(WHITE);
 }
@@ -0,0 +1,209 @@
+use nannou::prelude::*;
+
+/// Shape of an `Obstacle`, must match the `OBSTACLE_*` constants of `obstacles.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObstacleShape {
+    /// A disk around `a`.
+    Circle = 0,
+    /// An axis aligned box around `a` with half extents `b`, rounded by `radius`.
+    Box = 1,
+    /// The segment from `a` to `b` thickened by `radius`.
+    Capsule = 2,
+    /// An infinitely thin wall from `a` to `b`.
+    Segment = 3,
+}
+
+impl ObstacleShape {
+    pub fn from_uint(value: u32) -> Self {
+        match value {
+            1 => ObstacleShape::Box,
+            2 => ObstacleShape::Capsule,
+            3 => ObstacleShape::Segment,
+            _ => ObstacleShape::Circle,
+        }
+    }
+}
+
+/// A static collider described by its signed distance function.
+/// Laid out as the `Obstacle` struct of `obstacles.glsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Obstacle {
+    /// An `ObstacleShape` as uint.
+    pub shape: u32,
+    pub radius: f32,
+    /// Fraction of the tangential velocity lost on contact.
+    pub friction: f32,
+    /// Fraction of the normal velocity kept when bouncing off.
+    pub restitution: f32,
+    pub a: Point2,
+    pub b: Point2,
+}
+
+impl Obstacle {
+    pub fn circle(center: Point2, radius: f32) -> Self {
+        Self::new(ObstacleShape::Circle, center, pt2(0.0, 0.0), radius)
+    }
+
+    pub fn rectangle(center: Point2, half_extents: Point2) -> Self {
+        Self::new(ObstacleShape::Box, center, half_extents, 0.0)
+    }
+
+    pub fn capsule(a: Point2, b: Point2, radius: f32) -> Self {
+        Self::new(ObstacleShape::Capsule, a, b, radius)
+    }
+
+    pub fn segment(a: Point2, b: Point2) -> Self {
+        Self::new(ObstacleShape::Segment, a, b, 0.0)
+    }
+
+    fn new(shape: ObstacleShape, a: Point2, b: Point2, radius: f32) -> Self {
+        Obstacle {
+            shape: shape as u32,
+            radius,
+            friction: 0.1,
+            restitution: 0.5,
+            a,
+            b,
+        }
+    }
+
+    /// Rounds the corners of a box, or thickens a capsule.
+    pub fn with_radius(mut self, radius: f32) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    pub fn with_friction(mut self, friction: f32) -> Self {
+        self.friction = friction;
+        self
+    }
+
+    pub fn with_restitution(mut self, restitution: f32) -> Self {
+        self.restitution = restitution;
+        self
+    }
+
+    /// Signed distance from `position` to the surface, negative inside,
+    /// along with the outward surface normal. Mirrors `obstacle_distance` of `obstacles.glsl`.
+    pub fn distance(&self, position: Point2) -> (f32, Point2) {
+        match ObstacleShape::from_uint(self.shape) {
+            ObstacleShape::Circle => {
+                let diff = position - self.a;
+                (diff.length() - self.radius, safe_normalize(diff))
+            }
+            ObstacleShape::Box => {
+                let p = position - self.a;
+                let d = p.abs() - self.b;
+                let outside = d.max(vec2(0.0, 0.0));
+                let sides = vec2(side(p.x), side(p.y));
+                if d.x > 0.0 || d.y > 0.0 {
+                    // outside, the normal points away from the nearest point of the box
+                    let normal = safe_normalize(outside) * sides;
+                    (outside.length() - self.radius, normal)
+                } else if d.x > d.y {
+                    // inside, push out through the nearest side
+                    (d.x - self.radius, vec2(sides.x, 0.0))
+                } else {
+                    (d.y - self.radius, vec2(0.0, sides.y))
+                }
+            }
+            ObstacleShape::Capsule => {
+                let (distance, normal) = segment_distance(position, self.a, self.b);
+                (distance - self.radius, normal)
+            }
+            ObstacleShape::Segment => segment_distance(position, self.a, self.b),
+        }
+    }
+}
+
+/// Distance from `position` to the segment from `a` to `b`, and the direction away from it.
+fn segment_distance(position: Point2, a: Point2, b: Point2) -> (f32, Point2) {
+    let ab = b - a;
+    let ap = position - a;
+    let t = if ab.length_squared() > 0.0 {
+        (ap.dot(ab) / ab.length_squared()).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let diff = ap - ab * t;
+    (diff.length(), safe_normalize(diff))
+}
+
+/// Normalized `v`, or straight up for a zero vector.
+fn safe_normalize(v: Point2) -> Point2 {
+    let length = v.length();
+    if length > 0.0 {
+        v / length
+    } else {
+        vec2(0.0, 1.0)
+    }
+}
+
+/// -1 for negative values, 1 otherwise, even for zero unlike `signum` of -0.0.
+fn side(x: f32) -> f32 {
+    if x < 0.0 {
+        -1.0
+    } else {
+        1.0
+    }
+}
+
+/// See the `nannou::wgpu::bytes` documentation for why this is necessary.
+pub fn obstacles_as_bytes(data: &[Obstacle]) -> &[u8] {
+    unsafe { wgpu::bytes::from_slice(data) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_distance(obstacle: &Obstacle, position: Point2, distance: f32, normal: Point2) {
+        let (actual_distance, actual_normal) = obstacle.distance(position);
+        assert!(
+            (actual_distance - distance).abs() < 1e-5 && actual_normal.distance(normal) < 1e-5,
+            "{:?} at {:?}: ({}, {:?})",
+            obstacle,
+            position,
+            actual_distance,
+            actual_normal
+        );
+    }
+
+    #[test]
+    fn circles() {
+        let circle = Obstacle::circle(pt2(10.0, 0.0), 3.0);
+        assert_distance(&circle, pt2(15.0, 0.0), 2.0, vec2(1.0, 0.0));
+        assert_distance(&circle, pt2(10.0, -1.0), -2.0, vec2(0.0, -1.0));
+        assert_distance(&circle, pt2(10.0, 0.0), -3.0, vec2(0.0, 1.0));
+    }
+
+    #[test]
+    fn boxes() {
+        let rectangle = Obstacle::rectangle(pt2(0.0, 0.0), pt2(4.0, 2.0));
+        assert_distance(&rectangle, pt2(7.0, 0.0), 3.0, vec2(1.0, 0.0));
+        assert_distance(&rectangle, pt2(-7.0, -6.0), 5.0, vec2(-0.6, -0.8));
+        // inside, out through the nearest side
+        assert_distance(&rectangle, pt2(-3.5, 0.0), -0.5, vec2(-1.0, 0.0));
+        assert_distance(&rectangle, pt2(1.0, 1.5), -0.5, vec2(0.0, 1.0));
+
+        let rounded = rectangle.with_radius(1.0);
+        assert_distance(&rounded, pt2(7.0, 0.0), 2.0, vec2(1.0, 0.0));
+    }
+
+    #[test]
+    fn capsules_and_segments() {
+        let capsule = Obstacle::capsule(pt2(0.0, 0.0), pt2(10.0, 0.0), 2.0);
+        assert_distance(&capsule, pt2(5.0, -5.0), 3.0, vec2(0.0, -1.0));
+        assert_distance(&capsule, pt2(14.0, 0.0), 2.0, vec2(1.0, 0.0));
+        assert_distance(&capsule, pt2(-1.0, 0.0), -1.0, vec2(-1.0, 0.0));
+
+        let segment = Obstacle::segment(pt2(0.0, 0.0), pt2(0.0, 10.0));
+        assert_distance(&segment, pt2(-3.0, 4.0), 3.0, vec2(-1.0, 0.0));
+        assert_distance(&segment, pt2(0.0, 13.0), 3.0, vec2(0.0, 1.0));
+
+        // a segment of zero length is a point
+        let point = Obstacle::segment(pt2(1.0, 1.0), pt2(1.0, 1.0));
+        assert_distance(&point, pt2(4.0, 5.0), 5.0, vec2(0.6, 0.8));
+    }
+}
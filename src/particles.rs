@@ -2,123 +2,958 @@ use nannou::prelude::*;
 use nannou::wgpu::CommandEncoder;
 use rand;
 use rand::Rng;
+use std::path::Path;
 
+use crate::bonds::*;
+use crate::checkpoint::*;
+use crate::clusters::*;
 use crate::compute::*;
+use crate::diagnostics::*;
+use crate::distribution::*;
+use crate::environment::*;
+use crate::fields::*;
+use crate::grid::*;
+use crate::history::*;
+use crate::obstacles::*;
+use crate::population::*;
+use crate::quadtree::*;
+use crate::species::*;
 use crate::uniforms::*;
 use crate::util::*;
 
 pub struct ParticleSystem {
-    pub position_in_buffer: wgpu::Buffer,
-    pub position_out_buffer: wgpu::Buffer,
-    pub velocity_in_buffer: wgpu::Buffer,
-    pub velocity_out_buffer: wgpu::Buffer,
+    /// Ping-pong position buffers, each stage reads one and writes the other.
+    pub position_buffers: [wgpu::Buffer; 2],
+    /// Ping-pong velocity buffers, swapped together with the positions.
+    pub velocity_buffers: [wgpu::Buffer; 2],
+    pub species_buffer: wgpu::Buffer,
+    pub integrator_buffer: wgpu::Buffer,
+    /// Per-particle `ParticleAttributes`, changed in place when particles spawn.
+    pub attribute_buffer: wgpu::Buffer,
+    /// Static `Obstacle`s, shared with the renderer to draw them.
+    pub obstacle_buffer: wgpu::Buffer,
+    /// Cluster label of every slot, written by `write_clusters` and only read by the renderer.
+    pub cluster_buffer: wgpu::Buffer,
     pub buffer_size: u64,
+    pub species_buffer_size: u64,
+    pub attribute_buffer_size: u64,
+    pub obstacle_buffer_size: u64,
+    pub cluster_buffer_size: u64,
     pub initial_positions: Vec<Point2>,
-    pub compute: Compute,
-    pub particle_count: u32,
+    /// One compute pipeline per `UpdateStage`, indexed by the stage.
+    /// Bind group `i` reads from buffers `i` and writes to the others.
+    pub stages: Vec<Compute>,
+    pub grid: NeighborGrid,
+    /// Approximates unlimited range forces when `barnes_hut_theta` is positive.
+    pub quadtree: Quadtree,
+    pub population: Population,
+    pub bonds: Bonds,
+    pub diagnostics: DiagnosticsReduction,
+    /// Snapshots to rewind to, see `with_history`.
+    pub history: Option<History>,
+    pub particle_capacity: u32,
+    /// Whether the population passes need to run, see `PopulationConfig::is_dynamic`.
+    dynamic_population: bool,
+    /// The configs the system was created with, saved in checkpoints,
+    /// which can only be loaded into a system with the same configs.
+    species_config: SpeciesConfig,
+    population_config: PopulationConfig,
+    environment_config: EnvironmentConfig,
+    /// Index of the buffers holding the latest state.
+    current: usize,
+}
+
+/// One pass of `update.comp`, compiled as its own shader variant.
+/// Each integrator runs one or two stages per substep, the thermostat once per frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateStage {
+    Euler = 0,
+    VerletDrift = 1,
+    VerletKick = 2,
+    Rk2Half = 3,
+    Rk2Full = 4,
+    Thermostat = 5,
+}
+
+impl UpdateStage {
+    pub const ALL: [UpdateStage; 6] = [
+        UpdateStage::Euler,
+        UpdateStage::VerletDrift,
+        UpdateStage::VerletKick,
+        UpdateStage::Rk2Half,
+        UpdateStage::Rk2Full,
+        UpdateStage::Thermostat,
+    ];
+
+    pub fn for_integrator(integrator: Integrator) -> &'static [UpdateStage] {
+        match integrator {
+            Integrator::SemiImplicitEuler => &[UpdateStage::Euler],
+            Integrator::VelocityVerlet => &[UpdateStage::VerletDrift, UpdateStage::VerletKick],
+            Integrator::Rk2 => &[UpdateStage::Rk2Half, UpdateStage::Rk2Full],
+        }
+    }
+
+    /// The macro selecting this stage in `update.comp`.
+    pub fn define(self) -> &'static str {
+        match self {
+            UpdateStage::Euler => "STAGE_EULER",
+            UpdateStage::VerletDrift => "STAGE_VERLET_DRIFT",
+            UpdateStage::VerletKick => "STAGE_VERLET_KICK",
+            UpdateStage::Rk2Half => "STAGE_RK2_HALF",
+            UpdateStage::Rk2Full => "STAGE_RK2_FULL",
+            UpdateStage::Thermostat => "STAGE_THERMOSTAT",
+        }
+    }
+
+    /// Whether the stage evaluates forces, and so needs an up to date neighbor grid.
+    pub fn uses_forces(self) -> bool {
+        self != UpdateStage::VerletDrift && self != UpdateStage::Thermostat
+    }
+}
+
+/// Compile time settings of the `update.comp` pipelines.
+#[derive(Debug, Clone, Copy)]
+pub struct UpdateOptions {
+    /// Invocations per workgroup, at most `MAX_WORKGROUP_SIZE`.
+    pub workgroup_size: u32,
+    /// Stages the unlimited range force loop through workgroup shared memory.
+    pub tiled_forces: bool,
+}
+
+impl Default for UpdateOptions {
+    fn default() -> Self {
+        Self {
+            workgroup_size: 64,
+            tiled_forces: false,
+        }
+    }
+}
+
+/// Physical properties of a single particle.
+/// Laid out as a `vec4` in `update.comp`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParticleAttributes {
+    /// Divides the forces acting on the particle and weighs its collisions, must be positive.
+    pub mass: f32,
+    /// Fraction of the global `particle_radius`, positive and at most the uniforms'
+    /// `max_radius_scale` the neighbor grid is sized for.
+    pub radius: f32,
+    /// Signed charge, like charges repel according to `charge_strength`.
+    pub charge: f32,
+    padding: f32,
+}
+
+impl ParticleAttributes {
+    pub fn new(mass: f32, radius: f32, charge: f32) -> Self {
+        ParticleAttributes {
+            mass,
+            radius,
+            charge,
+            padding: 0.0,
+        }
+    }
+}
+
+/// A neutral particle of unit mass at the full `particle_radius`.
+impl Default for ParticleAttributes {
+    fn default() -> Self {
+        ParticleAttributes::new(1.0, 1.0, 0.0)
+    }
+}
+
+/// A CPU side copy of every particle's state, including the dead slots.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParticleState {
+    pub positions: Vec<Point2>,
+    pub velocities: Vec<Point2>,
+    pub species: Vec<u32>,
+    pub attributes: Vec<ParticleAttributes>,
+    pub alive: Vec<bool>,
+    /// `MAX_BONDS` slots per particle.
+    pub bonds: Vec<Bond>,
+}
+
+impl ParticleState {
+    /// Positions sampled from `distribution` with random velocities in [-1, 1].
+    pub fn sample<R: Rng>(
+        distribution: &InitialDistribution,
+        particle_count: u32,
+        species: &SpeciesConfig,
+        rng: &mut R,
+    ) -> Result<Self, DistributionError> {
+        let positions = distribution.sample(particle_count, rng)?;
+        let velocities = (0..particle_count)
+            .map(|_| pt2(rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0)))
+            .collect();
+
+        let species = species.assign(particle_count, rng);
+
+        Ok(Self {
+            positions,
+            velocities,
+            species,
+            attributes: vec![ParticleAttributes::default(); particle_count as usize],
+            alive: vec![true; particle_count as usize],
+            bonds: vec![Bond::NONE; (particle_count * MAX_BONDS) as usize],
+        })
+    }
+
+    /// Appends dead particles until the state holds `capacity` slots.
+    pub fn with_capacity(mut self, capacity: u32) -> Self {
+        let capacity = capacity as usize;
+        assert!(
+            self.len() <= capacity,
+            "particle state exceeds the capacity"
+        );
+
+        self.positions.resize(capacity, pt2(0.0, 0.0));
+        self.velocities.resize(capacity, pt2(0.0, 0.0));
+        self.species.resize(capacity, 0);
+        self.attributes
+            .resize(capacity, ParticleAttributes::default());
+        self.alive.resize(capacity, false);
+        self.bonds.resize(capacity * MAX_BONDS as usize, Bond::NONE);
+        self
+    }
+
+    /// Number of slots, alive or dead.
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn live_count(&self) -> usize {
+        self.alive.iter().filter(|alive| **alive).count()
+    }
 }
 
 impl ParticleSystem {
-    pub fn new(
-        app: &App,
+    /// Samples the initial state with `rng`, seed it for a reproducible run.
+    /// Fails when `distribution` can't place `population.initial_count` particles.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<R: Rng>(
+        shaders_dir: &Path,
         device: &wgpu::Device,
         uniforms: &UniformBuffer,
-        max_radius: f32,
-    ) -> Self {
-        let mut positions = vec![];
-        let mut velocities = vec![];
-
-        for _ in 0..uniforms.data.particle_count {
-            let position_angle =
-                rand::thread_rng().gen_range(-std::f32::consts::PI, std::f32::consts::PI);
-            let position_radius = rand::thread_rng().gen_range(0.0, max_radius);
-            let position_x = position_radius * position_angle.cos();
-            let position_y = position_radius * position_angle.sin();
-            let position = pt2(position_x, position_y);
-            positions.push(position);
+        distribution: &InitialDistribution,
+        species: &SpeciesConfig,
+        population: &PopulationConfig,
+        environment: &EnvironmentConfig,
+        options: UpdateOptions,
+        rng: &mut R,
+    ) -> Result<Self, DistributionError> {
+        let state = ParticleState::sample(distribution, population.initial_count, species, rng)?
+            .with_capacity(uniforms.data.particle_capacity);
+        Ok(Self::from_state(
+            shaders_dir,
+            device,
+            uniforms,
+            &state,
+            species,
+            population,
+            environment,
+            options,
+        ))
+    }
 
-            let velocity_x = rand::thread_rng().gen_range(-1.0, 1.0);
-            let velocity_y = rand::thread_rng().gen_range(-1.0, 1.0);
-            velocities.push(pt2(velocity_x, velocity_y));
-        }
+    /// Creates the particle system from an existing state,
+    /// which must hold `uniforms.data.particle_capacity` slots.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_state(
+        shaders_dir: &Path,
+        device: &wgpu::Device,
+        uniforms: &UniformBuffer,
+        state: &ParticleState,
+        species: &SpeciesConfig,
+        population: &PopulationConfig,
+        environment: &EnvironmentConfig,
+        options: UpdateOptions,
+    ) -> Self {
+        assert_eq!(
+            state.len(),
+            uniforms.data.particle_capacity as usize,
+            "particle state does not match the particle capacity"
+        );
+        assert_eq!(
+            (population.emitters.len(), population.sinks.len()),
+            (
+                uniforms.data.emitter_count as usize,
+                uniforms.data.sink_count as usize
+            ),
+            "emitter and sink counts do not match the uniforms"
+        );
+        assert_eq!(
+            (environment.obstacles.len(), environment.fields.len()),
+            (
+                uniforms.data.obstacle_count as usize,
+                uniforms.data.field_count as usize
+            ),
+            "obstacle and field counts do not match the uniforms"
+        );
+        assert!(
+            !ForceKernel::from_uint(uniforms.data.force_kernel).needs_finite_ranges()
+                || (uniforms.data.attraction_range > 0.0 && uniforms.data.repulsion_range > 0.0),
+            "the gaussian and linear ramp kernels need finite ranges, not 0 (unlimited)"
+        );
+        assert!(
+            state
+                .attributes
+                .iter()
+                .zip(&state.alive)
+                .filter(|(_, alive)| **alive)
+                .map(|(attributes, _)| attributes)
+                .chain(population.emitters.iter().map(|e| &e.attributes))
+                .all(|a| a.radius > 0.0 && a.radius <= uniforms.data.max_radius_scale),
+            "particle radii must be positive and at most max_radius_scale"
+        );
 
-        let position_bytes = vectors_as_byte_vec(&positions);
-        let velocity_bytes = vectors_as_byte_vec(&velocities);
+        let position_bytes = vectors_as_byte_vec(&state.positions);
+        let velocity_bytes = vectors_as_byte_vec(&state.velocities);
+        let species_bytes = uints_as_byte_vec(&state.species);
 
         // Create the buffers that will store the result of our compute operation.
-        let buffer_size = (uniforms.data.particle_count as usize * std::mem::size_of::<Point2>())
+        let buffer_size = (uniforms.data.particle_capacity as usize * std::mem::size_of::<Point2>())
+            as wgpu::BufferAddress;
+        let species_buffer_size = (uniforms.data.particle_capacity as usize
+            * std::mem::size_of::<u32>()) as wgpu::BufferAddress;
+        let integrator_buffer_size = (uniforms.data.particle_capacity as usize
+            * std::mem::size_of::<Vec4>())
             as wgpu::BufferAddress;
+        let attribute_buffer_size = (uniforms.data.particle_capacity as usize
+            * std::mem::size_of::<ParticleAttributes>())
+            as wgpu::BufferAddress;
+        let species_rule_buffer_size =
+            (species.rules.len() * std::mem::size_of::<SpeciesRule>()) as wgpu::BufferAddress;
+
+        let position_buffers = [
+            create_state_buffer(device, "position-buffer-0", &position_bytes),
+            create_state_buffer(device, "position-buffer-1", &position_bytes),
+        ];
+        let velocity_buffers = [
+            create_state_buffer(device, "velocity-buffer-0", &velocity_bytes),
+            create_state_buffer(device, "velocity-buffer-1", &velocity_bytes),
+        ];
 
-        let position_in_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
-            label: Some("position-in-buffer"),
-            contents: &position_bytes[..],
+        // Species only change when particles spawn, which happens in place,
+        // so a single buffer serves as both input and output.
+        let species_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
+            label: Some("species-buffer"),
+            contents: &species_bytes[..],
             usage: wgpu::BufferUsages::STORAGE
                 | wgpu::BufferUsages::COPY_DST
                 | wgpu::BufferUsages::COPY_SRC,
         });
 
-        let position_out_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
-            label: Some("position-out-buffer"),
-            contents: &position_bytes[..],
-            usage: wgpu::BufferUsages::STORAGE
-                | wgpu::BufferUsages::COPY_DST
-                | wgpu::BufferUsages::COPY_SRC,
+        let species_rule_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
+            label: Some("species-rule-buffer"),
+            contents: rules_as_bytes(&species.rules),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // storage buffers can't be empty, unused entries are skipped by obstacle_count and field_count
+        let obstacles = if environment.obstacles.is_empty() {
+            vec![Obstacle::circle(pt2(0.0, 0.0), 0.0)]
+        } else {
+            environment.obstacles.clone()
+        };
+        let fields = if environment.fields.is_empty() {
+            vec![ForceField::gravity(vec2(0.0, -1.0), 0.0)]
+        } else {
+            environment.fields.clone()
+        };
+        let obstacle_buffer_size =
+            (obstacles.len() * std::mem::size_of::<Obstacle>()) as wgpu::BufferAddress;
+        let obstacle_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
+            label: Some("obstacle-buffer"),
+            contents: obstacles_as_bytes(&obstacles),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let field_buffer_size =
+            (fields.len() * std::mem::size_of::<ForceField>()) as wgpu::BufferAddress;
+        let field_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
+            label: Some("field-buffer"),
+            contents: fields_as_bytes(&fields),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // one uint per slot, like the species
+        let cluster_buffer_size = species_buffer_size;
+        let cluster_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
+            label: Some("cluster-buffer"),
+            contents: &uints_as_byte_vec(&vec![NO_CLUSTER; state.len()]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         });
 
-        let velocity_in_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
-            label: Some("velocity-in-buffer"),
-            contents: &velocity_bytes[..],
+        let attribute_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
+            label: Some("attribute-buffer"),
+            contents: attributes_as_bytes(&state.attributes),
             usage: wgpu::BufferUsages::STORAGE
                 | wgpu::BufferUsages::COPY_DST
                 | wgpu::BufferUsages::COPY_SRC,
         });
 
-        let velocity_out_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
-            label: Some("velocity-out-buffer"),
-            contents: &velocity_bytes[..],
+        // Scratch state the integrators carry between stages, starts out zeroed.
+        let integrator_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("integrator-buffer"),
+            size: integrator_buffer_size,
             usage: wgpu::BufferUsages::STORAGE
                 | wgpu::BufferUsages::COPY_DST
                 | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
         });
 
-        // Create the compute shader module.
-        let update_cs_mod =
-            compile_shader(app, device, "update.comp", shaderc::ShaderKind::Compute);
+        let population_buffer_sets: Vec<Vec<&wgpu::Buffer>> = (0..2)
+            .map(|i| {
+                vec![
+                    &position_buffers[i],
+                    &velocity_buffers[i],
+                    &species_buffer,
+                    &integrator_buffer,
+                    &attribute_buffer,
+                ]
+            })
+            .collect();
+        let population_system = Population::new(
+            shaders_dir,
+            device,
+            uniforms,
+            population,
+            &state.alive,
+            &population_buffer_sets,
+            &[
+                buffer_size,
+                buffer_size,
+                species_buffer_size,
+                integrator_buffer_size,
+                attribute_buffer_size,
+            ],
+        );
 
-        let buffers = vec![
-            &position_in_buffer,
-            &position_out_buffer,
-            &velocity_in_buffer,
-            &velocity_out_buffer,
-        ];
-        let buffer_sizes = vec![buffer_size, buffer_size, buffer_size, buffer_size];
+        let grid = NeighborGrid::new(
+            shaders_dir,
+            device,
+            uniforms,
+            &position_buffers,
+            buffer_size,
+            &population_system.alive_buffer,
+            population_system.alive_buffer_size,
+        );
 
-        let compute = Compute::new::<Uniforms>(
+        let quadtree_leaves = NeighborGrid::quadtree_leaves(
+            shaders_dir,
             device,
-            Some(buffers),
-            Some(buffer_sizes),
-            Some(&uniforms.buffer),
-            &update_cs_mod,
-        )
-        .unwrap();
+            uniforms,
+            &position_buffers,
+            buffer_size,
+            &population_system.alive_buffer,
+            population_system.alive_buffer_size,
+        );
+        let quadtree_buffer_sets: Vec<Vec<&wgpu::Buffer>> = position_buffers
+            .iter()
+            .map(|positions| vec![positions, &species_buffer, &attribute_buffer])
+            .collect();
+        let quadtree = Quadtree::new(
+            shaders_dir,
+            device,
+            uniforms,
+            quadtree_leaves,
+            &quadtree_buffer_sets,
+            &[buffer_size, species_buffer_size, attribute_buffer_size],
+        );
+
+        let bond_buffer_sets: Vec<Vec<&wgpu::Buffer>> = position_buffers
+            .iter()
+            .map(|positions| {
+                vec![
+                    positions,
+                    &population_system.alive_buffer,
+                    &grid.cell_end_buffer,
+                    &grid.sorted_index_buffer,
+                ]
+            })
+            .collect();
+        let bonds = Bonds::new(
+            shaders_dir,
+            device,
+            uniforms,
+            &state.bonds,
+            &bond_buffer_sets,
+            &[
+                buffer_size,
+                population_system.alive_buffer_size,
+                grid.cell_buffer_size,
+                grid.index_buffer_size,
+            ],
+        );
+
+        let diagnostics_buffer_sets: Vec<Vec<&wgpu::Buffer>> = (0..2)
+            .map(|i| {
+                vec![
+                    &position_buffers[i],
+                    &velocity_buffers[i],
+                    &population_system.alive_buffer,
+                    &attribute_buffer,
+                ]
+            })
+            .collect();
+        let diagnostics = DiagnosticsReduction::new(
+            shaders_dir,
+            device,
+            uniforms,
+            &diagnostics_buffer_sets,
+            &[
+                buffer_size,
+                buffer_size,
+                population_system.alive_buffer_size,
+                attribute_buffer_size,
+            ],
+        );
+
+        // in and out swap roles between the two buffer sets
+        let buffer_sets: Vec<Vec<&wgpu::Buffer>> = (0..2)
+            .map(|i| {
+                vec![
+                    &position_buffers[i],
+                    &position_buffers[1 - i],
+                    &velocity_buffers[i],
+                    &velocity_buffers[1 - i],
+                    &grid.cell_end_buffer,
+                    &grid.sorted_index_buffer,
+                    &species_buffer,
+                    &species_rule_buffer,
+                    &integrator_buffer,
+                    &population_system.alive_buffer,
+                    &attribute_buffer,
+                    &obstacle_buffer,
+                    &field_buffer,
+                    &bonds.bond_buffer,
+                    &quadtree.leaves.cell_end_buffer,
+                    &quadtree.leaves.sorted_index_buffer,
+                    &quadtree.node_buffer,
+                ]
+            })
+            .collect();
+        let buffer_sizes = vec![
+            buffer_size,
+            buffer_size,
+            buffer_size,
+            buffer_size,
+            grid.cell_buffer_size,
+            grid.index_buffer_size,
+            species_buffer_size,
+            species_rule_buffer_size,
+            integrator_buffer_size,
+            population_system.alive_buffer_size,
+            attribute_buffer_size,
+            obstacle_buffer_size,
+            field_buffer_size,
+            bonds.bond_buffer_size,
+            quadtree.leaves.cell_buffer_size,
+            quadtree.leaves.index_buffer_size,
+            quadtree.node_buffer_size,
+        ];
+
+        let workgroup_size = format!("WORKGROUP_SIZE={}", options.workgroup_size);
+
+        // Create a compute pipeline for each stage's shader variant.
+        let stages = UpdateStage::ALL
+            .iter()
+            .map(|stage| {
+                let mut defines = vec![stage.define(), workgroup_size.as_str()];
+                if options.tiled_forces {
+                    defines.push("TILED_FORCES");
+                }
+
+                let update_cs_mod = compile_shader_variant(
+                    shaders_dir,
+                    device,
+                    "update.comp",
+                    shaderc::ShaderKind::Compute,
+                    &defines,
+                );
+
+                Compute::with_buffer_sets::<Uniforms>(
+                    device,
+                    buffer_sets.clone(),
+                    Some(buffer_sizes.clone()),
+                    Some(&uniforms.buffer),
+                    &update_cs_mod,
+                )
+                .unwrap()
+                .with_workgroup_size(options.workgroup_size)
+            })
+            .collect();
 
         Self {
-            position_in_buffer,
-            position_out_buffer,
-            velocity_in_buffer,
-            velocity_out_buffer,
+            position_buffers,
+            velocity_buffers,
+            species_buffer,
+            integrator_buffer,
+            attribute_buffer,
+            obstacle_buffer,
+            cluster_buffer,
             buffer_size,
-            initial_positions: positions,
-            compute,
-            particle_count: uniforms.data.particle_count,
+            species_buffer_size,
+            attribute_buffer_size,
+            obstacle_buffer_size,
+            cluster_buffer_size,
+            initial_positions: state.positions.clone(),
+            stages,
+            grid,
+            quadtree,
+            population: population_system,
+            bonds,
+            diagnostics,
+            history: None,
+            particle_capacity: uniforms.data.particle_capacity,
+            dynamic_population: population.is_dynamic(),
+            species_config: species.clone(),
+            population_config: population.clone(),
+            environment_config: environment.clone(),
+            current: 0,
+        }
+    }
+
+    /// Encodes the population and bond passes, all substeps of one frame
+    /// using the integrator selected in `uniforms`, and the thermostat.
+    /// Every stage swaps the ping-pong buffers, so the latest state alternates between them.
+    pub fn update(&mut self, encoder: &mut CommandEncoder, uniforms: &Uniforms) {
+        let integrator = Integrator::from_uint(uniforms.integrator);
+        let unlimited = uniforms.attraction_range == 0.0 || uniforms.repulsion_range == 0.0;
+        let barnes_hut = unlimited && uniforms.barnes_hut_theta > 0.0;
+
+        if self.dynamic_population {
+            self.population.update(encoder, self.current);
+        }
+
+        // bonds form from neighbors found through the grid
+        let mut listed = false;
+        if uniforms.bond_capture_distance > 0.0 {
+            self.grid.update(encoder, self.current);
+            self.bonds.update(encoder, self.current);
+            listed = true;
+        }
+
+        for _ in 0..uniforms.substeps.max(1) {
+            for stage in UpdateStage::for_integrator(integrator) {
+                // every stage runs over the grid's list of live particles,
+                // which only changes with the population at the start of the frame
+                if stage.uses_forces() || !listed {
+                    self.grid.update(encoder, self.current);
+                    listed = true;
+                }
+                if stage.uses_forces() && barnes_hut {
+                    self.quadtree.update(encoder, self.current);
+                }
+
+                self.stages[*stage as usize].compute_with(
+                    encoder,
+                    self.current,
+                    self.particle_capacity,
+                );
+                self.current = 1 - self.current;
+            }
+        }
+
+        if Thermostat::from_uint(uniforms.thermostat) != Thermostat::Off {
+            self.stages[UpdateStage::Thermostat as usize].compute_with(
+                encoder,
+                self.current,
+                self.particle_capacity,
+            );
+            self.current = 1 - self.current;
+        }
+    }
+
+    /// Rebuilds the grid the renderer looks particles up in from the current positions,
+    /// after an `update` moved them or the state changed without one.
+    pub fn list_live(&self, encoder: &mut CommandEncoder) {
+        self.grid.update(encoder, self.current);
+    }
+
+    /// Keeps the last `length` snapshots recorded by `record_history` on the GPU.
+    pub fn with_history(mut self, device: &wgpu::Device, length: usize) -> Self {
+        let sizes: Vec<u64> = self
+            .history_buffers()
+            .iter()
+            .map(|(_, size)| *size)
+            .collect();
+        self.history = Some(History::new(device, length, &sizes));
+        self
+    }
+
+    /// Every buffer a snapshot holds, with its size: the latest positions and velocities,
+    /// species, attributes, integrator state, alive flags, live count and bonds.
+    /// Bond candidates are left out, they only delay forming bonds.
+    fn history_buffers(&self) -> Vec<(&wgpu::Buffer, u64)> {
+        let integrator_buffer_size =
+            self.particle_capacity as u64 * std::mem::size_of::<Vec4>() as u64;
+        vec![
+            (self.current_positions(), self.buffer_size),
+            (self.current_velocities(), self.buffer_size),
+            (&self.species_buffer, self.species_buffer_size),
+            (&self.attribute_buffer, self.attribute_buffer_size),
+            (&self.integrator_buffer, integrator_buffer_size),
+            (
+                &self.population.alive_buffer,
+                self.population.alive_buffer_size,
+            ),
+            (
+                &self.population.count_buffer,
+                self.population.count_buffer_size,
+            ),
+            (&self.bonds.bond_buffer, self.bonds.bond_buffer_size),
+        ]
+    }
+
+    /// Encodes a snapshot of the latest state, labeled with `frame` and `time`.
+    /// Does nothing without `with_history`.
+    pub fn record_history(&mut self, encoder: &mut CommandEncoder, frame: u32, time: f32) {
+        if let Some(mut history) = self.history.take() {
+            let sources: Vec<&wgpu::Buffer> =
+                self.history_buffers().iter().map(|(b, _)| *b).collect();
+            history.record(encoder, &sources, HistoryEntry { frame, time });
+            self.history = Some(history);
+        }
+    }
+
+    /// Encodes a copy of the snapshot `age` snapshots before the latest over the latest state,
+    /// which the simulation continues from. Returns when the snapshot was taken,
+    /// or `None` without changing anything when there is no such snapshot.
+    pub fn restore_history(
+        &self,
+        encoder: &mut CommandEncoder,
+        age: usize,
+    ) -> Option<HistoryEntry> {
+        let targets: Vec<&wgpu::Buffer> = self.history_buffers().iter().map(|(b, _)| *b).collect();
+        let entry = self.history.as_ref()?.restore(encoder, age, &targets)?;
+        self.list_live(encoder);
+        Some(entry)
+    }
+
+    /// Copies the snapshot `age` snapshots before the latest back from the GPU,
+    /// blocking until it is available.
+    pub fn read_history(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        age: usize,
+    ) -> Option<(HistoryEntry, ParticleState)> {
+        let history = self.history.as_ref()?;
+        let entry = history.entry(age)?;
+        let buffers = history.read(device, queue, age)?;
+        let state = ParticleState {
+            positions: byte_vec_as_vectors(&buffers[0]),
+            velocities: byte_vec_as_vectors(&buffers[1]),
+            species: byte_vec_as_uints(&buffers[2]),
+            attributes: byte_vec_as_attributes(&buffers[3]),
+            alive: byte_vec_as_uints(&buffers[5])
+                .into_iter()
+                .map(|alive| alive != 0)
+                .collect(),
+            bonds: byte_vec_as_bonds(&buffers[7]),
+        };
+        Some((entry, state))
+    }
+
+    /// Index of the ping-pong buffers holding the latest state,
+    /// which is also the buffer set to render with.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// The position buffer holding the latest state.
+    pub fn current_positions(&self) -> &wgpu::Buffer {
+        &self.position_buffers[self.current]
+    }
+
+    /// The velocity buffer holding the latest state.
+    pub fn current_velocities(&self) -> &wgpu::Buffer {
+        &self.velocity_buffers[self.current]
+    }
+
+    /// Copies the current state back from the GPU, blocking until it is available.
+    pub fn read_state(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> ParticleState {
+        ParticleState {
+            positions: byte_vec_as_vectors(&read_buffer(
+                device,
+                queue,
+                self.current_positions(),
+                self.buffer_size,
+            )),
+            velocities: byte_vec_as_vectors(&read_buffer(
+                device,
+                queue,
+                self.current_velocities(),
+                self.buffer_size,
+            )),
+            species: byte_vec_as_uints(&read_buffer(
+                device,
+                queue,
+                &self.species_buffer,
+                self.species_buffer_size,
+            )),
+            attributes: byte_vec_as_attributes(&read_buffer(
+                device,
+                queue,
+                &self.attribute_buffer,
+                self.attribute_buffer_size,
+            )),
+            alive: byte_vec_as_uints(&read_buffer(
+                device,
+                queue,
+                &self.population.alive_buffer,
+                self.population.alive_buffer_size,
+            ))
+            .into_iter()
+            .map(|alive| alive != 0)
+            .collect(),
+            bonds: self.bonds.read_bonds(device, queue),
         }
     }
 
-    pub fn update(&self, encoder: &mut CommandEncoder) {
-        self.compute.compute(encoder, self.particle_count);
+    /// Copies the current state back from the GPU into a checkpoint of the run
+    /// with the given `seed` and `uniforms`, blocking until it is available.
+    pub fn read_checkpoint(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        seed: u64,
+        uniforms: &Uniforms,
+    ) -> Checkpoint {
+        let integrator_buffer_size =
+            self.particle_capacity as u64 * std::mem::size_of::<Vec4>() as u64;
+        Checkpoint {
+            seed,
+            uniforms: *uniforms,
+            state: self.read_state(device, queue),
+            integrator: read_buffer(
+                device,
+                queue,
+                &self.integrator_buffer,
+                integrator_buffer_size,
+            ),
+            candidates: byte_vec_as_candidates(&read_buffer(
+                device,
+                queue,
+                &self.bonds.candidate_buffer,
+                self.bonds.candidate_buffer_size,
+            )),
+            species: self.species_config.clone(),
+            population: self.population_config.clone(),
+            environment: self.environment_config.clone(),
+        }
+    }
+
+    /// Uploads the particles of `checkpoint` over the latest state, which the simulation
+    /// continues from once the queue is next submitted. The uniforms are left to the caller,
+    /// and so is `list_live` if the state is drawn before the next `update`.
+    /// Fails without changing anything unless the checkpoint was saved by a system
+    /// of the same capacity, species, population and environment.
+    pub fn load_checkpoint(
+        &self,
+        queue: &wgpu::Queue,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), CheckpointError> {
+        let state = &checkpoint.state;
+        let capacity = self.particle_capacity as usize;
+        if checkpoint.uniforms.particle_capacity != self.particle_capacity
+            || state.len() != capacity
+            || checkpoint.candidates.len() != capacity
+        {
+            return Err(CheckpointError::Mismatch("particle capacity"));
+        }
+        if checkpoint.uniforms.species_count != self.species_config.count
+            || checkpoint.species != self.species_config
+        {
+            return Err(CheckpointError::Mismatch("species"));
+        }
+        if checkpoint.population != self.population_config {
+            return Err(CheckpointError::Mismatch("population"));
+        }
+        if checkpoint.environment != self.environment_config {
+            return Err(CheckpointError::Mismatch("environment"));
+        }
+
+        let alive: Vec<u32> = state.alive.iter().map(|alive| *alive as u32).collect();
+        let live_count = state.live_count() as u32;
+        queue.write_buffer(
+            self.current_positions(),
+            0,
+            &vectors_as_byte_vec(&state.positions),
+        );
+        queue.write_buffer(
+            self.current_velocities(),
+            0,
+            &vectors_as_byte_vec(&state.velocities),
+        );
+        queue.write_buffer(&self.species_buffer, 0, &uints_as_byte_vec(&state.species));
+        queue.write_buffer(
+            &self.attribute_buffer,
+            0,
+            attributes_as_bytes(&state.attributes),
+        );
+        queue.write_buffer(&self.integrator_buffer, 0, &checkpoint.integrator);
+        queue.write_buffer(&self.population.alive_buffer, 0, &uints_as_byte_vec(&alive));
+        queue.write_buffer(
+            &self.population.count_buffer,
+            0,
+            &uints_as_byte_vec(&[live_count, 0, 0]),
+        );
+        queue.write_buffer(&self.bonds.bond_buffer, 0, bonds_as_bytes(&state.bonds));
+        queue.write_buffer(
+            &self.bonds.candidate_buffer,
+            0,
+            candidates_as_bytes(&checkpoint.candidates),
+        );
+
+        Ok(())
+    }
+
+    /// Encodes a diagnostics reduction of the latest state, see `DiagnosticsReduction::encode`.
+    pub fn encode_diagnostics(
+        &mut self,
+        encoder: &mut CommandEncoder,
+        frame: u32,
+        time: f32,
+    ) -> bool {
+        self.diagnostics.encode(encoder, self.current, frame, time)
+    }
+
+    /// Uploads the cluster labels of every slot for coloring, see `ClusterStats::labels`.
+    pub fn write_clusters(&self, queue: &wgpu::Queue, labels: &[u32]) {
+        assert_eq!(
+            labels.len(),
+            self.particle_capacity as usize,
+            "cluster labels do not match the particle capacity"
+        );
+        queue.write_buffer(&self.cluster_buffer, 0, &uints_as_byte_vec(labels));
+    }
+
+    /// Reads the live particle count back from the GPU, blocking until it is available.
+    pub fn read_particle_count(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> u32 {
+        self.population.read_count(device, queue)
     }
 }
 
+pub(crate) fn create_state_buffer(
+    device: &wgpu::Device,
+    label: &str,
+    contents: &[u8],
+) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::BufferInitDescriptor {
+        label: Some(label),
+        contents,
+        usage: wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC,
+    })
+}
+
 pub fn float_as_bytes(data: &f32) -> &[u8] {
     unsafe { wgpu::bytes::from(data) }
 }
@@ -131,3 +966,43 @@ pub fn vectors_as_byte_vec(data: &[Point2]) -> Vec<u8> {
     });
     bytes
 }
+
+pub fn uints_as_byte_vec(data: &[u32]) -> Vec<u8> {
+    let mut bytes = vec![];
+    data.iter().for_each(|v| bytes.extend(v.to_ne_bytes()));
+    bytes
+}
+
+pub fn byte_vec_as_vectors(bytes: &[u8]) -> Vec<Point2> {
+    bytes
+        .chunks_exact(8)
+        .map(|b| {
+            pt2(
+                f32::from_ne_bytes([b[0], b[1], b[2], b[3]]),
+                f32::from_ne_bytes([b[4], b[5], b[6], b[7]]),
+            )
+        })
+        .collect()
+}
+
+/// See the `nannou::wgpu::bytes` documentation for why this is necessary.
+pub fn attributes_as_bytes(data: &[ParticleAttributes]) -> &[u8] {
+    unsafe { wgpu::bytes::from_slice(data) }
+}
+
+pub fn byte_vec_as_attributes(bytes: &[u8]) -> Vec<ParticleAttributes> {
+    bytes
+        .chunks_exact(16)
+        .map(|b| {
+            let float = |i: usize| f32::from_ne_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+            ParticleAttributes::new(float(0), float(4), float(8))
+        })
+        .collect()
+}
+
+pub fn byte_vec_as_uints(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
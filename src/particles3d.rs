@@ -0,0 +1,302 @@
+//! A 3D variant of the simulation, stepped by `update3d.comp` and drawn by `shader3d.frag`.
+//! It shares the uniforms, species rules and force kernels with the 2D system, but every
+//! particle interacts with every other and there are no obstacles, fields, cursor, bonds,
+//! population changes or thermostats.
+
+use nannou::prelude::*;
+use nannou::wgpu::CommandEncoder;
+use rand::Rng;
+use std::path::Path;
+
+use crate::compute::*;
+use crate::particles::*;
+use crate::species::*;
+use crate::uniforms::*;
+use crate::util::*;
+
+/// A CPU side copy of every particle's state in 3D.
+#[derive(Debug, Clone, Default)]
+pub struct ParticleState3d {
+    pub positions: Vec<Vec3>,
+    pub velocities: Vec<Vec3>,
+    pub species: Vec<u32>,
+    pub attributes: Vec<ParticleAttributes>,
+}
+
+impl ParticleState3d {
+    /// Positions uniform in a ball of `radius` around the origin,
+    /// with random velocities in [-1, 1].
+    pub fn sample<R: Rng>(
+        particle_count: u32,
+        radius: f32,
+        species: &SpeciesConfig,
+        rng: &mut R,
+    ) -> Self {
+        let mut random_vector = || {
+            vec3(
+                rng.gen_range(-1.0, 1.0),
+                rng.gen_range(-1.0, 1.0),
+                rng.gen_range(-1.0, 1.0),
+            )
+        };
+
+        // rejection sampling from the enclosing cube
+        let positions = (0..particle_count)
+            .map(|_| loop {
+                let p = random_vector();
+                if p.length_squared() <= 1.0 {
+                    break p * radius;
+                }
+            })
+            .collect();
+        let velocities = (0..particle_count).map(|_| random_vector()).collect();
+
+        let species = species.assign(particle_count, rng);
+
+        Self {
+            positions,
+            velocities,
+            species,
+            attributes: vec![ParticleAttributes::default(); particle_count as usize],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+}
+
+pub struct ParticleSystem3d {
+    /// Ping-pong position buffers of `vec4`s with an unused w.
+    pub position_buffers: [wgpu::Buffer; 2],
+    /// Ping-pong velocity buffers, swapped together with the positions.
+    pub velocity_buffers: [wgpu::Buffer; 2],
+    pub species_buffer: wgpu::Buffer,
+    pub attribute_buffer: wgpu::Buffer,
+    pub buffer_size: u64,
+    pub species_buffer_size: u64,
+    pub attribute_buffer_size: u64,
+    /// Bind group `i` reads from buffers `i` and writes to the others.
+    pub update: Compute,
+    pub particle_capacity: u32,
+    /// Index of the buffers holding the latest state.
+    current: usize,
+}
+
+impl ParticleSystem3d {
+    /// `state` must hold `uniforms.data.particle_capacity` particles, all of them alive.
+    pub fn from_state(
+        shaders_dir: &Path,
+        device: &wgpu::Device,
+        uniforms: &UniformBuffer,
+        state: &ParticleState3d,
+        species: &SpeciesConfig,
+        workgroup_size: u32,
+    ) -> Self {
+        let particle_capacity = uniforms.data.particle_capacity;
+        assert_eq!(
+            state.len(),
+            particle_capacity as usize,
+            "particle state does not match the particle capacity"
+        );
+        assert!(
+            !ForceKernel::from_uint(uniforms.data.force_kernel).needs_finite_ranges()
+                || (uniforms.data.attraction_range > 0.0 && uniforms.data.repulsion_range > 0.0),
+            "the gaussian and linear ramp kernels need finite ranges, not 0 (unlimited)"
+        );
+
+        let position_bytes = vectors3_as_byte_vec(&state.positions);
+        let velocity_bytes = vectors3_as_byte_vec(&state.velocities);
+        let species_bytes = uints_as_byte_vec(&state.species);
+
+        let buffer_size = position_bytes.len() as wgpu::BufferAddress;
+        let species_buffer_size = species_bytes.len() as wgpu::BufferAddress;
+        let attribute_buffer_size = std::mem::size_of_val(&state.attributes[..]) as u64;
+        let species_rule_buffer_size =
+            (species.rules.len() * std::mem::size_of::<SpeciesRule>()) as wgpu::BufferAddress;
+
+        let position_buffers = [
+            create_state_buffer(device, "position-buffer-3d-0", &position_bytes),
+            create_state_buffer(device, "position-buffer-3d-1", &position_bytes),
+        ];
+        let velocity_buffers = [
+            create_state_buffer(device, "velocity-buffer-3d-0", &velocity_bytes),
+            create_state_buffer(device, "velocity-buffer-3d-1", &velocity_bytes),
+        ];
+        let species_buffer = create_state_buffer(device, "species-buffer-3d", &species_bytes);
+        let attribute_buffer = create_state_buffer(
+            device,
+            "attribute-buffer-3d",
+            attributes_as_bytes(&state.attributes),
+        );
+        let species_rule_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
+            label: Some("species-rule-buffer-3d"),
+            contents: rules_as_bytes(&species.rules),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let buffer_sets: Vec<Vec<&wgpu::Buffer>> = (0..2)
+            .map(|i| {
+                vec![
+                    &position_buffers[i],
+                    &position_buffers[1 - i],
+                    &velocity_buffers[i],
+                    &velocity_buffers[1 - i],
+                    &species_buffer,
+                    &species_rule_buffer,
+                    &attribute_buffer,
+                ]
+            })
+            .collect();
+
+        let define = format!("WORKGROUP_SIZE={}", workgroup_size);
+        let cs_mod = compile_shader_variant(
+            shaders_dir,
+            device,
+            "update3d.comp",
+            shaderc::ShaderKind::Compute,
+            &[define.as_str()],
+        );
+        let update = Compute::with_buffer_sets::<Uniforms>(
+            device,
+            buffer_sets,
+            Some(vec![
+                buffer_size,
+                buffer_size,
+                buffer_size,
+                buffer_size,
+                species_buffer_size,
+                species_rule_buffer_size,
+                attribute_buffer_size,
+            ]),
+            Some(&uniforms.buffer),
+            &cs_mod,
+        )
+        .unwrap()
+        .with_workgroup_size(workgroup_size);
+
+        Self {
+            position_buffers,
+            velocity_buffers,
+            species_buffer,
+            attribute_buffer,
+            buffer_size,
+            species_buffer_size,
+            attribute_buffer_size,
+            update,
+            particle_capacity,
+            current: 0,
+        }
+    }
+
+    /// Encodes one frame of the simulation, `uniforms.substeps` passes of `update3d.comp`.
+    pub fn update(&mut self, encoder: &mut CommandEncoder, uniforms: &Uniforms) {
+        for _ in 0..uniforms.substeps.max(1) {
+            self.update
+                .compute_with(encoder, self.current, self.particle_capacity);
+            self.current = 1 - self.current;
+        }
+    }
+
+    /// Index of the ping-pong buffers holding the latest state,
+    /// which is also the buffer set to render with.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// The position buffer holding the latest state.
+    pub fn current_positions(&self) -> &wgpu::Buffer {
+        &self.position_buffers[self.current]
+    }
+
+    /// Copies the latest state back from the GPU, blocking until it is available.
+    pub fn read_state(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> ParticleState3d {
+        ParticleState3d {
+            positions: byte_vec_as_vectors3(&read_buffer(
+                device,
+                queue,
+                self.current_positions(),
+                self.buffer_size,
+            )),
+            velocities: byte_vec_as_vectors3(&read_buffer(
+                device,
+                queue,
+                &self.velocity_buffers[self.current],
+                self.buffer_size,
+            )),
+            species: byte_vec_as_uints(&read_buffer(
+                device,
+                queue,
+                &self.species_buffer,
+                self.species_buffer_size,
+            )),
+            attributes: byte_vec_as_attributes(&read_buffer(
+                device,
+                queue,
+                &self.attribute_buffer,
+                self.attribute_buffer_size,
+            )),
+        }
+    }
+}
+
+/// Pads every vector to a `vec4`, the std430 stride of `vec3`.
+pub fn vectors3_as_byte_vec(data: &[Vec3]) -> Vec<u8> {
+    let mut bytes = vec![];
+    data.iter().for_each(|v| {
+        bytes.extend(float_as_bytes(&v.x));
+        bytes.extend(float_as_bytes(&v.y));
+        bytes.extend(float_as_bytes(&v.z));
+        bytes.extend(float_as_bytes(&0.0));
+    });
+    bytes
+}
+
+pub fn byte_vec_as_vectors3(bytes: &[u8]) -> Vec<Vec3> {
+    bytes
+        .chunks_exact(16)
+        .map(|b| {
+            let float = |i: usize| f32::from_ne_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+            vec3(float(0), float(4), float(8))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn round_trips_padded_vectors() {
+        let vectors = vec![vec3(1.0, -2.0, 3.5), vec3(0.0, 0.25, -1e6)];
+        let bytes = vectors3_as_byte_vec(&vectors);
+        assert_eq!(bytes.len(), vectors.len() * 16);
+        assert_eq!(&bytes[12..16], &0.0f32.to_ne_bytes());
+        assert_eq!(byte_vec_as_vectors3(&bytes), vectors);
+    }
+
+    #[test]
+    fn samples_a_ball() {
+        let species = SpeciesConfig::preset("chase").unwrap();
+        let state = ParticleState3d::sample(200, 50.0, &species, &mut StdRng::seed_from_u64(1));
+
+        assert_eq!(state.len(), 200);
+        assert_eq!((state.velocities.len(), state.attributes.len()), (200, 200));
+        assert!(state.positions.iter().all(|p| p.length() <= 50.0));
+        assert!(state
+            .velocities
+            .iter()
+            .all(|v| v.abs().max_element() <= 1.0));
+        assert!(state.species.iter().all(|s| *s < species.count));
+
+        // the same seed samples the same state
+        let again = ParticleState3d::sample(200, 50.0, &species, &mut StdRng::seed_from_u64(1));
+        assert_eq!(again.positions, state.positions);
+    }
+}
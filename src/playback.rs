@@ -0,0 +1,186 @@
+//! Pausing, single-stepping and changing the speed of the simulation, apart from rendering.
+
+/// Most steps per rendered frame when fast-forwarding,
+/// and most rendered frames per step in slow motion.
+pub const MAX_PLAYBACK_FACTOR: u32 = 64;
+
+/// Decides how many simulation steps each rendered frame runs.
+/// Fast-forwarding runs several steps per frame, slow motion one step every few frames,
+/// and while paused only the steps asked for with `step` run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Playback {
+    pub paused: bool,
+    /// Steps run by every rendered frame, 1 in slow motion.
+    pub steps_per_frame: u32,
+    /// Rendered frames per step, 1 unless in slow motion.
+    pub frames_per_step: u32,
+    /// Steps asked for while paused, run by the next frame.
+    pending_steps: u32,
+    /// Frames rendered since the last step in slow motion.
+    frames_since_step: u32,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Playback {
+            paused: false,
+            steps_per_frame: 1,
+            frames_per_step: 1,
+            pending_steps: 0,
+            frames_since_step: 0,
+        }
+    }
+}
+
+impl Playback {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_paused(mut self, paused: bool) -> Self {
+        self.paused = paused;
+        self
+    }
+
+    /// Fast-forwards, leaving slow motion.
+    pub fn with_steps_per_frame(mut self, steps: u32) -> Self {
+        self.steps_per_frame = steps.clamp(1, MAX_PLAYBACK_FACTOR);
+        self.frames_per_step = 1;
+        self
+    }
+
+    /// Slows down to one step every `frames` rendered frames, leaving fast-forward.
+    pub fn with_frames_per_step(mut self, frames: u32) -> Self {
+        self.frames_per_step = frames.clamp(1, MAX_PLAYBACK_FACTOR);
+        self.steps_per_frame = 1;
+        self
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.pending_steps = 0;
+    }
+
+    /// Pauses and runs a single step on the next frame.
+    pub fn step(&mut self) {
+        self.paused = true;
+        self.pending_steps += 1;
+    }
+
+    /// Doubles the speed, leaving slow motion before fast-forwarding.
+    pub fn faster(&mut self) {
+        if self.frames_per_step > 1 {
+            self.frames_per_step /= 2;
+        } else {
+            self.steps_per_frame = (self.steps_per_frame * 2).min(MAX_PLAYBACK_FACTOR);
+        }
+    }
+
+    /// Halves the speed, leaving fast-forward before slowing down.
+    pub fn slower(&mut self) {
+        if self.steps_per_frame > 1 {
+            self.steps_per_frame /= 2;
+        } else {
+            self.frames_per_step = (self.frames_per_step * 2).min(MAX_PLAYBACK_FACTOR);
+        }
+    }
+
+    /// The number of steps to run this frame, call once per rendered frame.
+    pub fn advance(&mut self) -> u32 {
+        if self.paused {
+            return std::mem::take(&mut self.pending_steps);
+        }
+
+        self.frames_since_step += 1;
+        if self.frames_since_step < self.frames_per_step {
+            return 0;
+        }
+        self.frames_since_step = 0;
+        self.steps_per_frame
+    }
+
+    /// A line for the HUD, empty at normal speed.
+    pub fn summary(&self) -> String {
+        if self.paused {
+            "paused".to_string()
+        } else if self.frames_per_step > 1 {
+            format!("slow motion: 1/{}x", self.frames_per_step)
+        } else if self.steps_per_frame > 1 {
+            format!("fast-forward: {}x", self.steps_per_frame)
+        } else {
+            String::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Steps run by each of the next `frames` frames.
+    fn steps(playback: &mut Playback, frames: usize) -> Vec<u32> {
+        (0..frames).map(|_| playback.advance()).collect()
+    }
+
+    #[test]
+    fn runs_one_step_per_frame_by_default() {
+        let mut playback = Playback::new();
+        assert_eq!(steps(&mut playback, 3), vec![1, 1, 1]);
+        assert_eq!(playback.summary(), "");
+    }
+
+    #[test]
+    fn steps_while_paused() {
+        let mut playback = Playback::new().with_paused(true);
+        assert_eq!(steps(&mut playback, 2), vec![0, 0]);
+        assert_eq!(playback.summary(), "paused");
+
+        playback.step();
+        playback.step();
+        assert_eq!(steps(&mut playback, 2), vec![2, 0]);
+
+        // resuming forgets steps asked for but not yet run
+        playback.step();
+        playback.toggle_pause();
+        assert_eq!(steps(&mut playback, 1), vec![1]);
+
+        // stepping pauses a running simulation
+        playback.step();
+        assert!(playback.paused);
+        assert_eq!(steps(&mut playback, 2), vec![1, 0]);
+    }
+
+    #[test]
+    fn changes_speed_through_normal_speed() {
+        let mut playback = Playback::new().with_frames_per_step(4);
+        assert_eq!(steps(&mut playback, 8), vec![0, 0, 0, 1, 0, 0, 0, 1]);
+        assert_eq!(playback.summary(), "slow motion: 1/4x");
+
+        playback.faster();
+        assert_eq!(playback.frames_per_step, 2);
+        playback.faster();
+        playback.faster();
+        assert_eq!((playback.frames_per_step, playback.steps_per_frame), (1, 2));
+        assert_eq!(steps(&mut playback, 2), vec![2, 2]);
+        assert_eq!(playback.summary(), "fast-forward: 2x");
+
+        playback.slower();
+        playback.slower();
+        assert_eq!((playback.frames_per_step, playback.steps_per_frame), (2, 1));
+    }
+
+    #[test]
+    fn clamps_the_speed() {
+        let mut playback = Playback::new().with_steps_per_frame(1000);
+        assert_eq!(playback.steps_per_frame, MAX_PLAYBACK_FACTOR);
+        playback.faster();
+        assert_eq!(playback.steps_per_frame, MAX_PLAYBACK_FACTOR);
+
+        let mut playback = Playback::new().with_frames_per_step(0);
+        assert_eq!(playback.frames_per_step, 1);
+        for _ in 0..10 {
+            playback.slower();
+        }
+        assert_eq!(playback.frames_per_step, MAX_PLAYBACK_FACTOR);
+    }
+}
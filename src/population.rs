@@ -0,0 +1,290 @@
+use nannou::prelude::*;
+use nannou::wgpu::CommandEncoder;
+use std::path::Path;
+
+use crate::compute::*;
+use crate::particles::{byte_vec_as_uints, uints_as_byte_vec, ParticleAttributes};
+use crate::uniforms::*;
+use crate::util::*;
+
+/// Invocations per workgroup of `population.comp`, must match its `local_size_x`.
+pub const POPULATION_WORKGROUP_SIZE: u32 = 64;
+
+/// A disk that spawns particles into dead slots of the particle buffers.
+/// Laid out as the `Emitter` struct of `population.comp`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Emitter {
+    pub center: Point2,
+    pub radius: f32,
+    /// Particles spawned per unit of time, as long as there are dead slots to fill.
+    pub rate: f32,
+    /// Mean velocity of spawned particles.
+    pub velocity: Point2,
+    /// Radius of the disk the velocity of spawned particles is spread over.
+    pub velocity_spread: f32,
+    pub species: u32,
+    pub attributes: ParticleAttributes,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Emitter {
+            center: pt2(0.0, 0.0),
+            radius: 10.0,
+            rate: 0.0,
+            velocity: pt2(0.0, 0.0),
+            velocity_spread: 1.0,
+            species: 0,
+            attributes: ParticleAttributes::default(),
+        }
+    }
+}
+
+/// A disk that removes every particle entering it.
+/// Laid out as a `vec4` in `population.comp`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sink {
+    pub center: Point2,
+    pub radius: f32,
+    padding: f32,
+}
+
+impl Sink {
+    pub fn new(center: Point2, radius: f32) -> Self {
+        Sink {
+            center,
+            radius,
+            padding: 0.0,
+        }
+    }
+
+    pub fn contains(&self, position: Point2) -> bool {
+        position.distance(self.center) <= self.radius
+    }
+}
+
+/// How many particles start out alive, and where particles enter and leave the simulation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PopulationConfig {
+    /// Particles alive at the start, the rest of the capacity starts out dead.
+    pub initial_count: u32,
+    pub emitters: Vec<Emitter>,
+    pub sinks: Vec<Sink>,
+}
+
+impl PopulationConfig {
+    /// A fixed population of `initial_count` particles without emitters or sinks.
+    pub fn new(initial_count: u32) -> Self {
+        PopulationConfig {
+            initial_count,
+            emitters: vec![],
+            sinks: vec![],
+        }
+    }
+
+    pub fn with_emitters(mut self, emitters: Vec<Emitter>) -> Self {
+        self.emitters = emitters;
+        self
+    }
+
+    pub fn with_sinks(mut self, sinks: Vec<Sink>) -> Self {
+        self.sinks = sinks;
+        self
+    }
+
+    /// Largest radius attribute of the spawned particles, 0 without emitters.
+    pub fn max_radius_scale(&self) -> f32 {
+        self.emitters
+            .iter()
+            .map(|e| e.attributes.radius)
+            .fold(0.0, f32::max)
+    }
+
+    /// Whether the population can change at all, otherwise the population passes are skipped.
+    pub fn is_dynamic(&self) -> bool {
+        !self.emitters.is_empty() || !self.sinks.is_empty()
+    }
+
+    /// Validates the config against the size of the particle buffers and the species.
+    pub fn validated(self, particle_capacity: u32, species_count: u32) -> Self {
+        assert!(
+            self.initial_count <= particle_capacity,
+            "the initial particle count must fit in the capacity"
+        );
+        assert!(
+            self.emitters.iter().all(|e| e.species < species_count),
+            "emitters must spawn one of the configured species"
+        );
+        assert!(
+            self.emitters.iter().all(|e| e.rate >= 0.0),
+            "emitter rates can not be negative"
+        );
+
+        self
+    }
+}
+
+/// Removes particles inside sinks and spawns emitted ones into dead slots, once per frame.
+/// Slots never move, so particles keep their index for as long as they live.
+/// The live particle count is kept in `count_buffer`, as `particle_count` of `population.comp`.
+pub struct Population {
+    /// One uint per particle, 0 for dead slots.
+    pub alive_buffer: wgpu::Buffer,
+    /// The live particle count followed by the spawn and removal counters of the current frame.
+    pub count_buffer: wgpu::Buffer,
+    pub alive_buffer_size: u64,
+    pub count_buffer_size: u64,
+    spawn: Compute,
+    count: Compute,
+    particle_capacity: u32,
+}
+
+impl Population {
+    /// `state_buffer_sets` holds, for each ping-pong position, the position, velocity, species,
+    /// integrator and attribute buffers, which the population pass edits in place.
+    pub fn new(
+        shaders_dir: &Path,
+        device: &wgpu::Device,
+        uniforms: &UniformBuffer,
+        config: &PopulationConfig,
+        alive: &[bool],
+        state_buffer_sets: &[Vec<&wgpu::Buffer>],
+        state_buffer_sizes: &[u64],
+    ) -> Self {
+        let particle_capacity = uniforms.data.particle_capacity;
+        let uint_size = std::mem::size_of::<u32>() as u64;
+        let alive_buffer_size = particle_capacity as u64 * uint_size;
+        let count_buffer_size = 3 * uint_size;
+
+        let alive: Vec<u32> = alive.iter().map(|a| *a as u32).collect();
+        let live_count = alive.iter().sum::<u32>();
+
+        let alive_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
+            label: Some("alive-buffer"),
+            contents: &uints_as_byte_vec(&alive),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let count_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
+            label: Some("population-count-buffer"),
+            contents: &uints_as_byte_vec(&[live_count, 0, 0]),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        // storage buffers can't be empty, unused entries are skipped by emitter_count and sink_count
+        let emitters = if config.emitters.is_empty() {
+            vec![Emitter::default()]
+        } else {
+            config.emitters.clone()
+        };
+        let sinks = if config.sinks.is_empty() {
+            vec![Sink::new(pt2(0.0, 0.0), 0.0)]
+        } else {
+            config.sinks.clone()
+        };
+        let emitter_buffer_size = (emitters.len() * std::mem::size_of::<Emitter>()) as u64;
+        let sink_buffer_size = (sinks.len() * std::mem::size_of::<Sink>()) as u64;
+
+        let emitter_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
+            label: Some("emitter-buffer"),
+            contents: emitters_as_bytes(&emitters),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let sink_buffer = device.create_buffer_init(&wgpu::BufferInitDescriptor {
+            label: Some("sink-buffer"),
+            contents: sinks_as_bytes(&sinks),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let spawn_cs_mod = compile_shader_in(
+            shaders_dir,
+            device,
+            "population.comp",
+            shaderc::ShaderKind::Compute,
+        );
+        let count_cs_mod = compile_shader_in(
+            shaders_dir,
+            device,
+            "population_count.comp",
+            shaderc::ShaderKind::Compute,
+        );
+
+        let buffer_sets = state_buffer_sets
+            .iter()
+            .map(|buffers| {
+                let mut buffers = buffers.clone();
+                buffers.extend([&alive_buffer, &count_buffer, &emitter_buffer, &sink_buffer]);
+                buffers
+            })
+            .collect();
+        let mut buffer_sizes = state_buffer_sizes.to_vec();
+        buffer_sizes.extend([
+            alive_buffer_size,
+            count_buffer_size,
+            emitter_buffer_size,
+            sink_buffer_size,
+        ]);
+
+        let spawn = Compute::with_buffer_sets::<Uniforms>(
+            device,
+            buffer_sets,
+            Some(buffer_sizes),
+            Some(&uniforms.buffer),
+            &spawn_cs_mod,
+        )
+        .unwrap()
+        .with_workgroup_size(POPULATION_WORKGROUP_SIZE);
+
+        let count = Compute::new::<Uniforms>(
+            device,
+            Some(vec![&count_buffer]),
+            Some(vec![count_buffer_size]),
+            None,
+            &count_cs_mod,
+        )
+        .unwrap();
+
+        Self {
+            alive_buffer,
+            count_buffer,
+            alive_buffer_size,
+            count_buffer_size,
+            spawn,
+            count,
+            particle_capacity,
+        }
+    }
+
+    /// Spawns and removes particles in the given ping-pong buffers, then updates the live count.
+    pub fn update(&self, encoder: &mut CommandEncoder, buffers: usize) {
+        self.spawn
+            .compute_with(encoder, buffers, self.particle_capacity);
+        self.count.compute(encoder, 1);
+    }
+
+    /// Reads the live particle count back from the GPU, blocking until it is available.
+    pub fn read_count(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> u32 {
+        byte_vec_as_uints(&read_buffer(
+            device,
+            queue,
+            &self.count_buffer,
+            self.count_buffer_size,
+        ))[0]
+    }
+}
+
+/// See the `nannou::wgpu::bytes` documentation for why this is necessary.
+pub fn emitters_as_bytes(data: &[Emitter]) -> &[u8] {
+    unsafe { wgpu::bytes::from_slice(data) }
+}
+
+/// See the `nannou::wgpu::bytes` documentation for why this is necessary.
+pub fn sinks_as_bytes(data: &[Sink]) -> &[u8] {
+    unsafe { wgpu::bytes::from_slice(data) }
+}
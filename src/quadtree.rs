@@ -0,0 +1,185 @@
+use nannou::prelude::*;
+use nannou::wgpu::CommandEncoder;
+use std::path::Path;
+
+use crate::compute::*;
+use crate::grid::*;
+use crate::uniforms::*;
+use crate::util::*;
+
+/// Invocations per workgroup of the quadtree passes, must match `QUADTREE_WORKGROUP_SIZE`
+/// in `quadtree.glsl`.
+pub const QUADTREE_WORKGROUP_SIZE: u32 = 64;
+
+/// The leaves are binned by the grid's counting sort, which handles at most `MAX_GRID_CELLS`.
+pub const MAX_QUADTREE_DEPTH: u32 = 8;
+
+/// Picks a depth of roughly one leaf per particle.
+pub fn default_quadtree_depth(particle_capacity: u32) -> u32 {
+    let leaves = particle_capacity.max(1).next_power_of_two();
+    leaves
+        .trailing_zeros()
+        .div_ceil(2)
+        .clamp(1, MAX_QUADTREE_DEPTH)
+}
+
+pub fn quadtree_leaf_count(depth: u32) -> u32 {
+    1 << (2 * depth)
+}
+
+/// Nodes on all levels from the root down to the leaves.
+pub fn quadtree_node_count(depth: u32) -> u32 {
+    (quadtree_leaf_count(depth + 1) - 1) / 3
+}
+
+/// A complete quadtree over the domain for the Barnes-Hut approximation of unlimited range
+/// forces, rebuilt from scratch whenever the forces are evaluated: particles are binned
+/// into leaves with the grid's counting sort, the leaves are summed,
+/// and every level is reduced from the one below, see `quadtree.glsl` for the layout.
+pub struct Quadtree {
+    /// Bins live particles by leaf, its cell ends and sorted indices list every leaf's particles.
+    pub leaves: NeighborGrid,
+    pub node_buffer: wgpu::Buffer,
+    pub node_buffer_size: u64,
+    summarize: Compute,
+    /// One pass per level above the leaves, indexed by level.
+    reduce: Vec<Compute>,
+    depth: u32,
+}
+
+impl Quadtree {
+    /// `leaves` must come from `NeighborGrid::quadtree_leaves`.
+    /// `state_buffer_sets` holds, for each ping-pong position, the position, species
+    /// and attribute buffers.
+    pub fn new(
+        shaders_dir: &Path,
+        device: &wgpu::Device,
+        uniforms: &UniformBuffer,
+        leaves: NeighborGrid,
+        state_buffer_sets: &[Vec<&wgpu::Buffer>],
+        state_buffer_sizes: &[u64],
+    ) -> Self {
+        let depth = uniforms.data.quadtree_depth;
+        assert!(
+            (1..=MAX_QUADTREE_DEPTH).contains(&depth),
+            "quadtree depth must be in 1..={}",
+            MAX_QUADTREE_DEPTH
+        );
+
+        let node_stride = (uniforms.data.species_count + 1) as u64;
+        let node_buffer_size =
+            quadtree_node_count(depth) as u64 * node_stride * std::mem::size_of::<Vec4>() as u64;
+        let node_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("quadtree-node-buffer"),
+            size: node_buffer_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let buffer_sets: Vec<Vec<&wgpu::Buffer>> = state_buffer_sets
+            .iter()
+            .map(|buffers| {
+                let mut buffers = buffers.clone();
+                buffers.extend([
+                    &leaves.cell_end_buffer,
+                    &leaves.sorted_index_buffer,
+                    &node_buffer,
+                ]);
+                buffers
+            })
+            .collect();
+        let mut buffer_sizes = state_buffer_sizes.to_vec();
+        buffer_sizes.extend([
+            leaves.cell_buffer_size,
+            leaves.index_buffer_size,
+            node_buffer_size,
+        ]);
+
+        let summarize_cs_mod = compile_shader_in(
+            shaders_dir,
+            device,
+            "quadtree_leaves.comp",
+            shaderc::ShaderKind::Compute,
+        );
+        let summarize = Compute::with_buffer_sets::<Uniforms>(
+            device,
+            buffer_sets,
+            Some(buffer_sizes),
+            Some(&uniforms.buffer),
+            &summarize_cs_mod,
+        )
+        .unwrap()
+        .with_workgroup_size(QUADTREE_WORKGROUP_SIZE);
+
+        let reduce = (0..depth)
+            .map(|level| {
+                let define = format!("QUADTREE_LEVEL={}", level);
+                let cs_mod = compile_shader_variant(
+                    shaders_dir,
+                    device,
+                    "quadtree_reduce.comp",
+                    shaderc::ShaderKind::Compute,
+                    &[define.as_str()],
+                );
+
+                Compute::new::<Uniforms>(
+                    device,
+                    Some(vec![&node_buffer]),
+                    Some(vec![node_buffer_size]),
+                    Some(&uniforms.buffer),
+                    &cs_mod,
+                )
+                .unwrap()
+                .with_workgroup_size(QUADTREE_WORKGROUP_SIZE)
+            })
+            .collect();
+
+        Self {
+            leaves,
+            node_buffer,
+            node_buffer_size,
+            summarize,
+            reduce,
+            depth,
+        }
+    }
+
+    /// Rebuilds the tree from the given ping-pong buffers, bottom up.
+    pub fn update(&self, encoder: &mut CommandEncoder, buffers: usize) {
+        self.leaves.update(encoder, buffers);
+        self.summarize
+            .compute_with(encoder, buffers, quadtree_leaf_count(self.depth));
+        for level in (0..self.depth).rev() {
+            self.reduce[level as usize].compute(encoder, quadtree_leaf_count(level));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_the_tree_for_one_leaf_per_particle() {
+        assert_eq!(default_quadtree_depth(0), 1);
+        assert_eq!(default_quadtree_depth(4), 1);
+        assert_eq!(default_quadtree_depth(256), 4);
+        assert_eq!(default_quadtree_depth(257), 5);
+        assert_eq!(default_quadtree_depth(1000), 5);
+        assert_eq!(default_quadtree_depth(1 << 24), MAX_QUADTREE_DEPTH);
+    }
+
+    #[test]
+    fn counts_the_nodes_of_every_level() {
+        assert_eq!(quadtree_leaf_count(0), 1);
+        assert_eq!(quadtree_leaf_count(3), 64);
+        assert_eq!(quadtree_node_count(0), 1);
+        assert_eq!(quadtree_node_count(2), 1 + 4 + 16);
+        assert_eq!(
+            quadtree_node_count(MAX_QUADTREE_DEPTH),
+            (0..=MAX_QUADTREE_DEPTH)
+                .map(quadtree_leaf_count)
+                .sum::<u32>()
+        );
+    }
+}
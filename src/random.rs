@@ -0,0 +1,37 @@
+//! Stateless random numbers, identical to `random.glsl` on the GPU.
+
+use nannou::prelude::*;
+
+/// lowbias32 integer hash
+pub fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    x
+}
+
+/// Folds a run's seed into the 32 bit `seed` uniform.
+pub fn gpu_seed(seed: u64) -> u32 {
+    (seed ^ (seed >> 32)) as u32
+}
+
+/// Uniform random number in [0, 1) for a particle and stream, changing every frame and with the seed.
+pub fn random(seed: u32, index: u32, frame: u32, stream: u32) -> f32 {
+    (hash(hash(hash(hash(index) ^ seed) ^ frame) ^ stream) >> 8) as f32 / 16777216.0
+}
+
+/// Uniform random point in the unit disk, using `stream` and `stream + 1`.
+pub fn random_in_disk(seed: u32, index: u32, frame: u32, stream: u32) -> Point2 {
+    let angle = random(seed, index, frame, stream) * 2.0 * PI;
+    let radius = random(seed, index, frame, stream + 1).sqrt();
+    vec2(angle.cos(), angle.sin()) * radius
+}
+
+/// Standard normal random point, using `stream` and `stream + 1` (Box-Muller).
+pub fn random_normal(seed: u32, index: u32, frame: u32, stream: u32) -> Point2 {
+    let u = 1.0 - random(seed, index, frame, stream);
+    let angle = random(seed, index, frame, stream + 1) * 2.0 * PI;
+    vec2(angle.cos(), angle.sin()) * (-2.0 * u.ln()).sqrt()
+}
@@ -30,7 +30,8 @@ pub enum RendererError {
 }
 
 pub struct CustomRenderer {
-    bind_group: wgpu::BindGroup,
+    /// One bind group per buffer set, all sharing the same layout.
+    bind_groups: Vec<wgpu::BindGroup>,
     render_pipeline: wgpu::RenderPipeline,
     pub output_texture: wgpu::Texture,
     pub texture_reshaper: wgpu::TextureReshaper,
@@ -39,6 +40,7 @@ pub struct CustomRenderer {
 
 /// A render pipeline generator for a fragment shader with optional textures, sampler, and uniform buffer
 impl CustomRenderer {
+    #[allow(clippy::too_many_arguments)]
     pub fn new<T>(
         device: &wgpu::Device,
         vs_mod: &wgpu::ShaderModule,
@@ -53,50 +55,79 @@ impl CustomRenderer {
         texture_sample_count: u32,
         device_sample_count: u32,
     ) -> Result<Self, RendererError>
+    where
+        T: Copy,
+    {
+        let buffer_sets = buffers.map(|b| vec![b.clone()]).unwrap_or_default();
+        Self::with_buffer_sets::<T>(
+            device,
+            vs_mod,
+            fs_mod,
+            &buffer_sets,
+            buffer_sizes,
+            uniform_textures,
+            sampler,
+            uniform_buffer,
+            width,
+            height,
+            texture_sample_count,
+            device_sample_count,
+        )
+    }
+
+    /// Like `new`, but creates one bind group per set of buffers,
+    /// so that `render_with` can draw from buffers that swap roles between frames.
+    /// Every set must match `buffer_sizes`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_buffer_sets<T>(
+        device: &wgpu::Device,
+        vs_mod: &wgpu::ShaderModule,
+        fs_mod: &wgpu::ShaderModule,
+        buffer_sets: &[Vec<&wgpu::Buffer>],
+        buffer_sizes: Option<&Vec<&wgpu::BufferAddress>>,
+        uniform_textures: Option<&Vec<&wgpu::Texture>>,
+        sampler: Option<&wgpu::Sampler>,
+        uniform_buffer: Option<&wgpu::Buffer>,
+        width: u32,
+        height: u32,
+        texture_sample_count: u32,
+        device_sample_count: u32,
+    ) -> Result<Self, RendererError>
     where
         T: Copy,
     {
         println!("creating bind group");
 
         let mut bind_group_layout_builder = wgpu::BindGroupLayoutBuilder::new();
-        let mut bind_group_builder = wgpu::BindGroupBuilder::new();
 
-        if let Some(b) = buffers {
+        let buffer_count = buffer_sets.first().map_or(0, |b| b.len());
+        if buffer_count > 0 {
             if let Some(s) = buffer_sizes {
-                if b.len() != s.len() {
+                if buffer_sets.iter().any(|b| b.len() != s.len()) {
                     return Err(RendererError::BufferCountAndBufferSizeCountMismatch);
                 }
 
                 let storage_dynamic = false;
                 let storage_readonly = false;
 
-                for (i, buffer) in b.iter().enumerate() {
-                    let buffer_size = *s[i];
-
+                for _ in 0..buffer_count {
                     bind_group_layout_builder = bind_group_layout_builder.storage_buffer(
                         wgpu::ShaderStages::FRAGMENT,
                         storage_dynamic,
                         storage_readonly,
                     );
-
-                    let buffer_size_bytes = std::num::NonZeroU64::new(buffer_size).unwrap();
-                    bind_group_builder =
-                        bind_group_builder.buffer_bytes(buffer, 0, Some(buffer_size_bytes));
                 }
             } else {
                 return Err(RendererError::MissingBufferSizes);
             }
         }
 
-        let texture_views = match uniform_textures {
-            Some(textures) => Some(
-                textures
-                    .iter()
-                    .map(|t| t.view().build())
-                    .collect::<Vec<wgpu::TextureView>>(),
-            ),
-            None => None,
-        };
+        let texture_views = uniform_textures.map(|textures| {
+            textures
+                .iter()
+                .map(|t| t.view().build())
+                .collect::<Vec<wgpu::TextureView>>()
+        });
 
         if let Some(textures) = uniform_textures {
             for t in textures.iter() {
@@ -107,30 +138,57 @@ impl CustomRenderer {
                     t.sample_type(),
                 )
             }
-
-            if let Some(views) = texture_views.as_ref() {
-                for v in views {
-                    bind_group_builder = bind_group_builder.texture_view(v);
-                }
-            }
         }
 
-        if let Some(ref s) = sampler {
+        if sampler.is_some() {
             bind_group_layout_builder =
                 bind_group_layout_builder.sampler(wgpu::ShaderStages::FRAGMENT, true);
-
-            bind_group_builder = bind_group_builder.sampler(s);
         }
 
-        if let Some(ref buffer) = uniform_buffer {
+        if uniform_buffer.is_some() {
             bind_group_layout_builder =
                 bind_group_layout_builder.uniform_buffer(wgpu::ShaderStages::FRAGMENT, false);
-
-            bind_group_builder = bind_group_builder.buffer::<T>(buffer, 0..1);
         }
 
         let bind_group_layout = bind_group_layout_builder.build(device);
-        let bind_group = bind_group_builder.build(device, &bind_group_layout);
+
+        // a renderer without buffers still needs its textures and uniforms bound
+        let no_buffers = [vec![]];
+        let buffer_sets = if buffer_sets.is_empty() {
+            &no_buffers[..]
+        } else {
+            buffer_sets
+        };
+
+        let bind_groups = buffer_sets
+            .iter()
+            .map(|buffers| {
+                let mut bind_group_builder = wgpu::BindGroupBuilder::new();
+
+                for (i, buffer) in buffers.iter().enumerate() {
+                    let buffer_size = *buffer_sizes.unwrap()[i];
+                    let buffer_size_bytes = std::num::NonZeroU64::new(buffer_size).unwrap();
+                    bind_group_builder =
+                        bind_group_builder.buffer_bytes(buffer, 0, Some(buffer_size_bytes));
+                }
+
+                if let Some(views) = texture_views.as_ref() {
+                    for v in views {
+                        bind_group_builder = bind_group_builder.texture_view(v);
+                    }
+                }
+
+                if let Some(s) = sampler {
+                    bind_group_builder = bind_group_builder.sampler(s);
+                }
+
+                if let Some(buffer) = uniform_buffer {
+                    bind_group_builder = bind_group_builder.buffer::<T>(buffer, 0..1);
+                }
+
+                bind_group_builder.build(device, &bind_group_layout)
+            })
+            .collect();
 
         println!("creating pipeline layout");
         let pipeline_layout = create_pipeline_layout(device, &bind_group_layout);
@@ -139,16 +197,16 @@ impl CustomRenderer {
         let render_pipeline = create_render_pipeline(
             device,
             &pipeline_layout,
-            &vs_mod,
-            &fs_mod,
+            vs_mod,
+            fs_mod,
             texture_sample_count,
         );
 
         println!("creating texture and reshaper");
 
-        let output_texture = create_app_texture(&device, width, height, texture_sample_count);
+        let output_texture = create_app_texture(device, width, height, texture_sample_count);
         let texture_reshaper = create_texture_reshaper(
-            &device,
+            device,
             &output_texture,
             texture_sample_count,
             device_sample_count,
@@ -164,7 +222,7 @@ impl CustomRenderer {
         });
 
         Ok(Self {
-            bind_group,
+            bind_groups,
             render_pipeline,
             output_texture,
             texture_reshaper,
@@ -173,11 +231,16 @@ impl CustomRenderer {
     }
 
     pub fn render(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.render_with(encoder, 0);
+    }
+
+    /// Renders using the bind group of the given buffer set.
+    pub fn render_with(&self, encoder: &mut wgpu::CommandEncoder, buffer_set: usize) {
         let texture_view = self.output_texture.view().build();
         let mut render_pass = wgpu::RenderPassBuilder::new()
             .color_attachment(&texture_view, |color| color)
             .begin(encoder);
-        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(0, &self.bind_groups[buffer_set], &[]);
         render_pass.set_pipeline(&self.render_pipeline);
         render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
         let vertex_range = 0..VERTICES.len() as u32;
@@ -230,7 +293,7 @@ fn create_pipeline_layout(
 ) -> wgpu::PipelineLayout {
     let desc = wgpu::PipelineLayoutDescriptor {
         label: Some("render-pipeline-layout"),
-        bind_group_layouts: &[&bind_group_layout],
+        bind_group_layouts: &[bind_group_layout],
         push_constant_ranges: &[],
     };
     device.create_pipeline_layout(&desc)
@@ -0,0 +1,19 @@
+// Bond storage shared by the bond passes, the update shader and the renderer.
+
+// must match `bonds::MAX_BONDS`
+#define MAX_BONDS 4u
+
+// partner of an empty bond slot, or of a particle without a bond candidate
+#define NO_BOND 0xffffffffu
+
+// a spring to another particle, stored on both ends
+struct Bond {
+    uint partner;
+    float rest_length;
+};
+
+// the nearest particle a particle could bond with, and how long it has been within reach
+struct BondCandidate {
+    uint partner;
+    float time;
+};
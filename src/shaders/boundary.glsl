@@ -0,0 +1,27 @@
+// Boundary helpers, the modes must match `uniforms::BoundaryMode`.
+// Requires the `Uniforms` block to be declared before inclusion.
+
+#define BOUNDARY_OPEN 0
+#define BOUNDARY_WRAP 1
+#define BOUNDARY_REFLECT 2
+#define BOUNDARY_RESPAWN 3
+
+// the domain is centered on the origin, like the rendered view
+vec2 domain_size() {
+    return vec2(width, height);
+}
+
+// shortest vector between two points, across the edges of a periodic domain
+vec2 minimum_image(vec2 diff) {
+    if (boundary_mode == BOUNDARY_WRAP) {
+        vec2 domain = domain_size();
+        diff -= domain * round(diff / domain);
+    }
+
+    return diff;
+}
+
+vec2 wrap_position(vec2 position) {
+    vec2 domain = domain_size();
+    return mod(position + domain * 0.5, domain) - domain * 0.5;
+}
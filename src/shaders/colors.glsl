@@ -0,0 +1,12 @@
+// Particle colors shared by the renderers.
+// Requires the `Uniforms` block to be declared before inclusion.
+
+// evenly spaced hues, white when there is only one species
+vec3 species_color(uint s) {
+    if (species_count <= 1) {
+        return vec3(1.0);
+    }
+
+    float t = float(s) / float(species_count);
+    return 0.6 + 0.4 * cos(6.28318 * (t + vec3(0.0, 0.33, 0.67)));
+}
@@ -0,0 +1,6 @@
+// Flattens the 2D dispatches `Compute` splits large invocation counts into.
+// Out of range invocations must be skipped, the last workgroups may be partly unused.
+uint invocation_index() {
+    return gl_GlobalInvocationID.y * gl_NumWorkGroups.x * gl_WorkGroupSize.x
+        + gl_GlobalInvocationID.x;
+}
@@ -0,0 +1,81 @@
+// External force fields, identical to `fields.rs` on the CPU.
+// Requires the `Uniforms` block and `random.glsl` to be included before inclusion.
+
+#define FIELD_CURL_NOISE 0u
+#define FIELD_VORTEX 1u
+#define FIELD_GRAVITY 2u
+#define FIELD_RADIAL 3u
+
+// falloff is the distance from center at which the field is down to half strength, 0 never fades
+struct ForceField {
+    uint kind;
+    float strength;
+    float falloff;
+    float scale;
+    float speed;
+    float padding;
+    vec2 center;
+    vec2 direction;
+};
+
+// pseudo random value in [-1, 1) of a noise lattice point
+float lattice_value(ivec3 cell) {
+    uint h = hash(hash(hash(uint(cell.x) ^ seed) ^ uint(cell.y)) ^ uint(cell.z));
+    return float(h >> 8) / 8388608.0 - 1.0;
+}
+
+// gradient in x and y of 3D value noise with a quintic interpolant
+vec2 value_noise_gradient(vec3 p) {
+    vec3 cell = floor(p);
+    vec3 w = p - cell;
+    vec3 u = w * w * w * (w * (w * 6.0 - 15.0) + 10.0);
+    vec3 du = 30.0 * w * w * (w * (w - 2.0) + 1.0);
+    ivec3 i = ivec3(cell);
+
+    float a = lattice_value(i + ivec3(0, 0, 0));
+    float b = lattice_value(i + ivec3(1, 0, 0));
+    float c = lattice_value(i + ivec3(0, 1, 0));
+    float d = lattice_value(i + ivec3(1, 1, 0));
+    float e = lattice_value(i + ivec3(0, 0, 1));
+    float f = lattice_value(i + ivec3(1, 0, 1));
+    float g = lattice_value(i + ivec3(0, 1, 1));
+    float h = lattice_value(i + ivec3(1, 1, 1));
+
+    float k1 = b - a;
+    float k2 = c - a;
+    float k4 = a - b - c + d;
+    float k5 = a - c - e + g;
+    float k6 = a - b - e + f;
+    float k7 = -a + b + c - d + e - f - g + h;
+
+    return vec2(
+        du.x * (k1 + k4 * u.y + k6 * u.z + k7 * u.y * u.z),
+        du.y * (k2 + k5 * u.z + k4 * u.x + k7 * u.z * u.x)
+    );
+}
+
+// acceleration of the field at position, regardless of mass
+vec2 field_acceleration(ForceField field, vec2 position) {
+    vec2 diff = position - field.center;
+    float dist = length(diff);
+    float fade = 1.0;
+    if (field.falloff > 0.0) {
+        float x = dist / field.falloff;
+        fade = 1.0 / (1.0 + x * x);
+    }
+
+    vec2 direction = vec2(0.0);
+    if (field.kind == FIELD_CURL_NOISE) {
+        vec3 p = vec3(position.x / field.scale, position.y / field.scale, time * field.speed);
+        vec2 gradient = value_noise_gradient(p);
+        direction = vec2(gradient.y, -gradient.x);
+    } else if (field.kind == FIELD_VORTEX && dist > 0.0) {
+        direction = vec2(-diff.y, diff.x) / dist;
+    } else if (field.kind == FIELD_GRAVITY) {
+        direction = field.direction;
+    } else if (field.kind == FIELD_RADIAL && dist > 0.0) {
+        direction = diff / dist;
+    }
+
+    return direction * field.strength * fade;
+}
@@ -0,0 +1,64 @@
+// Spatial hash helpers shared by the grid passes and the update shader.
+// Requires the `Uniforms` block and `boundary.glsl` to be declared before inclusion.
+// With QUADTREE_LEAVES defined the grid passes bin particles by quadtree leaf instead.
+
+// must match `grid::GRID_WORKGROUP_SIZE`
+#define GRID_WORKGROUP_SIZE 256
+
+// bin of dead particles, which are left out of the grid
+#define GRID_DEAD_BIN 0xffffffffu
+
+// cells are large enough that every interaction lies within the 3x3 neighborhood
+float grid_cell_size() {
+    return max(max(attraction_range, repulsion_range), particle_radius * max_radius_scale * 2.0);
+}
+
+// periodic domains need a whole number of cells per axis so cells line up across the edges
+ivec2 grid_dimensions() {
+    return max(ivec2(domain_size() / grid_cell_size()), ivec2(1));
+}
+
+ivec2 grid_wrap(ivec2 cell) {
+    if (boundary_mode == BOUNDARY_WRAP) {
+        ivec2 dimensions = grid_dimensions();
+        return cell - dimensions * ivec2(floor(vec2(cell) / vec2(dimensions)));
+    }
+
+    return cell;
+}
+
+ivec2 grid_cell(vec2 position) {
+    if (boundary_mode == BOUNDARY_WRAP) {
+        vec2 cell_size = domain_size() / vec2(grid_dimensions());
+        return grid_wrap(ivec2(floor((position + domain_size() * 0.5) / cell_size)));
+    }
+
+    return ivec2(floor(position / grid_cell_size()));
+}
+
+// hashing keeps the table finite even though the domain is unbounded
+uint grid_hash(ivec2 cell) {
+    return ((uint(cell.x) * 73856093u) ^ (uint(cell.y) * 19349663u)) % grid_cell_count;
+}
+
+#ifdef QUADTREE_LEAVES
+#include "quadtree.glsl"
+#endif
+
+// number of bins the counting sort distributes particles over
+uint grid_bin_count() {
+#ifdef QUADTREE_LEAVES
+    return quadtree_leaf_count();
+#else
+    return grid_cell_count;
+#endif
+}
+
+// bin of a live particle
+uint grid_bin(vec2 position) {
+#ifdef QUADTREE_LEAVES
+    return quadtree_leaf(position);
+#else
+    return grid_hash(grid_cell(position));
+#endif
+}
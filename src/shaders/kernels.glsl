@@ -0,0 +1,98 @@
+// Force kernels, the kernels must match `uniforms::ForceKernel`.
+// Requires the `Uniforms` block to be declared before inclusion.
+
+#define KERNEL_INVERSE_SQUARE 0
+#define KERNEL_GAUSSIAN 1
+#define KERNEL_LENNARD_JONES 2
+#define KERNEL_LINEAR_RAMP 3
+#define KERNEL_MORSE 4
+
+// Fades a force out over the last kernel_smoothing fraction of its range instead of cutting it off.
+// The global range is scaled by the species rule, a global range of 0 is unlimited.
+float cutoff(float dist, float global_range, float scale) {
+    if (global_range == 0.0) {
+        return 1.0;
+    }
+
+    float range = global_range * scale;
+    float fade = range * kernel_smoothing;
+    if (fade <= 0.0) {
+        return dist <= range ? 1.0 : 0.0;
+    }
+
+    return 1.0 - smoothstep(range - fade, range, dist);
+}
+
+// bell curve with a width of gaussian_width times the range,
+// the particle systems refuse this kernel with an unlimited range of 0
+float gaussian(float dist, float range) {
+    float width = gaussian_width * range;
+    if (width <= 0.0) {
+        return 0.0;
+    }
+
+    float x = dist / width;
+    return exp(-0.5 * x * x);
+}
+
+// Coulomb force between two particles whose charges multiply to charge_product, like charges repel.
+// Fades out at the repulsion range like the other kernels.
+float charge_force(float dist, float charge_product) {
+    return -charge_strength * charge_product / (dist * dist) * cutoff(dist, repulsion_range, 1.0);
+}
+
+// Signed magnitude of the force between two particles at distance dist, positive attracts.
+// The rule scales the global strengths (xy) and ranges (zw) for this pair of species.
+float kernel_force(float dist, vec4 rule) {
+    float attraction = attraction_strength * rule.x;
+    float repulsion = repulsion_strength * rule.y;
+    float attraction_cutoff = cutoff(dist, attraction_range, rule.z);
+    float repulsion_cutoff = cutoff(dist, repulsion_range, rule.w);
+
+    if (force_kernel == KERNEL_GAUSSIAN) {
+        return attraction * gaussian(dist, attraction_range * rule.z) * attraction_cutoff
+            - repulsion * gaussian(dist, repulsion_range * rule.w) * repulsion_cutoff;
+    }
+
+    if (force_kernel == KERNEL_LENNARD_JONES) {
+        // clamped below half of sigma, where the repulsion explodes,
+        // the r^-6 tail is scaled like attraction and the r^-12 core like repulsion
+        float r = max(dist, 0.5 * lennard_jones_sigma);
+        float s = lennard_jones_sigma / r;
+        float s6 = s * s * s * s * s * s;
+        float tail = 24.0 * lennard_jones_epsilon * s6 / r;
+        float core = 48.0 * lennard_jones_epsilon * s6 * s6 / r;
+        return tail * rule.x * attraction_cutoff - core * rule.y * repulsion_cutoff;
+    }
+
+    if (force_kernel == KERNEL_LINEAR_RAMP) {
+        // particle life: linear repulsion up to ramp_beta of the attraction range,
+        // then a triangular attraction falling back to zero at the range,
+        // which the particle systems refuse to leave unlimited
+        float range = attraction_range * rule.z;
+        if (range <= 0.0) {
+            return 0.0;
+        }
+
+        float r = dist / range;
+        if (r < ramp_beta) {
+            return repulsion * (r / ramp_beta - 1.0);
+        }
+        if (r < 1.0) {
+            return attraction * (1.0 - abs(2.0 * r - 1.0 - ramp_beta) / (1.0 - ramp_beta));
+        }
+        return 0.0;
+    }
+
+    if (force_kernel == KERNEL_MORSE) {
+        // the single exponential is scaled like attraction, the squared one like repulsion
+        float e = exp(-morse_width * (dist - morse_equilibrium));
+        float magnitude = 2.0 * morse_depth * morse_width;
+        return magnitude * e * rule.x * attraction_cutoff
+            - magnitude * e * e * rule.y * repulsion_cutoff;
+    }
+
+    // inverse square
+    float r2 = dist * dist;
+    return attraction / r2 * attraction_cutoff - repulsion / r2 * repulsion_cutoff;
+}
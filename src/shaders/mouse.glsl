@@ -0,0 +1,35 @@
+// Cursor interaction, the modes must match `uniforms::MouseMode`.
+// Requires the `Uniforms` block and `boundary.glsl` to be included before inclusion.
+
+#define MOUSE_OFF 0u
+#define MOUSE_ATTRACT 1u
+#define MOUSE_REPEL 2u
+#define MOUSE_GRAB 3u
+
+// acceleration toward or away from the cursor within mouse_radius,
+// or the spring holding grabbed particles to it
+vec2 mouse_acceleration(vec2 position, vec2 velocity) {
+    vec2 diff = minimum_image(vec2(mouse_x, mouse_y) - position);
+    float dist = length(diff);
+    if (mouse_mode == MOUSE_OFF || dist >= mouse_radius) {
+        return vec2(0.0);
+    }
+
+    if (mouse_mode == MOUSE_GRAB) {
+        // critically damped spring toward the cursor, moving along with it
+        vec2 mouse_velocity = vec2(mouse_velocity_x, mouse_velocity_y);
+        return diff * mouse_strength + (mouse_velocity - velocity) * 2.0 * sqrt(mouse_strength);
+    }
+
+    if (dist == 0.0) {
+        return vec2(0.0);
+    }
+
+    // strongest at the cursor, fading out linearly to the edge of the radius
+    float pull = mouse_strength * (1.0 - dist / mouse_radius);
+    if (mouse_mode == MOUSE_REPEL) {
+        pull = -pull;
+    }
+
+    return diff / dist * pull;
+}
@@ -0,0 +1,68 @@
+// Signed distance functions of the static obstacles, identical to `obstacles.rs` on the CPU.
+
+#define OBSTACLE_CIRCLE 0u
+#define OBSTACLE_BOX 1u
+#define OBSTACLE_CAPSULE 2u
+#define OBSTACLE_SEGMENT 3u
+
+// circles are centered at a, boxes at a with half extents b, capsules and segments run from a to b
+struct Obstacle {
+    uint shape;
+    float radius;
+    float friction;
+    float restitution;
+    vec2 a;
+    vec2 b;
+};
+
+// normalized v, or straight up for a zero vector
+vec2 safe_normalize(vec2 v) {
+    float len = length(v);
+    return len > 0.0 ? v / len : vec2(0.0, 1.0);
+}
+
+// -1 for negative values, 1 otherwise, even for zero unlike sign
+vec2 side(vec2 v) {
+    return vec2(v.x < 0.0 ? -1.0 : 1.0, v.y < 0.0 ? -1.0 : 1.0);
+}
+
+// distance from position to the segment from a to b, and the direction away from it
+float segment_distance(vec2 position, vec2 a, vec2 b, out vec2 normal) {
+    vec2 ab = b - a;
+    vec2 ap = position - a;
+    float ab2 = dot(ab, ab);
+    float t = ab2 > 0.0 ? clamp(dot(ap, ab) / ab2, 0.0, 1.0) : 0.0;
+    vec2 diff = ap - ab * t;
+    normal = safe_normalize(diff);
+    return length(diff);
+}
+
+// signed distance from position to the surface, negative inside, with the outward normal
+float obstacle_distance(Obstacle obstacle, vec2 position, out vec2 normal) {
+    if (obstacle.shape == OBSTACLE_BOX) {
+        vec2 p = position - obstacle.a;
+        vec2 d = abs(p) - obstacle.b;
+        vec2 outside = max(d, vec2(0.0));
+        vec2 sides = side(p);
+        if (d.x > 0.0 || d.y > 0.0) {
+            // outside, the normal points away from the nearest point of the box
+            normal = safe_normalize(outside) * sides;
+            return length(outside) - obstacle.radius;
+        } else if (d.x > d.y) {
+            // inside, push out through the nearest side
+            normal = vec2(sides.x, 0.0);
+            return d.x - obstacle.radius;
+        } else {
+            normal = vec2(0.0, sides.y);
+            return d.y - obstacle.radius;
+        }
+    } else if (obstacle.shape == OBSTACLE_CAPSULE) {
+        return segment_distance(position, obstacle.a, obstacle.b, normal) - obstacle.radius;
+    } else if (obstacle.shape == OBSTACLE_SEGMENT) {
+        return segment_distance(position, obstacle.a, obstacle.b, normal);
+    }
+
+    vec2 diff = position - obstacle.a;
+    normal = safe_normalize(diff);
+    return length(diff) - obstacle.radius;
+}
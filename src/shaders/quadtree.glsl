@@ -0,0 +1,42 @@
+// Layout of the Barnes-Hut quadtree built by `Quadtree`, a complete tree over the domain
+// stored level by level, each level row major with 2^level nodes per side.
+// Every node holds its bounds followed by one sum per species, see `quadtree_node`.
+// Requires the `Uniforms` block and `boundary.glsl` to be declared before inclusion.
+
+// must match `quadtree::QUADTREE_WORKGROUP_SIZE`
+#define QUADTREE_WORKGROUP_SIZE 64
+
+// traversal stack entries, 3 per level plus the root covers `quadtree::MAX_QUADTREE_DEPTH`
+#define QUADTREE_STACK_SIZE 32
+
+uint quadtree_side(uint level) {
+    return 1u << level;
+}
+
+uint quadtree_leaf_count() {
+    return 1u << (2u * quadtree_depth);
+}
+
+// index of the first node of a level, the number of nodes on all levels above it
+uint quadtree_level_offset(uint level) {
+    return ((1u << (2u * level)) - 1u) / 3u;
+}
+
+// vec4s per node: xy: bounds min, zw: bounds max,
+// then per species xy: sum of positions, z: count, w: sum of charges
+uint quadtree_node_stride() {
+    return species_count + 1u;
+}
+
+// offset into the node buffer of the node at index on level
+uint quadtree_node(uint level, uint index) {
+    return (quadtree_level_offset(level) + index) * quadtree_node_stride();
+}
+
+// leaf containing position, particles outside the domain go to the nearest leaf on the edge
+uint quadtree_leaf(vec2 position) {
+    int side = int(quadtree_side(quadtree_depth));
+    ivec2 cell = ivec2(floor((position / domain_size() + 0.5) * float(side)));
+    cell = clamp(cell, ivec2(0), ivec2(side - 1));
+    return uint(cell.y * side + cell.x);
+}
@@ -0,0 +1,31 @@
+// Stateless random numbers, identical to `random.rs` on the CPU.
+// Requires the `Uniforms` block to be declared before inclusion.
+
+// lowbias32 integer hash
+uint hash(uint x) {
+    x ^= x >> 16;
+    x *= 0x7feb352du;
+    x ^= x >> 15;
+    x *= 0x846ca68bu;
+    x ^= x >> 16;
+    return x;
+}
+
+// uniform random number in [0, 1) for a particle and stream, changing every frame and with the seed
+float random(uint index, uint stream) {
+    return float(hash(hash(hash(hash(index) ^ seed) ^ frame) ^ stream) >> 8) / 16777216.0;
+}
+
+// uniform random point in the unit disk, using stream and stream + 1
+vec2 random_in_disk(uint index, uint stream) {
+    float angle = random(index, stream) * 2.0 * 3.14159265;
+    float radius = sqrt(random(index, stream + 1u));
+    return vec2(cos(angle), sin(angle)) * radius;
+}
+
+// standard normal random point, using stream and stream + 1 (Box-Muller)
+vec2 random_normal(uint index, uint stream) {
+    float u = 1.0 - random(index, stream);
+    float angle = random(index, stream + 1u) * 2.0 * 3.14159265;
+    return vec2(cos(angle), sin(angle)) * sqrt(-2.0 * log(u));
+}
@@ -0,0 +1,19 @@
+// Workgroup wide inclusive prefix sum (Hillis-Steele) in shared memory.
+// Every invocation of the workgroup must call it.
+
+shared uint scan_values[GRID_WORKGROUP_SIZE];
+
+uint workgroup_inclusive_scan(uint value) {
+    uint local_index = gl_LocalInvocationID.x;
+    scan_values[local_index] = value;
+    barrier();
+
+    for (uint offset = 1; offset < GRID_WORKGROUP_SIZE; offset *= 2) {
+        uint addend = local_index >= offset ? scan_values[local_index - offset] : 0;
+        barrier();
+        scan_values[local_index] += addend;
+        barrier();
+    }
+
+    return scan_values[local_index];
+}
@@ -9,24 +9,33 @@
 layout(location = 0) in vec2 tex_coords;
 layout(location = 0) out vec4 f_color;
 
+#include "obstacles.glsl"
+#include "bonds.glsl"
+
 layout(set = 0, binding = 0) buffer PositionBuffer { vec2[] positions; };
-layout(set = 0, binding = 1) uniform Uniforms {
-    uint particle_count;
-    float width;
-    float height;
-    float speed;
-    float attraction_strength;
-    float repulsion_strength;
-    float attraction_range;
-    float repulsion_range;
-    float center_strength;
-    float particle_radius;
-    float collision_response;
-    float momentum;
-    float max_acceleration;
-    float max_velocity;
+layout(set = 0, binding = 1) buffer SpeciesBuffer { uint[] species; };
+layout(set = 0, binding = 2) buffer AliveBuffer { uint[] alive; };
+// x: mass, y: radius as a fraction of particle_radius, z: charge
+layout(set = 0, binding = 3) buffer AttributeBuffer { vec4[] attributes; };
+layout(set = 0, binding = 4) buffer ObstacleBuffer { Obstacle[] obstacles; };
+// MAX_BONDS slots per particle
+layout(set = 0, binding = 5) buffer BondBuffer { Bond[] bonds; };
+// cluster label of every slot, the lowest index of its members
+layout(set = 0, binding = 6) buffer ClusterBuffer { uint[] clusters; };
+// ends of the neighbor grid's cells, built from the positions being drawn
+layout(set = 0, binding = 7) buffer CellEndBuffer { uint[] cell_ends; };
+// the live particles in grid order
+layout(set = 0, binding = 8) buffer SortedIndexBuffer { uint[] sorted_indices; };
+layout(set = 0, binding = 9) uniform Uniforms {
+#include "uniforms.glsl"
 };
 
+#include "boundary.glsl"
+#include "grid.glsl"
+#include "colors.glsl"
+
+#define NO_CLUSTER 0xffffffffu
+
 vec3 hash(in vec3 x) {
     const vec3 k = vec3(0.3183099, 0.3678794, 0.3456789);
     x = x*k + k.yzx;
@@ -87,32 +96,97 @@ float fbm(in vec3 x, in float H, in int numOctaves) {
     return t;
 }
 
+// hues spread by the golden ratio, so clusters with close ids still differ,
+// gray until the first cluster pass
+vec3 cluster_color(uint c) {
+    if (c == NO_CLUSTER) {
+        return vec3(0.5);
+    }
+
+    float t = fract(float(c) * 0.618034);
+    return 0.6 + 0.4 * cos(6.28318 * (t + vec3(0.0, 0.33, 0.67)));
+}
+
 vec3 get_color(vec2 position) {
     vec3 color = vec3(0.0);
-    const float range = particle_radius + 0.6;
     float metaball = 0.0;
     float min_dist = max(width, height);
+    // distance to the surface of the nearest particle
+    float min_edge = min_dist;
+    uint nearest_species = 0;
+    uint nearest_cluster = NO_CLUSTER;
+    // distance to the nearest bond
+    float min_bond = min_dist;
 
-    for (uint i = 0; i < particle_count; i++) {
-        vec2 particle_position = positions[i];
-        vec2 diff = position - particle_position;
-        float d = length(diff);
-        min_dist = min(d, min_dist);
-        metaball += range * range / dot(diff, diff);
-
-        // if (d < particle_radius) {
-        //     density = 1.0;
-        //     break;
-        // } else if (d < range) {
-        //     density += d / range;
-        // }
+    // only particles in the surrounding cells are drawn, farther ones add next to nothing,
+    // dead particles are not in the grid
+    ivec2 cell = grid_cell(position);
+    uint visited[9];
+    uint visited_count = 0;
+
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            uint bucket = grid_hash(grid_wrap(cell + ivec2(x, y)));
+
+            // neighboring cells may share a bucket, visit each bucket once
+            bool seen = false;
+            for (uint v = 0; v < visited_count; v++) {
+                seen = seen || visited[v] == bucket;
+            }
+            if (seen) {
+                continue;
+            }
+            visited[visited_count++] = bucket;
+
+            uint cell_start = bucket == 0 ? 0 : cell_ends[bucket - 1];
+            uint cell_end = cell_ends[bucket];
+            for (uint j = cell_start; j < cell_end; j++) {
+                uint i = sorted_indices[j];
+                vec2 particle_position = positions[i];
+                // periodic domains wrap around the edges of the view
+                vec2 diff = minimum_image(position - particle_position);
+                float d = length(diff);
+                float radius = particle_radius * attributes[i].y;
+                if (d - radius < min_edge) {
+                    min_edge = d - radius;
+                    min_dist = d;
+                    nearest_species = species[i];
+                    nearest_cluster = clusters[i];
+                }
+                float range = radius + 0.6;
+                metaball += range * range / dot(diff, diff);
+
+                // drawn from both ends, either of which may be the only one in the neighborhood
+                for (uint b = 0; show_bonds != 0u && b < MAX_BONDS; b++) {
+                    uint partner = bonds[i * MAX_BONDS + b].partner;
+                    if (partner != NO_BOND) {
+                        vec2 bond = minimum_image(positions[partner] - particle_position);
+                        vec2 normal;
+                        min_bond = min(min_bond, segment_distance(diff, vec2(0.0), bond, normal));
+                    }
+                }
+            }
+        }
+    }
+
+    // add obstacles, a dim fill with a brighter outline
+    for (uint i = 0; i < obstacle_count; i++) {
+        vec2 normal;
+        float d = obstacle_distance(obstacles[i], position, normal);
+        color = mix(color, vec3(0.15), smoothstep(0.5, -0.5, d));
+        color = mix(color, vec3(0.4), smoothstep(1.0, 0.0, abs(d)));
     }
 
     // add metaball
-    color = mix(color, vec3(fbm(vec3(position, min_dist), 1.0, 2)) + 0.5, smoothstep(1.0, 1.1, metaball));
+    vec3 base_color = color_by_cluster != 0u ? cluster_color(nearest_cluster) : species_color(nearest_species);
+    vec3 metaball_color = (vec3(fbm(vec3(position, min_dist), 1.0, 2)) + 0.5) * base_color;
+    color = mix(color, metaball_color, smoothstep(1.0, 1.1, metaball));
+
+    // add bonds
+    color = mix(color, vec3(0.8), smoothstep(1.0, 0.0, min_bond));
 
     // add center dot
-    color = mix(color, vec3(1.0), smoothstep(particle_radius + 0.1, particle_radius, min_dist));
+    color = mix(color, vec3(1.0), smoothstep(0.1, 0.0, min_edge));
 
     return color;
 }
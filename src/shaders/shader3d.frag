@@ -0,0 +1,70 @@
+#version 450
+
+// Ray traces the particles of `ParticleSystem3d` as shaded spheres, seen by a camera
+// orbiting the origin according to the camera uniforms.
+
+layout(location = 0) in vec2 tex_coords;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) buffer PositionBuffer { vec4[] positions; };
+layout(set = 0, binding = 1) buffer SpeciesBuffer { uint[] species; };
+// x: mass, y: radius as a fraction of particle_radius, z: charge
+layout(set = 0, binding = 2) buffer AttributeBuffer { vec4[] attributes; };
+layout(set = 0, binding = 3) uniform Uniforms {
+#include "uniforms.glsl"
+};
+
+#include "colors.glsl"
+
+void main() {
+    vec3 eye = camera_distance * vec3(
+        cos(camera_pitch) * sin(camera_yaw),
+        sin(camera_pitch),
+        cos(camera_pitch) * cos(camera_yaw)
+    );
+    vec3 forward = normalize(-eye);
+    vec3 right = normalize(cross(forward, vec3(0.0, 1.0, 0.0)));
+    vec3 up = cross(right, forward);
+
+    // screen position in [-1, 1] with y up
+    vec2 screen = vec2(tex_coords.x, 1.0 - tex_coords.y) * 2.0 - 1.0;
+    float scale = tan(camera_fov * 0.5);
+    vec3 ray = normalize(forward + (right * screen.x * width / height + up * screen.y) * scale);
+
+    // nearest sphere along the ray
+    float nearest = camera_distance * 4.0;
+    uint hit = particle_capacity;
+    for (uint i = 0; i < particle_capacity; i++) {
+        float radius = particle_radius * attributes[i].y;
+        vec3 offset = eye - positions[i].xyz;
+        float b = dot(offset, ray);
+        float c = dot(offset, offset) - radius * radius;
+        float discriminant = b * b - c;
+        if (discriminant < 0.0) {
+            continue;
+        }
+
+        float t = -b - sqrt(discriminant);
+        if (t > 0.0 && t < nearest) {
+            nearest = t;
+            hit = i;
+        }
+    }
+
+    vec3 background = vec3(0.02);
+    if (hit == particle_capacity) {
+        f_color = vec4(background, 1.0);
+        return;
+    }
+
+    // blinn-phong with a fixed light over the camera's shoulder, fading into the distance
+    vec3 point = eye + ray * nearest;
+    vec3 normal = normalize(point - positions[hit].xyz);
+    vec3 light = normalize(up + right * 0.5 - forward);
+    float diffuse = max(dot(normal, light), 0.0);
+    float specular = pow(max(dot(normal, normalize(light - ray)), 0.0), 32.0);
+    vec3 color = species_color(species[hit]) * (0.15 + 0.85 * diffuse) + vec3(0.3 * specular);
+    float fog = smoothstep(camera_distance * 0.5, camera_distance * 2.0, nearest);
+
+    f_color = vec4(mix(color, background, fog), 1.0);
+}
@@ -0,0 +1,21 @@
+// Temperature control applied once per frame, the modes must match `uniforms::Thermostat`.
+// Requires the `Uniforms` block and `random.glsl` to be included before inclusion.
+
+#define THERMOSTAT_OFF 0u
+#define THERMOSTAT_LANGEVIN 1u
+#define THERMOSTAT_BERENDSEN 2u
+
+// velocity after one frame of thermostat for a particle of the given mass
+vec2 thermostat_velocity(uint index, vec2 velocity, float mass) {
+    if (thermostat == THERMOSTAT_LANGEVIN) {
+        // exact Ornstein-Uhlenbeck step over the frame: friction, and random kicks
+        // that balance it at the target temperature
+        float decay = exp(-langevin_friction * dt);
+        float spread = sqrt((1.0 - decay * decay) * target_temperature / mass);
+        return velocity * decay + random_normal(index, 8u) * spread;
+    } else if (thermostat == THERMOSTAT_BERENDSEN) {
+        return velocity * velocity_scale;
+    }
+
+    return velocity;
+}
@@ -0,0 +1,65 @@
+// Members of the `Uniforms` block, must match `uniforms::Uniforms` field for field.
+uint particle_capacity;
+float width;
+float height;
+float dt;
+uint substeps;
+uint integrator;
+float attraction_strength;
+float repulsion_strength;
+float attraction_range;
+float repulsion_range;
+float center_strength;
+float particle_radius;
+float max_radius_scale;
+float collision_response;
+float charge_strength;
+float momentum;
+float max_acceleration;
+float max_velocity;
+uint grid_cell_count;
+uint quadtree_depth;
+float barnes_hut_theta;
+uint species_count;
+uint emitter_count;
+uint sink_count;
+uint obstacle_count;
+uint field_count;
+uint frame;
+uint seed;
+float time;
+uint boundary_mode;
+float restitution;
+uint mouse_mode;
+float mouse_x;
+float mouse_y;
+float mouse_velocity_x;
+float mouse_velocity_y;
+float mouse_radius;
+float mouse_strength;
+float bond_capture_distance;
+float bond_time;
+float bond_break_strain;
+float bond_stiffness;
+float bond_damping;
+uint show_bonds;
+uint color_by_cluster;
+uint thermostat;
+float target_temperature;
+float langevin_friction;
+float berendsen_coupling_time;
+float velocity_scale;
+float depth;
+float camera_yaw;
+float camera_pitch;
+float camera_distance;
+float camera_fov;
+uint force_kernel;
+float kernel_smoothing;
+float gaussian_width;
+float lennard_jones_epsilon;
+float lennard_jones_sigma;
+float ramp_beta;
+float morse_depth;
+float morse_width;
+float morse_equilibrium;
@@ -0,0 +1,534 @@
+use nannou::prelude::*;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Most species a config can hold, so the `count × count` rule matrix stays small.
+pub const MAX_SPECIES: u32 = 256;
+
+/// How particles of one species react to particles of another.
+/// Strengths scale the global `Uniforms` strengths and may be negative,
+/// ranges are fractions of the global ranges so those remain the neighbor search radius.
+/// Laid out as a `vec4` in `update.comp`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeciesRule {
+    pub attraction_strength: f32,
+    pub repulsion_strength: f32,
+    pub attraction_range: f32,
+    pub repulsion_range: f32,
+}
+
+impl SpeciesRule {
+    pub fn new(
+        attraction_strength: f32,
+        repulsion_strength: f32,
+        attraction_range: f32,
+        repulsion_range: f32,
+    ) -> Self {
+        SpeciesRule {
+            attraction_strength,
+            repulsion_strength,
+            attraction_range,
+            repulsion_range,
+        }
+    }
+}
+
+impl Default for SpeciesRule {
+    fn default() -> Self {
+        SpeciesRule {
+            attraction_strength: 1.0,
+            repulsion_strength: 1.0,
+            attraction_range: 1.0,
+            repulsion_range: 1.0,
+        }
+    }
+}
+
+/// The species × species rule matrix along with the species ratio used at init.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeciesConfig {
+    pub count: u32,
+    /// Row major, `rules[a * count + b]` is how species `a` reacts to species `b`.
+    pub rules: Vec<SpeciesRule>,
+    /// Relative amount of each species, does not need to be normalized.
+    pub ratios: Vec<f32>,
+}
+
+/// A single species that behaves exactly like the global uniforms.
+impl Default for SpeciesConfig {
+    fn default() -> Self {
+        SpeciesConfig {
+            count: 1,
+            rules: vec![SpeciesRule::default()],
+            ratios: vec![1.0],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SpeciesError {
+    UnknownPreset(String),
+    UnknownParameter(String),
+    InvalidValue(String),
+    MissingParameter(&'static str),
+    /// The rules file could not be read.
+    Io(String),
+    /// A line of a rules file, counting from 1, is not a valid statement.
+    InvalidLine(usize),
+    /// The number of ratios does not match the species count.
+    RatioCount {
+        expected: u32,
+        found: usize,
+    },
+}
+
+impl SpeciesConfig {
+    /// A fixed rule matrix, see `SpeciesSource::from_str` for the names.
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "uniform" => Some(SpeciesConfig::default()),
+            // each species clumps with its own kind and pushes the other one away
+            "separate" => Some(SpeciesConfig {
+                count: 2,
+                rules: vec![
+                    SpeciesRule::new(1.5, 1.0, 1.0, 0.5),
+                    SpeciesRule::new(0.2, 1.5, 0.5, 1.0),
+                    SpeciesRule::new(0.2, 1.5, 0.5, 1.0),
+                    SpeciesRule::new(1.5, 1.0, 1.0, 0.5),
+                ],
+                ratios: vec![1.0, 1.0],
+            }),
+            // every species chases the next one and flees the previous one
+            "chase" => {
+                let count = 3;
+                let rules = (0..count * count)
+                    .map(|i| match (i % count + count - i / count) % count {
+                        0 => SpeciesRule::new(1.0, 1.0, 0.5, 0.5),
+                        1 => SpeciesRule::new(1.5, 0.5, 1.0, 0.5),
+                        _ => SpeciesRule::new(0.0, 1.5, 0.5, 1.0),
+                    })
+                    .collect();
+                Some(SpeciesConfig {
+                    count,
+                    rules,
+                    ratios: vec![1.0; count as usize],
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Parses a rule matrix from text: a `count <n>` line of at most `MAX_SPECIES`, then any number of
+    /// `ratios <r0> <r1> ...` and `rule <species> <other> <attraction strength>
+    /// <repulsion strength> <attraction range> <repulsion range>` lines.
+    /// Rules not listed keep the defaults, ratios default to equal amounts.
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn parse_rules(text: &str) -> Result<Self, SpeciesError> {
+        let mut config: Option<SpeciesConfig> = None;
+
+        for (i, line) in text.lines().enumerate() {
+            let invalid = || SpeciesError::InvalidLine(i + 1);
+            let mut words = line.split_whitespace();
+            let statement = match words.next() {
+                None => continue,
+                Some(word) if word.starts_with('#') => continue,
+                Some(word) => word,
+            };
+            let words: Vec<&str> = words.collect();
+            let numbers = |words: &[&str]| -> Result<Vec<f32>, SpeciesError> {
+                words
+                    .iter()
+                    .map(|word| word.parse().map_err(|_| invalid()))
+                    .collect()
+            };
+            let index = |word: &str| word.parse::<u32>().map_err(|_| invalid());
+
+            match (statement, config.as_mut()) {
+                ("count", None) if words.len() == 1 => {
+                    let count = index(words[0])?;
+                    if count == 0 || count > MAX_SPECIES {
+                        return Err(invalid());
+                    }
+                    config = Some(SpeciesConfig {
+                        count,
+                        rules: vec![SpeciesRule::default(); (count * count) as usize],
+                        ratios: vec![1.0; count as usize],
+                    });
+                }
+                ("ratios", Some(config)) => {
+                    let ratios = numbers(&words)?;
+                    if ratios.len() != config.count as usize {
+                        return Err(SpeciesError::RatioCount {
+                            expected: config.count,
+                            found: ratios.len(),
+                        });
+                    }
+                    if !valid_ratios(&ratios) {
+                        return Err(invalid());
+                    }
+                    config.ratios = ratios;
+                }
+                ("rule", Some(config)) if words.len() == 6 => {
+                    let (species, other) = (index(words[0])?, index(words[1])?);
+                    if species >= config.count || other >= config.count {
+                        return Err(invalid());
+                    }
+                    let numbers = numbers(&words[2..])?;
+                    config.rules[(species * config.count + other) as usize] =
+                        SpeciesRule::new(numbers[0], numbers[1], numbers[2], numbers[3]);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        config.ok_or(SpeciesError::MissingParameter("count"))
+    }
+
+    /// `count` species in equal amounts with randomized rules.
+    pub fn random<R: Rng>(count: u32, rng: &mut R) -> Self {
+        let rules = (0..count * count)
+            .map(|_| SpeciesRule {
+                attraction_strength: rng.gen_range(0.0, 2.0),
+                repulsion_strength: rng.gen_range(0.0, 2.0),
+                attraction_range: rng.gen_range(0.25, 1.0),
+                repulsion_range: rng.gen_range(0.25, 1.0),
+            })
+            .collect();
+
+        SpeciesConfig {
+            count,
+            rules,
+            ratios: vec![1.0; count as usize],
+        }
+    }
+
+    pub fn with_ratios(mut self, ratios: Vec<f32>) -> Self {
+        self.ratios = ratios;
+        self
+    }
+
+    pub fn rule(&self, species: u32, other: u32) -> &SpeciesRule {
+        &self.rules[(species * self.count + other) as usize]
+    }
+
+    /// Assigns a species to each of `particle_count` particles according to the ratios.
+    pub fn assign<R: Rng>(&self, particle_count: u32, rng: &mut R) -> Vec<u32> {
+        let distribution = WeightedIndex::new(&self.ratios).expect("invalid species ratios");
+        (0..particle_count)
+            .map(|_| distribution.sample(rng) as u32)
+            .collect()
+    }
+
+    /// Validates the config, clamping ranges so they never exceed the global ranges.
+    pub fn validated(mut self) -> Self {
+        assert!(
+            (1..=MAX_SPECIES).contains(&self.count),
+            "the species count must be in 1..={}",
+            MAX_SPECIES
+        );
+        assert_eq!(
+            self.rules.len(),
+            (self.count * self.count) as usize,
+            "the species matrix must have count × count rules"
+        );
+        assert_eq!(
+            self.ratios.len(),
+            self.count as usize,
+            "there must be one ratio per species"
+        );
+
+        for rule in self.rules.iter_mut() {
+            rule.attraction_range = rule.attraction_range.clamp(0.0, 1.0);
+            rule.repulsion_range = rule.repulsion_range.clamp(0.0, 1.0);
+        }
+
+        self
+    }
+}
+
+/// Where the species of a run come from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpeciesSource {
+    /// `count` species with rules drawn from the run's random numbers.
+    Random {
+        count: u32,
+        ratios: Option<Vec<f32>>,
+    },
+    /// A preset or a rules file.
+    Fixed(SpeciesConfig),
+}
+
+impl SpeciesSource {
+    /// The species config, drawing the rules from `rng` when they are random.
+    pub fn build<R: Rng>(&self, rng: &mut R) -> SpeciesConfig {
+        match self {
+            SpeciesSource::Random { count, ratios } => {
+                let config = SpeciesConfig::random(*count, rng);
+                match ratios {
+                    Some(ratios) => config.with_ratios(ratios.clone()),
+                    None => config,
+                }
+            }
+            SpeciesSource::Fixed(config) => config.clone(),
+        }
+    }
+
+    fn count(&self) -> u32 {
+        match self {
+            SpeciesSource::Random { count, .. } => *count,
+            SpeciesSource::Fixed(config) => config.count,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), SpeciesError> {
+        match (self, key) {
+            (SpeciesSource::Random { count, .. }, "count") => {
+                *count = parse(value)?;
+                if *count > MAX_SPECIES {
+                    return Err(SpeciesError::InvalidValue(format!(
+                        "count={} exceeds {}",
+                        value, MAX_SPECIES
+                    )));
+                }
+            }
+            (SpeciesSource::Random { ratios, .. }, "ratios") => {
+                *ratios = Some(parse_ratios(value)?)
+            }
+            (SpeciesSource::Fixed(config), "ratios") => config.ratios = parse_ratios(value)?,
+            (SpeciesSource::Fixed(config), "path") => {
+                let text = std::fs::read_to_string(Path::new(value))
+                    .map_err(|e| SpeciesError::Io(format!("{}: {}", value, e)))?;
+                *config = SpeciesConfig::parse_rules(&text)?;
+            }
+            _ => return Err(SpeciesError::UnknownParameter(key.to_string())),
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for SpeciesSource {
+    fn default() -> Self {
+        SpeciesSource::Random {
+            count: 3,
+            ratios: None,
+        }
+    }
+}
+
+/// Parses species from `name` or `name:key=value,key=value` like `InitialDistribution`:
+/// `random:count=4,ratios=2/1/1/1` for random rules, `file:path=rules.txt` for a file
+/// in the format of `SpeciesConfig::parse_rules`, or the name of a `SpeciesConfig::preset`,
+/// `uniform`, `separate` or `chase`. Ratios are separated by slashes.
+impl FromStr for SpeciesSource {
+    type Err = SpeciesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap();
+        let mut source = match name {
+            "random" => SpeciesSource::default(),
+            // replaced by the file's rules once `path` is set
+            "file" => SpeciesSource::Fixed(SpeciesConfig {
+                count: 0,
+                rules: vec![],
+                ratios: vec![],
+            }),
+            _ => SpeciesSource::Fixed(
+                SpeciesConfig::preset(name)
+                    .ok_or_else(|| SpeciesError::UnknownPreset(name.to_string()))?,
+            ),
+        };
+
+        // the path goes first, so ratios given alongside it override the file's
+        let mut parameters: Vec<&str> = parts
+            .next()
+            .unwrap_or("")
+            .split(',')
+            .filter(|parameter| !parameter.is_empty())
+            .collect();
+        parameters.sort_by_key(|parameter| !parameter.starts_with("path="));
+        for parameter in parameters {
+            let mut key_value = parameter.splitn(2, '=');
+            let key = key_value.next().unwrap();
+            let value = key_value
+                .next()
+                .ok_or_else(|| SpeciesError::InvalidValue(parameter.to_string()))?;
+            source.set(key, value)?;
+        }
+
+        let count = source.count();
+        if count == 0 {
+            return Err(match name {
+                "file" => SpeciesError::MissingParameter("path"),
+                _ => SpeciesError::InvalidValue("count=0".to_string()),
+            });
+        }
+        let ratio_count = match &source {
+            SpeciesSource::Random { ratios, .. } => ratios.as_ref().map(|ratios| ratios.len()),
+            SpeciesSource::Fixed(config) => Some(config.ratios.len()),
+        };
+        match ratio_count {
+            Some(found) if found != count as usize => Err(SpeciesError::RatioCount {
+                expected: count,
+                found,
+            }),
+            _ => Ok(source),
+        }
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, SpeciesError> {
+    value
+        .parse()
+        .map_err(|_| SpeciesError::InvalidValue(value.to_string()))
+}
+
+fn parse_ratios(value: &str) -> Result<Vec<f32>, SpeciesError> {
+    let ratios: Vec<f32> = value.split('/').map(parse).collect::<Result<_, _>>()?;
+    if !valid_ratios(&ratios) {
+        return Err(SpeciesError::InvalidValue(value.to_string()));
+    }
+    Ok(ratios)
+}
+
+/// Ratios `assign` can draw from: finite, not negative and not all zero.
+fn valid_ratios(ratios: &[f32]) -> bool {
+    ratios
+        .iter()
+        .all(|ratio| ratio.is_finite() && *ratio >= 0.0)
+        && ratios.iter().any(|ratio| *ratio > 0.0)
+}
+
+/// See the `nannou::wgpu::bytes` documentation for why this is necessary.
+pub fn rules_as_bytes(data: &[SpeciesRule]) -> &[u8] {
+    unsafe { wgpu::bytes::from_slice(data) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn parses_random_species() {
+        let source: SpeciesSource = "random:count=2,ratios=3/1".parse().unwrap();
+        assert_eq!(
+            source,
+            SpeciesSource::Random {
+                count: 2,
+                ratios: Some(vec![3.0, 1.0])
+            }
+        );
+
+        let config = source.build(&mut StdRng::seed_from_u64(1));
+        assert_eq!((config.count, config.rules.len()), (2, 4));
+        assert_eq!(config.ratios, vec![3.0, 1.0]);
+    }
+
+    #[test]
+    fn parses_presets() {
+        let source: SpeciesSource = "chase:ratios=1/2/3".parse().unwrap();
+        let config = source.build(&mut StdRng::seed_from_u64(1));
+        assert_eq!(config.count, 3);
+        assert_eq!(config.ratios, vec![1.0, 2.0, 3.0]);
+        // 0 chases 1 and flees 2
+        assert!(config.rule(0, 1).attraction_strength > config.rule(0, 2).attraction_strength);
+        assert_eq!(config.rule(0, 1), config.rule(1, 2));
+        assert_eq!(config.rule(0, 1), config.rule(2, 0));
+    }
+
+    #[test]
+    fn parses_rules() {
+        let config = SpeciesConfig::parse_rules(
+            "# two species\ncount 2\nratios 1 3\n\nrule 0 1 0.5 2 0.25 1\n",
+        )
+        .unwrap();
+        assert_eq!(config.count, 2);
+        assert_eq!(config.ratios, vec![1.0, 3.0]);
+        assert_eq!(*config.rule(0, 1), SpeciesRule::new(0.5, 2.0, 0.25, 1.0));
+        assert_eq!(*config.rule(1, 0), SpeciesRule::default());
+    }
+
+    #[test]
+    fn rejects_invalid_species() {
+        assert!(matches!(
+            "swarm".parse::<SpeciesSource>(),
+            Err(SpeciesError::UnknownPreset(_))
+        ));
+        assert!(matches!(
+            "random:colors=2".parse::<SpeciesSource>(),
+            Err(SpeciesError::UnknownParameter(_))
+        ));
+        assert!(matches!(
+            "random:count=two".parse::<SpeciesSource>(),
+            Err(SpeciesError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            "random:count=3,ratios=1/1".parse::<SpeciesSource>(),
+            Err(SpeciesError::RatioCount {
+                expected: 3,
+                found: 2
+            })
+        ));
+        assert!(matches!(
+            "separate:ratios=0/0".parse::<SpeciesSource>(),
+            Err(SpeciesError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            "file".parse::<SpeciesSource>(),
+            Err(SpeciesError::MissingParameter("path"))
+        ));
+        assert!(matches!(
+            "file:path=/nonexistent/rules.txt".parse::<SpeciesSource>(),
+            Err(SpeciesError::Io(_))
+        ));
+        assert!(matches!(
+            SpeciesConfig::parse_rules("ratios 1 1"),
+            Err(SpeciesError::InvalidLine(1))
+        ));
+        assert!(matches!(
+            SpeciesConfig::parse_rules("count 2\nrule 0 2 1 1 1 1"),
+            Err(SpeciesError::InvalidLine(2))
+        ));
+        for (rules, line) in [
+            ("count 2\nratios 0 0", 2),
+            ("count 2\nratios 1 -1", 2),
+            ("count 2\nratios 1 NaN", 2),
+            ("count 2.7", 1),
+            ("count 0", 1),
+            ("count 100000", 1),
+            ("count 2\nrule 1.9 0 1 1 1 1", 2),
+            ("count 2\nrule -1 0 1 1 1 1", 2),
+        ] {
+            assert!(
+                matches!(
+                    SpeciesConfig::parse_rules(rules),
+                    Err(SpeciesError::InvalidLine(l)) if l == line
+                ),
+                "{}",
+                rules
+            );
+        }
+        assert!(matches!(
+            "random:count=100000".parse::<SpeciesSource>(),
+            Err(SpeciesError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            "random:count=2.5".parse::<SpeciesSource>(),
+            Err(SpeciesError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            "random:count=2,ratios=1/inf".parse::<SpeciesSource>(),
+            Err(SpeciesError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            SpeciesConfig::parse_rules("# empty"),
+            Err(SpeciesError::MissingParameter("count"))
+        ));
+    }
+}
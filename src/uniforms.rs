@@ -2,54 +2,344 @@ use glsl_layout::float;
 use glsl_layout::*;
 use nannou::prelude::*;
 
+use crate::grid::default_grid_cell_count;
+use crate::quadtree::default_quadtree_depth;
+
+/// Checkpoints save these as their raw bytes, so changing the fields or their order
+/// must bump `checkpoint::CHECKPOINT_VERSION`.
 #[repr(C)]
 #[derive(Debug, Default, Clone, Copy, Uniform)]
 pub struct Uniforms {
-    pub particle_count: uint,
+    /// Size of the particle buffers, the live count is kept on the GPU by the population passes.
+    pub particle_capacity: uint,
     pub width: float,
     pub height: float,
-    pub speed: float,
+    /// Simulated time per frame, split evenly over `substeps`.
+    pub dt: float,
+    pub substeps: uint,
+    /// An `Integrator` as uint.
+    pub integrator: uint,
     pub attraction_strength: float,
     pub repulsion_strength: float,
     pub attraction_range: float,
     pub repulsion_range: float,
     pub center_strength: float,
     pub particle_radius: float,
+    /// Largest radius attribute of any particle, as a fraction of `particle_radius`.
+    /// The neighbor grid is sized for it, so every collision is found.
+    pub max_radius_scale: float,
     pub collision_response: float,
+    /// Coulomb constant of the force between particle charges, like charges repel.
+    pub charge_strength: float,
     pub momentum: float,
     pub max_acceleration: float,
     pub max_velocity: float,
+    pub grid_cell_count: uint,
+    /// Levels below the root of the Barnes-Hut quadtree, which has `4^quadtree_depth` leaves.
+    pub quadtree_depth: uint,
+    /// Opening angle of the Barnes-Hut approximation of unlimited range forces: nodes
+    /// smaller than this fraction of their distance act as one particle per species.
+    /// 0 computes unlimited range forces exactly, looping over every pair.
+    pub barnes_hut_theta: float,
+    pub species_count: uint,
+    pub emitter_count: uint,
+    pub sink_count: uint,
+    pub obstacle_count: uint,
+    pub field_count: uint,
+    /// Frames simulated so far, seeds the per-frame random numbers of `random.glsl`.
+    pub frame: uint,
+    /// The run's seed folded to 32 bits by `random::gpu_seed`, mixed into every random number.
+    pub seed: uint,
+    /// Simulated time, animates the curl noise of `fields.glsl`.
+    pub time: float,
+    /// A `BoundaryMode` as uint.
+    pub boundary_mode: uint,
+    /// Fraction of the normal velocity kept when bouncing off a reflective wall.
+    pub restitution: float,
+    /// A `MouseMode` as uint.
+    pub mouse_mode: uint,
+    /// Cursor position in simulation space.
+    pub mouse_x: float,
+    pub mouse_y: float,
+    /// Cursor velocity in simulation space per unit of time, grabbed particles move along.
+    pub mouse_velocity_x: float,
+    pub mouse_velocity_y: float,
+    pub mouse_radius: float,
+    /// Peak acceleration of attraction and repulsion, or the spring stiffness of grabbing.
+    pub mouse_strength: float,
+    /// Particles closer than this bond once they have been each other's nearest neighbor
+    /// for `bond_time`, at most the grid cell size. 0 stops bonds from forming and breaking.
+    pub bond_capture_distance: float,
+    pub bond_time: float,
+    /// Stretch beyond the rest length, as a fraction of it, at which bonds break.
+    pub bond_break_strain: float,
+    pub bond_stiffness: float,
+    /// Damping of the relative velocity along bonds.
+    pub bond_damping: float,
+    /// Whether `shader.frag` draws the bonds, 0 or 1.
+    pub show_bonds: uint,
+    /// Whether `shader.frag` colors particles by cluster instead of by species, 0 or 1.
+    pub color_by_cluster: uint,
+    /// A `Thermostat` as uint.
+    pub thermostat: uint,
+    /// Temperature the thermostat drives toward, see `Diagnostics::temperature`.
+    pub target_temperature: float,
+    /// Rate per unit time at which the Langevin thermostat's friction relaxes velocities.
+    pub langevin_friction: float,
+    /// Time over which the Berendsen thermostat closes the gap to the target temperature.
+    pub berendsen_coupling_time: float,
+    /// Factor the Berendsen thermostat scales velocities by this frame,
+    /// 1 unless a new temperature reading arrived, see `Diagnostics::berendsen_scale`.
+    pub velocity_scale: float,
+    /// Extent of the 3D domain along z, used by `update3d.comp` only.
+    pub depth: float,
+    /// Angle of the 3D camera around the y axis, in radians.
+    pub camera_yaw: float,
+    /// Angle of the 3D camera above the xz plane, in radians.
+    pub camera_pitch: float,
+    /// Distance of the 3D camera from the origin it orbits.
+    pub camera_distance: float,
+    /// Vertical field of view of the 3D camera, in radians.
+    pub camera_fov: float,
+    /// A `ForceKernel` as uint.
+    pub force_kernel: uint,
+    /// Fraction of each range over which forces fade out, 0 cuts them off hard.
+    pub kernel_smoothing: float,
+    /// Width of the gaussian kernel as a fraction of the ranges.
+    pub gaussian_width: float,
+    /// Well depth of the Lennard-Jones kernel, in place of the global strengths.
+    pub lennard_jones_epsilon: float,
+    /// Distance at which the Lennard-Jones force changes sign is `2^(1/6)` times this.
+    pub lennard_jones_sigma: float,
+    /// Fraction of the attraction range the linear ramp kernel repels within.
+    pub ramp_beta: float,
+    /// Well depth of the Morse kernel, in place of the global strengths.
+    pub morse_depth: float,
+    /// Inverse width of the Morse well, larger is stiffer.
+    pub morse_width: float,
+    /// Distance at which the Morse force changes sign.
+    pub morse_equilibrium: float,
 }
 
 impl Uniforms {
-    pub fn new(particle_count: uint, width: float, height: float) -> Self {
+    pub fn new(particle_capacity: uint, width: float, height: float) -> Self {
         Uniforms {
-            particle_count,
+            particle_capacity,
             width,
             height,
-            speed: 1.0,
+            dt: 1.0,
+            substeps: 1,
+            integrator: Integrator::SemiImplicitEuler as uint,
             attraction_strength: 2.8,
             repulsion_strength: 3.0,
             attraction_range: 20.0, // 0.045,
             repulsion_range: 130.0, // 1.2,
             center_strength: 0.0001,
             particle_radius: 2.0,
+            max_radius_scale: 1.0,
             collision_response: 0.1,
+            charge_strength: 0.0,
             momentum: 0.97,
             max_acceleration: 0.0,
             max_velocity: 5.0,
+            grid_cell_count: default_grid_cell_count(particle_capacity),
+            quadtree_depth: default_quadtree_depth(particle_capacity),
+            barnes_hut_theta: 0.0,
+            species_count: 1,
+            emitter_count: 0,
+            sink_count: 0,
+            obstacle_count: 0,
+            field_count: 0,
+            frame: 0,
+            seed: 0,
+            time: 0.0,
+            boundary_mode: BoundaryMode::Open as uint,
+            restitution: 0.8,
+            mouse_mode: MouseMode::Off as uint,
+            mouse_x: 0.0,
+            mouse_y: 0.0,
+            mouse_velocity_x: 0.0,
+            mouse_velocity_y: 0.0,
+            mouse_radius: 100.0,
+            mouse_strength: 0.3,
+            bond_capture_distance: 0.0,
+            bond_time: 30.0,
+            bond_break_strain: 0.5,
+            bond_stiffness: 0.05,
+            bond_damping: 0.1,
+            show_bonds: 1,
+            color_by_cluster: 0,
+            thermostat: Thermostat::Off as uint,
+            target_temperature: 0.1,
+            langevin_friction: 0.05,
+            berendsen_coupling_time: 50.0,
+            velocity_scale: 1.0,
+            depth: 600.0,
+            camera_yaw: 0.0,
+            camera_pitch: 0.3,
+            camera_distance: 1200.0,
+            camera_fov: 0.8,
+            force_kernel: ForceKernel::InverseSquare as uint,
+            kernel_smoothing: 0.0,
+            gaussian_width: 0.5,
+            lennard_jones_epsilon: 0.05,
+            lennard_jones_sigma: 8.0,
+            ramp_beta: 0.3,
+            morse_depth: 0.05,
+            morse_width: 0.2,
+            morse_equilibrium: 8.0,
+        }
+    }
+}
+
+/// Integration scheme used by `update.comp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integrator {
+    SemiImplicitEuler = 0,
+    VelocityVerlet = 1,
+    Rk2 = 2,
+}
+
+impl Integrator {
+    /// Falls back to semi-implicit euler for unknown values.
+    pub fn from_uint(value: uint) -> Self {
+        match value {
+            1 => Integrator::VelocityVerlet,
+            2 => Integrator::Rk2,
+            _ => Integrator::SemiImplicitEuler,
+        }
+    }
+}
+
+/// What happens at the edges of the `width` x `height` domain, which is centered on the origin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoundaryMode {
+    /// No boundary, particles may drift off forever.
+    Open = 0,
+    /// Periodic, particles leaving one edge enter at the opposite one
+    /// and forces act across the edges.
+    Wrap = 1,
+    /// Particles bounce off the edges, keeping `restitution` of their normal velocity.
+    Reflect = 2,
+    /// Particles leaving the domain reappear at a random position inside it, at rest.
+    Respawn = 3,
+}
+
+impl BoundaryMode {
+    /// Falls back to open for unknown values.
+    pub fn from_uint(value: uint) -> Self {
+        match value {
+            1 => BoundaryMode::Wrap,
+            2 => BoundaryMode::Reflect,
+            3 => BoundaryMode::Respawn,
+            _ => BoundaryMode::Open,
         }
     }
 }
 
+/// How the cursor acts on particles within `mouse_radius` of it, see `mouse.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseMode {
+    Off = 0,
+    /// Pulls particles toward the cursor.
+    Attract = 1,
+    /// Pushes particles away from the cursor.
+    Repel = 2,
+    /// Holds particles to the cursor with a spring, dragging them along.
+    Grab = 3,
+}
+
+impl MouseMode {
+    /// Falls back to off for unknown values.
+    pub fn from_uint(value: uint) -> Self {
+        match value {
+            1 => MouseMode::Attract,
+            2 => MouseMode::Repel,
+            3 => MouseMode::Grab,
+            _ => MouseMode::Off,
+        }
+    }
+}
+
+/// Temperature control on top of the global `momentum` damping, see `thermostat.glsl`.
+/// Runs once per frame after the substeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Thermostat {
+    Off = 0,
+    /// Friction plus seeded random kicks, which hold every particle at the target temperature.
+    Langevin = 1,
+    /// Rescales all velocities toward the target temperature measured by the diagnostics.
+    Berendsen = 2,
+}
+
+impl Thermostat {
+    /// Falls back to off for unknown values.
+    pub fn from_uint(value: uint) -> Self {
+        match value {
+            1 => Thermostat::Langevin,
+            2 => Thermostat::Berendsen,
+            _ => Thermostat::Off,
+        }
+    }
+
+    /// The next mode, wrapping around to off.
+    pub fn next(self) -> Self {
+        Self::from_uint(self as uint + 1)
+    }
+}
+
+/// Shape of the force between two particles, see `kernels.glsl`.
+/// Every kernel is scaled per species pair by the species rules and fades out at the ranges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForceKernel {
+    /// `strength / r²` for both attraction and repulsion.
+    InverseSquare = 0,
+    /// Attraction and repulsion bell curves, `gaussian_width` times their range wide.
+    /// Needs finite ranges.
+    Gaussian = 1,
+    /// Lennard-Jones with `lennard_jones_epsilon` and `lennard_jones_sigma`.
+    /// The species rules scale its attractive r⁻⁶ tail, cut off at the attraction range,
+    /// and its repulsive r⁻¹² core, cut off at the repulsion range, separately.
+    LennardJones = 2,
+    /// Particle life style linear ramps: repulsion within `ramp_beta` of the attraction range,
+    /// then a triangular attraction peaking halfway to the range. Needs finite ranges.
+    LinearRamp = 3,
+    /// Morse with `morse_depth`, `morse_width` and `morse_equilibrium`,
+    /// split into attraction and repulsion terms like Lennard-Jones.
+    Morse = 4,
+}
+
+impl ForceKernel {
+    /// Falls back to inverse-square for unknown values.
+    pub fn from_uint(value: uint) -> Self {
+        match value {
+            1 => ForceKernel::Gaussian,
+            2 => ForceKernel::LennardJones,
+            3 => ForceKernel::LinearRamp,
+            4 => ForceKernel::Morse,
+            _ => ForceKernel::InverseSquare,
+        }
+    }
+
+    /// Kernels shaped by the force ranges, which would vanish with an unlimited range of 0.
+    pub fn needs_finite_ranges(self) -> bool {
+        matches!(self, ForceKernel::Gaussian | ForceKernel::LinearRamp)
+    }
+}
+
 pub struct UniformBuffer {
     pub data: Uniforms,
     pub buffer: wgpu::Buffer,
 }
 
 impl UniformBuffer {
-    pub fn new(device: &wgpu::Device, particle_count: uint, width: float, height: float) -> Self {
-        let data = Uniforms::new(particle_count, width, height);
+    pub fn new(
+        device: &wgpu::Device,
+        particle_capacity: uint,
+        width: float,
+        height: float,
+    ) -> Self {
+        let data = Uniforms::new(particle_capacity, width, height);
 
         let std140_uniforms = data.std140();
         let uniforms_bytes = std140_uniforms.as_raw();
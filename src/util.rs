@@ -1,5 +1,11 @@
 use nannou::prelude::*;
 use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Returns the directory containing the shaders.
+pub fn shaders_directory(app: &App) -> PathBuf {
+    app.project_path().unwrap().join("src").join("shaders")
+}
 
 /// Compiles a shader from the shaders directory
 pub fn compile_shader(
@@ -8,20 +14,166 @@ pub fn compile_shader(
     filename: &str,
     kind: shaderc::ShaderKind,
 ) -> wgpu::ShaderModule {
-    println!("compiling {:?}", filename);
-    let path = app
-        .project_path()
-        .unwrap()
-        .join("src")
-        .join("shaders")
+    compile_shader_in(&shaders_directory(app), device, filename, kind)
+}
+
+/// Compiles a shader from the given directory, which does not require a running app.
+/// `#include "file.glsl"` directives are resolved relative to the same directory.
+pub fn compile_shader_in(
+    shaders_dir: &Path,
+    device: &wgpu::Device,
+    filename: &str,
+    kind: shaderc::ShaderKind,
+) -> wgpu::ShaderModule {
+    compile_shader_variant(shaders_dir, device, filename, kind, &[])
+}
+
+/// Compiles a shader from the given directory with each of `defines` defined as a macro,
+/// either as `NAME` or `NAME=VALUE`.
+pub fn compile_shader_variant(
+    shaders_dir: &Path,
+    device: &wgpu::Device,
+    filename: &str,
+    kind: shaderc::ShaderKind,
+    defines: &[&str],
+) -> wgpu::ShaderModule {
+    println!("compiling {:?} {:?}", filename, defines);
+    let path = shaders_dir
         .join(filename)
         .into_os_string()
         .into_string()
         .unwrap();
     let code = fs::read_to_string(path).expect("faild to read shader");
+    let mut options = shaderc::CompileOptions::new().unwrap();
+    for define in defines {
+        let mut parts = define.splitn(2, '=');
+        let name = parts.next().unwrap();
+        options.add_macro_definition(name, parts.next());
+    }
+    options.set_include_callback(|name, _include_type, _source, _depth| {
+        let include_path = shaders_dir.join(name);
+        let content = fs::read_to_string(&include_path).map_err(|e| e.to_string())?;
+        Ok(shaderc::ResolvedInclude {
+            resolved_name: include_path.to_string_lossy().into_owned(),
+            content,
+        })
+    });
     let mut compiler = shaderc::Compiler::new().unwrap();
     let spirv = compiler
-        .compile_into_spirv(code.as_str(), kind, filename, "main", None)
+        .compile_into_spirv(code.as_str(), kind, filename, "main", Some(&options))
         .expect("failed to compile shader");
     wgpu::shader_from_spirv_bytes(device, spirv.as_binary_u8())
 }
+
+/// The most storage buffers the simulation binds to a single shader stage.
+pub const STORAGE_BUFFERS_PER_STAGE: u32 = 20;
+
+#[derive(Debug)]
+pub enum AdapterError {
+    NoAdapter,
+    /// The adapter binds fewer storage buffers per shader stage than the simulation needs.
+    TooFewStorageBuffers {
+        required: u32,
+        supported: u32,
+    },
+}
+
+/// The default device descriptor with room for all of the simulation's storage buffers.
+pub fn device_descriptor() -> wgpu::DeviceDescriptor<'static> {
+    let mut descriptor = wgpu::default_device_descriptor();
+    descriptor.limits.max_storage_buffers_per_shader_stage = STORAGE_BUFFERS_PER_STAGE;
+    descriptor
+}
+
+/// Checks that an adapter with these limits can create the device of `device_descriptor`.
+pub fn check_limits(limits: &wgpu::Limits) -> Result<(), AdapterError> {
+    let supported = limits.max_storage_buffers_per_shader_stage;
+    if supported < STORAGE_BUFFERS_PER_STAGE {
+        return Err(AdapterError::TooFewStorageBuffers {
+            required: STORAGE_BUFFERS_PER_STAGE,
+            supported,
+        });
+    }
+    Ok(())
+}
+
+/// Checks the adapter that new windows will share, before a window requests its device
+/// and an unsupported adapter fails inside wgpu instead.
+pub fn check_window_adapter(app: &App) -> Result<(), AdapterError> {
+    // the app keys its adapters by power preference, so this is the adapter the window reuses
+    let options = wgpu::RequestAdapterOptions {
+        power_preference: nannou::window::Builder::DEFAULT_POWER_PREFERENCE,
+        force_fallback_adapter: nannou::window::Builder::DEFAULT_FORCE_FALLBACK_ADAPTER,
+        compatible_surface: None,
+    };
+    let adapter = app
+        .wgpu_adapters()
+        .get_or_request(options, app.instance())
+        .ok_or(AdapterError::NoAdapter)?;
+    check_limits(&adapter.limits())
+}
+
+/// Copies `size` bytes of a `COPY_SRC` buffer into CPU memory, blocking until the GPU is done.
+pub fn read_buffer(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    size: wgpu::BufferAddress,
+) -> Vec<u8> {
+    read_buffer_range(device, queue, buffer, 0, size)
+}
+
+/// Copies `size` bytes starting at `offset` of a `COPY_SRC` buffer into CPU memory,
+/// blocking until the GPU is done.
+pub fn read_buffer_range(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    offset: wgpu::BufferAddress,
+    size: wgpu::BufferAddress,
+) -> Vec<u8> {
+    let read_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("read-buffer"),
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let desc = wgpu::CommandEncoderDescriptor {
+        label: Some("read-buffer-encoder"),
+    };
+    let mut encoder = device.create_command_encoder(&desc);
+    encoder.copy_buffer_to_buffer(buffer, offset, &read_buffer, 0, size);
+    queue.submit(Some(encoder.finish()));
+
+    let slice = read_buffer.slice(..);
+    let mapping = slice.map_async(wgpu::MapMode::Read);
+    device.poll(wgpu::Maintain::Wait);
+    futures::executor::block_on(mapping).expect("failed to map read buffer");
+
+    let bytes = slice.get_mapped_range().to_vec();
+    read_buffer.unmap();
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_limits_of_the_device_descriptor() {
+        assert!(check_limits(&device_descriptor().limits).is_ok());
+    }
+
+    #[test]
+    fn rejects_too_few_storage_buffers() {
+        let limits = wgpu::Limits::default();
+        assert!(matches!(
+            check_limits(&limits),
+            Err(AdapterError::TooFewStorageBuffers {
+                required: STORAGE_BUFFERS_PER_STAGE,
+                supported: 8,
+            })
+        ));
+    }
+}
@@ -0,0 +1,73 @@
+//! Continues a run on a software adapter from a checkpoint written mid-run.
+
+mod common;
+
+use common::*;
+use generative_matter::checkpoint::*;
+use generative_matter::particles::*;
+use generative_matter::uniforms::*;
+use nannou::prelude::*;
+
+#[test]
+fn checkpoint_continues_the_run() {
+    let Some(device_queue_pair) = software_device() else {
+        return;
+    };
+    let device = device_queue_pair.device();
+    let queue = device_queue_pair.queue();
+
+    let mut uniform_buffer = UniformBuffer::new(device, PARTICLE_COUNT, 1920.0, 1080.0);
+    let (_, mut original) = seeded_system(device, &uniform_buffer);
+    let (_, mut continued) = seeded_system(device, &uniform_buffer);
+
+    let step = |particle_system: &mut ParticleSystem, uniforms: &Uniforms| {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("checkpoint-test"),
+        });
+        particle_system.update(&mut encoder, uniforms);
+        queue.submit(Some(encoder.finish()));
+    };
+    for _ in 0..3 {
+        step(&mut original, &uniform_buffer.data);
+    }
+    uniform_buffer.data.frame = 3;
+
+    // through the file format and into a system that has not moved yet
+    let mut bytes = vec![];
+    original
+        .read_checkpoint(device, queue, 1234, &uniform_buffer.data)
+        .write(&mut bytes)
+        .unwrap();
+    let checkpoint = Checkpoint::read(&mut bytes.as_slice()).unwrap();
+    assert_eq!((checkpoint.seed, checkpoint.frame()), (1234, 3));
+    continued.load_checkpoint(queue, &checkpoint).unwrap();
+
+    step(&mut original, &uniform_buffer.data);
+    step(&mut continued, &checkpoint.uniforms);
+    let expected = original.read_state(device, queue);
+    let actual = continued.read_state(device, queue);
+    assert_close(
+        "checkpoint",
+        4,
+        "positions",
+        &actual.positions,
+        &expected.positions,
+    );
+    assert_close(
+        "checkpoint",
+        4,
+        "velocities",
+        &actual.velocities,
+        &expected.velocities,
+    );
+    assert_eq!(actual.alive, expected.alive);
+
+    assert!(matches!(
+        Checkpoint::read(&mut &bytes[..16]),
+        Err(CheckpointError::Io(_))
+    ));
+    assert!(matches!(
+        Checkpoint::read(&mut &b"not a checkpoint"[..]),
+        Err(CheckpointError::NotACheckpoint)
+    ));
+}
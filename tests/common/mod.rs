@@ -0,0 +1,155 @@
+//! Fixtures shared by the tests that run the compute shaders on a software adapter
+//! (lavapipe/llvmpipe). Each test returns early, printing why, when there is none.
+
+#![allow(dead_code)]
+
+use generative_matter::bonds::*;
+use generative_matter::environment::*;
+use generative_matter::particles::*;
+use generative_matter::population::*;
+use generative_matter::species::*;
+use generative_matter::uniforms::*;
+use generative_matter::util;
+use nannou::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub const PARTICLE_COUNT: u32 = 256;
+pub const STEPS: usize = 16;
+pub const SEED: u64 = 7;
+pub const ABSOLUTE_TOLERANCE: f32 = 1e-3;
+pub const RELATIVE_TOLERANCE: f32 = 1e-3;
+
+pub fn shaders_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("src")
+        .join("shaders")
+}
+
+/// The fallback adapter's device, `None` after printing why the test is skipped
+/// when there is no software Vulkan driver.
+pub fn software_device() -> Option<Arc<wgpu::DeviceQueuePair>> {
+    let instance = wgpu::Instance::new(wgpu::Backends::PRIMARY);
+    let adapters = wgpu::AdapterMap::default();
+    let options = wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        force_fallback_adapter: true,
+        compatible_surface: None,
+    };
+    let adapter = match adapters.get_or_request(options, &instance) {
+        Some(adapter) => adapter,
+        None => {
+            println!(
+                "skipped: no software adapter, install a software Vulkan driver such as lavapipe"
+            );
+            return None;
+        }
+    };
+    if let Err(e) = util::check_limits(&adapter.limits()) {
+        println!("skipped: unsupported software adapter: {:?}", e);
+        return None;
+    }
+    Some(adapter.get_or_request_device(util::device_descriptor()))
+}
+
+/// `PARTICLE_COUNT` slots of which the first `live_count` are alive,
+/// with a mix of masses, radii and charges.
+pub fn seeded_state(species_count: u32, live_count: u32) -> ParticleState {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let mut attribute_rng = StdRng::seed_from_u64(SEED + 1);
+    let mut state = ParticleState::default();
+
+    for index in 0..PARTICLE_COUNT {
+        let angle = rng.gen_range(-PI, PI);
+        let radius = rng.gen_range(0.0, 120.0);
+        state
+            .positions
+            .push(pt2(radius * angle.cos(), radius * angle.sin()));
+        state
+            .velocities
+            .push(pt2(rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0)));
+        state.species.push(rng.gen_range(0, species_count));
+        state.attributes.push(ParticleAttributes::new(
+            attribute_rng.gen_range(0.5, 2.0),
+            attribute_rng.gen_range(0.5, 1.0),
+            attribute_rng.gen_range(-1.0, 1.0),
+        ));
+        state.alive.push(index < live_count);
+        state.bonds.extend([Bond::NONE; MAX_BONDS as usize].iter());
+    }
+
+    state
+}
+
+pub fn seeded_species(species_count: u32) -> SpeciesConfig {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let rules = (0..species_count * species_count)
+        .map(|_| SpeciesRule {
+            attraction_strength: rng.gen_range(0.0, 2.0),
+            repulsion_strength: rng.gen_range(0.0, 2.0),
+            attraction_range: rng.gen_range(0.25, 1.0),
+            repulsion_range: rng.gen_range(0.25, 1.0),
+        })
+        .collect();
+
+    SpeciesConfig {
+        count: species_count,
+        rules,
+        ratios: vec![1.0; species_count as usize],
+    }
+    .validated()
+}
+
+/// A system of the default species on `seeded_state` with a quarter of the slots dead,
+/// returned along with that state.
+pub fn seeded_system(
+    device: &wgpu::Device,
+    uniform_buffer: &UniformBuffer,
+) -> (ParticleState, ParticleSystem) {
+    let species = SpeciesConfig::default();
+    let population = PopulationConfig::new(PARTICLE_COUNT * 3 / 4);
+    let state = seeded_state(species.count, population.initial_count);
+    let particle_system = ParticleSystem::from_state(
+        &shaders_dir(),
+        device,
+        uniform_buffer,
+        &state,
+        &species,
+        &population,
+        &EnvironmentConfig::new(),
+        UpdateOptions::default(),
+    );
+    (state, particle_system)
+}
+
+pub fn assert_close(preset: &str, step: usize, what: &str, gpu: &[Point2], cpu: &[Point2]) {
+    assert_eq!(gpu.len(), cpu.len());
+    for (index, (g, c)) in gpu.iter().zip(cpu).enumerate() {
+        let error = (*g - *c).length();
+        let tolerance = ABSOLUTE_TOLERANCE + RELATIVE_TOLERANCE * c.length();
+        assert!(
+            error <= tolerance,
+            "{}: {} of particle {} diverged after step {}: gpu {:?} cpu {:?} (error {})",
+            preset,
+            what,
+            index,
+            step,
+            g,
+            c,
+            error
+        );
+    }
+}
+
+pub fn assert_close_scalar(what: &str, gpu: f32, cpu: f32) {
+    let tolerance = ABSOLUTE_TOLERANCE + RELATIVE_TOLERANCE * cpu.abs();
+    assert!(
+        (gpu - cpu).abs() <= tolerance,
+        "{} diverged: gpu {} cpu {}",
+        what,
+        gpu,
+        cpu
+    );
+}
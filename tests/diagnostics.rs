@@ -0,0 +1,55 @@
+//! Checks the diagnostics reduced on a software adapter against the CPU sums.
+
+mod common;
+
+use common::*;
+use generative_matter::diagnostics::*;
+use generative_matter::uniforms::*;
+use nannou::prelude::*;
+
+#[test]
+fn diagnostics_match_cpu_reference() {
+    let Some(device_queue_pair) = software_device() else {
+        return;
+    };
+    let device = device_queue_pair.device();
+    let queue = device_queue_pair.queue();
+
+    let uniform_buffer = UniformBuffer::new(device, PARTICLE_COUNT, 1920.0, 1080.0);
+    let (state, mut particle_system) = seeded_system(device, &uniform_buffer);
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("diagnostics-test"),
+    });
+    assert!(particle_system.encode_diagnostics(&mut encoder, 3, 1.5));
+    queue.submit(Some(encoder.finish()));
+    particle_system.diagnostics.submitted();
+
+    let gpu = loop {
+        if let Some(diagnostics) = particle_system.diagnostics.poll(device) {
+            break diagnostics;
+        }
+    };
+    let cpu = Diagnostics::from_sums(&DiagnosticSums::from_state(&state), 3, 1.5);
+
+    assert_eq!((gpu.frame, gpu.time), (cpu.frame, cpu.time));
+    assert_eq!(gpu.live_count, cpu.live_count);
+    assert_close_scalar("kinetic energy", gpu.kinetic_energy, cpu.kinetic_energy);
+    assert_close_scalar("mean speed", gpu.mean_speed, cpu.mean_speed);
+    assert_close_scalar("max speed", gpu.max_speed, cpu.max_speed);
+    assert_close_scalar("temperature", gpu.temperature, cpu.temperature);
+    assert_close(
+        "diagnostics",
+        0,
+        "momentum",
+        &[gpu.momentum],
+        &[cpu.momentum],
+    );
+    assert_close(
+        "diagnostics",
+        0,
+        "centroid",
+        &[gpu.centroid],
+        &[cpu.centroid],
+    );
+}
@@ -0,0 +1,68 @@
+//! Records snapshots into the GPU history on a software adapter and restores them.
+
+mod common;
+
+use common::*;
+use generative_matter::uniforms::*;
+use nannou::prelude::*;
+
+#[test]
+fn history_restores_recorded_state() {
+    let Some(device_queue_pair) = software_device() else {
+        return;
+    };
+    let device = device_queue_pair.device();
+    let queue = device_queue_pair.queue();
+
+    let uniform_buffer = UniformBuffer::new(device, PARTICLE_COUNT, 1920.0, 1080.0);
+    let (state, particle_system) = seeded_system(device, &uniform_buffer);
+    let mut particle_system = particle_system.with_history(device, 2);
+
+    // three snapshots, one step apart, in a history of two
+    let mut recorded = vec![];
+    for frame in 0..3 {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("history-test"),
+        });
+        particle_system.record_history(&mut encoder, frame, frame as f32);
+        particle_system.update(&mut encoder, &uniform_buffer.data);
+        queue.submit(Some(encoder.finish()));
+        recorded.push(particle_system.read_history(device, queue, 0).unwrap());
+    }
+
+    let history = particle_system.history.as_ref().unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history.entry(1).unwrap().frame, 1);
+    assert!(history.entry(2).is_none());
+    assert_close(
+        "history",
+        0,
+        "first snapshot",
+        &recorded[0].1.positions,
+        &state.positions,
+    );
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("history-test"),
+    });
+    let entry = particle_system.restore_history(&mut encoder, 1).unwrap();
+    queue.submit(Some(encoder.finish()));
+    let restored = particle_system.read_state(device, queue);
+
+    assert_eq!(entry, recorded[1].0);
+    assert_close(
+        "history",
+        1,
+        "positions",
+        &restored.positions,
+        &recorded[1].1.positions,
+    );
+    assert_close(
+        "history",
+        1,
+        "velocities",
+        &restored.velocities,
+        &recorded[1].1.velocities,
+    );
+    assert_eq!(restored.alive, recorded[1].1.alive);
+}
@@ -0,0 +1,398 @@
+//! Runs `update.comp` on a software adapter (lavapipe/llvmpipe) and the CPU reference
+//! implementation side by side from the same seeded state, and checks that they agree.
+
+mod common;
+
+use common::*;
+use generative_matter::backend::*;
+use generative_matter::bonds::*;
+use generative_matter::cpu::*;
+use generative_matter::environment::*;
+use generative_matter::fields::*;
+use generative_matter::obstacles::*;
+use generative_matter::particles::*;
+use generative_matter::population::*;
+use generative_matter::species::*;
+use generative_matter::uniforms::*;
+use nannou::prelude::*;
+
+/// One configuration to step on both backends.
+struct Run {
+    name: &'static str,
+    uniforms: Uniforms,
+    species: SpeciesConfig,
+    population: PopulationConfig,
+    environment: EnvironmentConfig,
+    options: UpdateOptions,
+}
+
+impl Run {
+    fn new(name: &'static str, uniforms: Uniforms) -> Self {
+        Run {
+            name,
+            uniforms,
+            species: SpeciesConfig::default(),
+            population: PopulationConfig::new(PARTICLE_COUNT),
+            environment: EnvironmentConfig::new(),
+            options: UpdateOptions::default(),
+        }
+    }
+
+    fn with_species(mut self, species: SpeciesConfig) -> Self {
+        self.uniforms.species_count = species.count;
+        self.species = species;
+        self
+    }
+
+    fn with_options(mut self, options: UpdateOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    fn with_population(mut self, population: PopulationConfig) -> Self {
+        self.uniforms.emitter_count = population.emitters.len() as u32;
+        self.uniforms.sink_count = population.sinks.len() as u32;
+        self.population = population;
+        self
+    }
+
+    fn with_environment(mut self, environment: EnvironmentConfig) -> Self {
+        self.uniforms.obstacle_count = environment.obstacles.len() as u32;
+        self.uniforms.field_count = environment.fields.len() as u32;
+        self.environment = environment;
+        self
+    }
+}
+
+/// Every configuration stepped on both backends: the main branches of the update shader,
+/// its compile options on the unlimited range loop, and emitters, sinks, obstacles and fields
+/// between reflective walls.
+fn runs() -> Vec<Run> {
+    let base = Uniforms::new(PARTICLE_COUNT, 1920.0, 1080.0);
+    let unlimited = Uniforms {
+        attraction_range: 0.0,
+        ..base
+    };
+    let reflect = Uniforms {
+        boundary_mode: BoundaryMode::Reflect as u32,
+        width: 200.0,
+        height: 160.0,
+        ..base
+    };
+    let initial_count = PARTICLE_COUNT * 3 / 4;
+
+    vec![
+        Run::new("default", base),
+        Run::new("unlimited range", unlimited),
+        Run::new(
+            "barnes hut",
+            Uniforms {
+                attraction_range: 0.0,
+                barnes_hut_theta: 0.7,
+                charge_strength: 1.0,
+                width: 320.0,
+                height: 320.0,
+                ..base
+            },
+        )
+        .with_species(seeded_species(3)),
+        Run::new(
+            "clamped acceleration",
+            Uniforms {
+                max_acceleration: 0.05,
+                center_strength: 0.001,
+                ..base
+            },
+        ),
+        Run::new(
+            "dense collisions",
+            Uniforms {
+                particle_radius: 6.0,
+                collision_response: 0.02,
+                ..base
+            },
+        ),
+        Run::new(
+            "charges",
+            Uniforms {
+                charge_strength: 3.0,
+                ..base
+            },
+        ),
+        Run::new(
+            "mouse repel",
+            Uniforms {
+                mouse_mode: MouseMode::Repel as u32,
+                mouse_x: 20.0,
+                mouse_y: -10.0,
+                mouse_radius: 80.0,
+                ..base
+            },
+        ),
+        Run::new(
+            "mouse grab",
+            Uniforms {
+                mouse_mode: MouseMode::Grab as u32,
+                mouse_x: -30.0,
+                mouse_y: 15.0,
+                mouse_velocity_x: 1.5,
+                mouse_velocity_y: -0.5,
+                mouse_radius: 60.0,
+                ..base
+            },
+        ),
+        Run::new("species", Uniforms { ..base }).with_species(seeded_species(3)),
+        Run::new(
+            "velocity verlet",
+            Uniforms {
+                integrator: Integrator::VelocityVerlet as u32,
+                ..base
+            },
+        ),
+        Run::new(
+            "rk2 substeps",
+            Uniforms {
+                integrator: Integrator::Rk2 as u32,
+                substeps: 4,
+                dt: 0.5,
+                ..base
+            },
+        ),
+        Run::new(
+            "wrap",
+            Uniforms {
+                boundary_mode: BoundaryMode::Wrap as u32,
+                width: 200.0,
+                height: 160.0,
+                ..base
+            },
+        ),
+        Run::new("reflect", reflect),
+        Run::new(
+            "respawn",
+            Uniforms {
+                boundary_mode: BoundaryMode::Respawn as u32,
+                seed: 12345,
+                integrator: Integrator::VelocityVerlet as u32,
+                width: 200.0,
+                height: 160.0,
+                ..base
+            },
+        ),
+        Run::new(
+            "bonds",
+            Uniforms {
+                bond_capture_distance: 8.0,
+                bond_time: 2.0,
+                bond_break_strain: 0.1,
+                ..base
+            },
+        ),
+        Run::new(
+            "langevin",
+            Uniforms {
+                thermostat: Thermostat::Langevin as u32,
+                target_temperature: 0.05,
+                langevin_friction: 0.1,
+                seed: 54321,
+                ..base
+            },
+        ),
+        Run::new(
+            "berendsen",
+            Uniforms {
+                thermostat: Thermostat::Berendsen as u32,
+                velocity_scale: 0.9,
+                substeps: 2,
+                ..base
+            },
+        ),
+        Run::new(
+            "smoothed inverse square",
+            Uniforms {
+                kernel_smoothing: 0.3,
+                ..base
+            },
+        ),
+        Run::new(
+            "gaussian",
+            Uniforms {
+                force_kernel: ForceKernel::Gaussian as u32,
+                attraction_strength: 0.02,
+                repulsion_strength: 0.01,
+                kernel_smoothing: 0.2,
+                ..base
+            },
+        )
+        .with_species(seeded_species(3)),
+        Run::new(
+            "lennard-jones",
+            Uniforms {
+                force_kernel: ForceKernel::LennardJones as u32,
+                kernel_smoothing: 0.2,
+                ..base
+            },
+        )
+        .with_species(seeded_species(3)),
+        Run::new(
+            "linear ramp",
+            Uniforms {
+                force_kernel: ForceKernel::LinearRamp as u32,
+                attraction_strength: 0.02,
+                repulsion_strength: 0.05,
+                ..base
+            },
+        )
+        .with_species(seeded_species(3)),
+        Run::new(
+            "morse",
+            Uniforms {
+                force_kernel: ForceKernel::Morse as u32,
+                kernel_smoothing: 0.2,
+                ..base
+            },
+        )
+        .with_species(seeded_species(3)),
+        Run::new("unlimited range, single invocation workgroups", unlimited).with_options(
+            UpdateOptions {
+                workgroup_size: 1,
+                tiled_forces: false,
+            },
+        ),
+        Run::new("unlimited range, partial workgroups", unlimited).with_options(UpdateOptions {
+            workgroup_size: 96,
+            tiled_forces: false,
+        }),
+        Run::new("unlimited range, tiled forces", unlimited).with_options(UpdateOptions {
+            workgroup_size: 96,
+            tiled_forces: true,
+        }),
+        Run::new("reflect, sinks", reflect).with_population(
+            PopulationConfig::new(initial_count).with_sinks(vec![
+                Sink::new(pt2(-60.0, 40.0), 30.0),
+                Sink::new(pt2(80.0, -60.0), 40.0),
+            ]),
+        ),
+        Run::new("reflect, emitters and sinks", reflect).with_population(
+            PopulationConfig::new(initial_count)
+                .with_emitters(vec![
+                    Emitter {
+                        center: pt2(-80.0, 0.0),
+                        radius: 10.0,
+                        rate: 12.0,
+                        velocity: pt2(2.0, 0.0),
+                        velocity_spread: 0.5,
+                        species: 0,
+                        attributes: ParticleAttributes::new(2.0, 0.5, 1.0),
+                    },
+                    Emitter {
+                        center: pt2(0.0, 60.0),
+                        radius: 20.0,
+                        rate: 4.0,
+                        velocity: pt2(0.0, -1.0),
+                        ..Emitter::default()
+                    },
+                ])
+                .with_sinks(vec![Sink::new(pt2(80.0, 0.0), 30.0)]),
+        ),
+        Run::new("reflect, circle and box", reflect).with_environment(
+            EnvironmentConfig::new().with_obstacles(vec![
+                Obstacle::circle(pt2(-40.0, 20.0), 25.0),
+                Obstacle::rectangle(pt2(50.0, -30.0), pt2(20.0, 35.0))
+                    .with_radius(4.0)
+                    .with_friction(0.3),
+            ]),
+        ),
+        Run::new("reflect, capsule and segment", reflect).with_environment(
+            EnvironmentConfig::new().with_obstacles(vec![
+                Obstacle::capsule(pt2(-80.0, -50.0), pt2(-10.0, 10.0), 6.0).with_restitution(0.9),
+                Obstacle::segment(pt2(20.0, 60.0), pt2(90.0, 10.0)).with_friction(0.0),
+            ]),
+        ),
+        Run::new("reflect, force fields", reflect).with_environment(
+            EnvironmentConfig::new().with_fields(vec![
+                ForceField::curl_noise(0.05, 40.0, 0.1),
+                ForceField::vortex(pt2(30.0, 20.0), 0.03).with_falloff(50.0),
+                ForceField::gravity(vec2(0.3, -1.0), 0.01),
+                ForceField::radial(pt2(-50.0, -20.0), -0.04).with_falloff(30.0),
+            ]),
+        ),
+    ]
+}
+
+#[test]
+fn gpu_matches_cpu_reference() {
+    let Some(device_queue_pair) = software_device() else {
+        return;
+    };
+    let device = device_queue_pair.device();
+
+    for run in runs() {
+        let Run {
+            name,
+            uniforms,
+            species,
+            population,
+            environment,
+            options,
+        } = run;
+        let state = seeded_state(species.count, population.initial_count);
+
+        let mut uniform_buffer = UniformBuffer::new(device, PARTICLE_COUNT, 1920.0, 1080.0);
+        uniform_buffer.data = uniforms;
+        let particle_system = ParticleSystem::from_state(
+            &shaders_dir(),
+            device,
+            &uniform_buffer,
+            &state,
+            &species,
+            &population,
+            &environment,
+            options,
+        );
+
+        let mut gpu = GpuBackend::new(device_queue_pair.clone(), uniform_buffer, particle_system);
+        let mut cpu = CpuBackend::new(state, species, population, environment);
+
+        for step in 1..=STEPS {
+            let uniforms = Uniforms {
+                frame: step as u32,
+                time: step as f32 * uniforms.dt,
+                ..uniforms
+            };
+            gpu.step(&uniforms);
+            cpu.step(&uniforms);
+
+            let gpu_state = gpu.state();
+            let cpu_state = cpu.state();
+            assert_eq!(
+                gpu_state.alive, cpu_state.alive,
+                "{}: alive particles diverged after step {}",
+                name, step
+            );
+            let partners =
+                |bonds: &[Bond]| bonds.iter().map(|bond| bond.partner).collect::<Vec<_>>();
+            assert_eq!(
+                partners(&gpu_state.bonds),
+                partners(&cpu_state.bonds),
+                "{}: bonds diverged after step {}",
+                name,
+                step
+            );
+            assert_close(
+                name,
+                step,
+                "position",
+                &gpu_state.positions,
+                &cpu_state.positions,
+            );
+            assert_close(
+                name,
+                step,
+                "velocity",
+                &gpu_state.velocities,
+                &cpu_state.velocities,
+            );
+        }
+    }
+}